@@ -0,0 +1,283 @@
+//! Compact cell storage - packed colours and per-buffer interned styles
+
+use crate::color::ColorDepth;
+use crate::render::{Attrs, Cell};
+use crossterm::style::Color;
+use std::collections::HashMap;
+
+/// Largest number of distinct styles a buffer can hold at once
+const MAX_STYLES: usize = u16::MAX as usize + 1;
+
+/// Styles kept free for styles fitted to 16 colours, used once the rest is full
+const RESERVED_STYLES: usize = 1024;
+
+/// Styles added (or refused) since the last compaction before a full table is
+/// compacted again - a pass walks every cell of the buffer
+const COMPACT_AFTER: usize = 4096;
+
+/// An `Option<Color>` packed into 32 bits: a tag in the top byte, the RGB
+/// value or colour index below it
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PackedColor(u32);
+
+impl PackedColor {
+    const NONE: u32 = 0;
+    const RGB: u32 = 1 << 24;
+    const INDEXED: u32 = 2 << 24;
+    const NAMED: u32 = 3 << 24;
+
+    /// crossterm's named colours, packed by their position here
+    const NAMED_COLORS: [Color; 17] = [
+        Color::Reset,
+        Color::Black,
+        Color::DarkGrey,
+        Color::Red,
+        Color::DarkRed,
+        Color::Green,
+        Color::DarkGreen,
+        Color::Yellow,
+        Color::DarkYellow,
+        Color::Blue,
+        Color::DarkBlue,
+        Color::Magenta,
+        Color::DarkMagenta,
+        Color::Cyan,
+        Color::DarkCyan,
+        Color::White,
+        Color::Grey,
+    ];
+
+    pub fn pack(color: Option<Color>) -> Self {
+        Self(match color {
+            None => Self::NONE,
+            Some(Color::Rgb { r, g, b }) => Self::RGB | (r as u32) << 16 | (g as u32) << 8 | b as u32,
+            Some(Color::AnsiValue(n)) => Self::INDEXED | n as u32,
+            Some(named) => {
+                let index = Self::NAMED_COLORS.iter().position(|&c| c == named).unwrap_or(0);
+                Self::NAMED | index as u32
+            }
+        })
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub fn unpack(self) -> Option<Color> {
+        let value = self.0 & 0xff_ffff;
+        match self.0 & 0xff00_0000 {
+            Self::RGB => Some(Color::Rgb {
+                r: (value >> 16) as u8,
+                g: (value >> 8) as u8,
+                b: value as u8,
+            }),
+            Self::INDEXED => Some(Color::AnsiValue(value as u8)),
+            Self::NAMED => Self::NAMED_COLORS.get(value as usize).copied(),
+            _ => None,
+        }
+    }
+}
+
+/// Colours and attributes shared by many cells
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+struct Style {
+    fg: PackedColor,
+    bg: PackedColor,
+    attrs: Attrs,
+}
+
+/// A cell as stored in a buffer: the character and its interned style
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct StoredCell {
+    pub ch: char,
+    pub style: u16,
+}
+
+impl StoredCell {
+    /// A space in the default style (what trailing blanks of a line are)
+    pub const BLANK: StoredCell = StoredCell { ch: ' ', style: 0 };
+}
+
+impl Default for StoredCell {
+    fn default() -> Self {
+        Self::BLANK
+    }
+}
+
+/// Interned styles of one buffer; id 0 is always the default style
+pub struct StyleTable {
+    styles: Vec<Style>,
+    ids: HashMap<Style, u16>,
+    // Last style looked up - runs of cells usually share one
+    last: (Style, u16),
+    // Styles added or refused since the last compaction
+    added: usize,
+}
+
+impl StyleTable {
+    pub fn new() -> Self {
+        let mut ids = HashMap::new();
+        ids.insert(Style::default(), 0);
+        Self {
+            styles: vec![Style::default()],
+            ids,
+            last: (Style::default(), 0),
+            added: 0,
+        }
+    }
+
+    /// Id of a style, adding it if needed; None once the table is full
+    pub fn intern(&mut self, fg: Option<Color>, bg: Option<Color>, attrs: Attrs) -> Option<u16> {
+        let style = Style {
+            fg: PackedColor::pack(fg),
+            bg: PackedColor::pack(bg),
+            attrs,
+        };
+        let len = self.styles.len();
+        let id = self.intern_style(style, MAX_STYLES - RESERVED_STYLES);
+        if id.is_none() || self.styles.len() > len {
+            self.added += 1;
+        }
+        id
+    }
+
+    /// Id of a style with its colours fitted to the 16 ANSI colours, for when
+    /// `intern` finds the table full. Falls back to the default style (id 0)
+    /// only once the reserved styles are used up as well
+    pub fn intern_fitted(&mut self, fg: Option<Color>, bg: Option<Color>, attrs: Attrs) -> u16 {
+        let fit = |color: Option<Color>| PackedColor::pack(color.map(|c| ColorDepth::Ansi16.quantize(c)));
+        let style = Style { fg: fit(fg), bg: fit(bg), attrs };
+        self.intern_style(style, MAX_STYLES).unwrap_or(0)
+    }
+
+    /// Whether a full table is worth compacting: enough styles came and went
+    /// since the last pass that some are likely unused
+    pub fn compact_due(&self) -> bool {
+        self.added >= COMPACT_AFTER
+    }
+
+    fn intern_style(&mut self, style: Style, limit: usize) -> Option<u16> {
+        if self.last.0 == style {
+            return Some(self.last.1);
+        }
+        let id = match self.ids.get(&style) {
+            Some(&id) => id,
+            None if self.styles.len() < limit => {
+                let id = self.styles.len() as u16;
+                self.styles.push(style);
+                self.ids.insert(style, id);
+                id
+            }
+            None => return None,
+        };
+        self.last = (style, id);
+        Some(id)
+    }
+
+    /// The full cell for a stored one
+    pub fn resolve(&self, cell: StoredCell) -> Cell {
+        let style = self.styles.get(cell.style as usize).copied().unwrap_or_default();
+        Cell {
+            ch: cell.ch,
+            fg: style.fg.unpack(),
+            bg: style.bg.unpack(),
+            attrs: style.attrs,
+        }
+    }
+
+    /// Drop styles no cell uses any more. `cells` must hold every stored cell of
+    /// the buffer, whose style ids are rewritten to match the compacted table
+    pub fn compact(&mut self, cells: &mut [&mut [StoredCell]]) {
+        let mut used = vec![false; self.styles.len()];
+        used[0] = true;
+        for cell in cells.iter().flat_map(|c| c.iter()) {
+            used[cell.style as usize] = true;
+        }
+        let mut remap = vec![0u16; self.styles.len()];
+        let old = std::mem::take(&mut self.styles);
+        self.ids.clear();
+        for (id, style) in old.into_iter().enumerate() {
+            if used[id] {
+                remap[id] = self.styles.len() as u16;
+                self.ids.insert(style, remap[id]);
+                self.styles.push(style);
+            }
+        }
+        for cell in cells.iter_mut().flat_map(|c| c.iter_mut()) {
+            cell.style = remap[cell.style as usize];
+        }
+        self.last = (Style::default(), 0);
+        self.added = 0;
+    }
+}
+
+/// Memory used by a scrollback line of `cells` stored cells
+pub fn line_bytes(cells: usize) -> usize {
+    cells * std::mem::size_of::<StoredCell>() + std::mem::size_of::<Box<[StoredCell]>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packed_color_round_trip() {
+        let colors = [
+            None,
+            Some(Color::Reset),
+            Some(Color::DarkCyan),
+            Some(Color::Grey),
+            Some(Color::AnsiValue(200)),
+            Some(Color::Rgb { r: 1, g: 2, b: 3 }),
+        ];
+        for color in colors {
+            assert_eq!(PackedColor::pack(color).unpack(), color);
+        }
+        assert_eq!(std::mem::size_of::<StoredCell>(), 8);
+    }
+
+    #[test]
+    fn test_style_table_compact() {
+        let mut table = StyleTable::new();
+        let red = table.intern(Some(Color::Red), None, Attrs::default()).unwrap();
+        let blue = table.intern(Some(Color::Blue), None, Attrs::default()).unwrap();
+        assert_eq!(table.intern(Some(Color::Red), None, Attrs::default()), Some(red));
+        assert_eq!((red, blue), (1, 2));
+
+        // Only the blue cell survives, so red is dropped and blue renumbered
+        let mut cells = vec![StoredCell { ch: 'b', style: blue }, StoredCell::BLANK];
+        table.compact(&mut [cells.as_mut_slice()]);
+        assert_eq!(cells[0].style, 1);
+        assert_eq!(table.intern(Some(Color::Red), None, Attrs::default()), Some(2));
+        assert_eq!(table.resolve(cells[0]).fg, Some(Color::Blue));
+        assert_eq!(table.resolve(cells[1]).fg, None);
+    }
+
+    #[test]
+    fn test_style_table_full() {
+        let mut table = StyleTable::new();
+        let rgb = |n: usize| Some(Color::Rgb { r: (n >> 16) as u8, g: (n >> 8) as u8, b: n as u8 });
+        let mut cells = Vec::new();
+        for n in 1..MAX_STYLES - RESERVED_STYLES {
+            let style = table.intern(rgb(n), None, Attrs::default()).unwrap();
+            cells.push(StoredCell { ch: 'x', style });
+        }
+        assert_eq!(table.intern(rgb(MAX_STYLES), None, Attrs::default()), None);
+        assert!(table.compact_due());
+
+        // Every style is still in use, so compacting frees nothing
+        table.compact(&mut [cells.as_mut_slice()]);
+        assert_eq!(table.intern(rgb(MAX_STYLES), None, Attrs::default()), None);
+        assert!(!table.compact_due());
+
+        // The colour is fitted to 16 colours instead of being lost
+        let red = Some(Color::Rgb { r: 250, g: 10, b: 10 });
+        let id = table.intern_fitted(red, None, Attrs::default());
+        assert_eq!(table.resolve(StoredCell { ch: 'x', style: id }).fg, Some(Color::AnsiValue(9)));
+        assert_eq!(table.intern_fitted(red, None, Attrs::default()), id);
+    }
+}
@@ -0,0 +1,264 @@
+//! Colour depth of the outer terminal, and fitting colours into it
+
+use crate::config;
+use crate::render::Cell;
+use crate::terminfo;
+use anyhow::{bail, Result};
+use crossterm::style::{Color, SetBackgroundColor, SetForegroundColor};
+use crossterm::Command;
+use std::fmt;
+
+/// Colours the outer terminal can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16, // Also sent as the classic SGR 30-37/90-97 forms
+}
+
+impl ColorDepth {
+    /// Parse a --colors value: 16, 256 or truecolor (24bit)
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "16" => Ok(ColorDepth::Ansi16),
+            "256" => Ok(ColorDepth::Ansi256),
+            "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+            _ => bail!("Invalid colour depth '{}' (expected 16, 256 or truecolor)", value),
+        }
+    }
+
+    /// The depth from config::COLOR_DEPTH, else COLORTERM, else the terminfo
+    /// entry for TERM; truecolor if none of them tell
+    pub fn detect() -> Self {
+        if let Some(depth) = config::COLOR_DEPTH {
+            return depth;
+        }
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorDepth::TrueColor;
+        }
+        let Some(support) = std::env::var("TERM").ok().and_then(|term| terminfo::color_support(&term)) else {
+            return ColorDepth::TrueColor;
+        };
+        if support.direct {
+            ColorDepth::TrueColor
+        } else if support.colors >= 256 {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    /// The closest colour this depth can show
+    pub fn quantize(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::TrueColor, _) | (_, Color::Reset) => color,
+            (ColorDepth::Ansi256, Color::Rgb { r, g, b }) => Color::AnsiValue(nearest_256((r, g, b))),
+            (ColorDepth::Ansi256, _) => color,
+            (ColorDepth::Ansi16, _) => match palette_index(color) {
+                Some(index) if index < 16 => Color::AnsiValue(index),
+                _ => rgb(color).map_or(color, |value| Color::AnsiValue(nearest_16(value))),
+            },
+        }
+    }
+}
+
+/// SetForegroundColor, with the colour fitted to a depth
+pub struct SetFg(pub Color, pub ColorDepth);
+
+/// SetBackgroundColor, with the colour fitted to a depth
+pub struct SetBg(pub Color, pub ColorDepth);
+
+impl Command for SetFg {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        match self.1.quantize(self.0) {
+            color if self.1 == ColorDepth::Ansi16 => write_sgr16(f, color, 30, 90),
+            color => SetForegroundColor(color).write_ansi(f),
+        }
+    }
+}
+
+impl Command for SetBg {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        match self.1.quantize(self.0) {
+            color if self.1 == ColorDepth::Ansi16 => write_sgr16(f, color, 40, 100),
+            color => SetBackgroundColor(color).write_ansi(f),
+        }
+    }
+}
+
+/// A quantized 16-colour value as SGR `base` + 0-7 or `bright` + 0-7 (base + 9 resets)
+fn write_sgr16(f: &mut impl fmt::Write, color: Color, base: u8, bright: u8) -> fmt::Result {
+    match color {
+        Color::AnsiValue(n @ 0..=7) => write!(f, "\x1b[{}m", base + n),
+        Color::AnsiValue(n) => write!(f, "\x1b[{}m", bright + (n & 7)),
+        _ => write!(f, "\x1b[{}m", base + 9),
+    }
+}
+
+/// Dimming of inactive windows by blending their colours toward another
+pub struct DimBlend {
+    /// Colour to blend toward, usually the terminal's background
+    pub color: (u8, u8, u8),
+    /// How far to blend, 0-100 percent
+    pub amount: u8,
+    /// What text in the default colour looks like (the outer terminal's foreground)
+    pub default_fg: (u8, u8, u8),
+}
+
+impl DimBlend {
+    /// Blend a cell's colours. Indexed colours go through the palette first;
+    /// the default background is left alone, as it already is the backdrop
+    pub fn apply(&self, cell: &mut Cell) {
+        let fg = cell.fg.and_then(rgb).unwrap_or(self.default_fg);
+        cell.fg = Some(self.blend(fg));
+        cell.bg = cell.bg.and_then(rgb).map(|bg| self.blend(bg));
+    }
+
+    fn blend(&self, (r, g, b): (u8, u8, u8)) -> Color {
+        let amount = self.amount.min(100) as u32;
+        let mix = |from: u8, to: u8| ((from as u32 * (100 - amount) + to as u32 * amount + 50) / 100) as u8;
+        let (tr, tg, tb) = self.color;
+        Color::Rgb { r: mix(r, tr), g: mix(g, tg), b: mix(b, tb) }
+    }
+}
+
+/// Palette index of an indexed or named colour
+fn palette_index(color: Color) -> Option<u8> {
+    Some(match color {
+        Color::AnsiValue(n) => n,
+        Color::Black => 0,
+        Color::DarkRed => 1,
+        Color::DarkGreen => 2,
+        Color::DarkYellow => 3,
+        Color::DarkBlue => 4,
+        Color::DarkMagenta => 5,
+        Color::DarkCyan => 6,
+        Color::Grey => 7,
+        Color::DarkGrey => 8,
+        Color::Red => 9,
+        Color::Green => 10,
+        Color::Yellow => 11,
+        Color::Blue => 12,
+        Color::Magenta => 13,
+        Color::Cyan => 14,
+        Color::White => 15,
+        Color::Rgb { .. } | Color::Reset => return None,
+    })
+}
+
+/// RGB value of a colour as xterm shows it by default (None for Reset)
+pub fn rgb(color: Color) -> Option<(u8, u8, u8)> {
+    let index = match color {
+        Color::Rgb { r, g, b } => return Some((r, g, b)),
+        _ => palette_index(color)?,
+    };
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00), (0xcd, 0x00, 0x00), (0x00, 0xcd, 0x00), (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee), (0xcd, 0x00, 0xcd), (0x00, 0xcd, 0xcd), (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f), (0xff, 0x00, 0x00), (0x00, 0xff, 0x00), (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff), (0xff, 0x00, 0xff), (0x00, 0xff, 0xff), (0xff, 0xff, 0xff),
+    ];
+    Some(match index {
+        0..=15 => BASIC[index as usize],
+        16..=231 => {
+            // 6x6x6 colour cube
+            let n = index - 16;
+            (cube_level(n / 36), cube_level(n / 6 % 6), cube_level(n % 6))
+        }
+        _ => {
+            // Grayscale ramp
+            let level = 8 + (index - 232) * 10;
+            (level, level, level)
+        }
+    })
+}
+
+fn cube_level(n: u8) -> u8 {
+    if n == 0 { 0 } else { 55 + n * 40 }
+}
+
+/// Closest entry of the 256-colour palette: the nearest cube colour or gray
+fn nearest_256((r, g, b): (u8, u8, u8)) -> u8 {
+    // Cube coordinate whose level is closest to a component
+    let axis = |v: u8| (0..6u8).min_by_key(|&n| (cube_level(n) as i32 - v as i32).abs()).unwrap_or(0);
+    let cube = 16 + 36 * axis(r) + 6 * axis(g) + axis(b);
+    let average = (r as i32 + g as i32 + b as i32) / 3;
+    let gray = 232 + ((average - 3) / 10).clamp(0, 23) as u8;
+    let distance = |index: u8| {
+        let (pr, pg, pb) = rgb(Color::AnsiValue(index)).unwrap_or_default();
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(pr, r) + d(pg, g) + d(pb, b)
+    };
+    if distance(gray) < distance(cube) { gray } else { cube }
+}
+
+/// Closest of the 16 basic colours. Distance alone turns muted colours gray,
+/// so the hue is kept: each channel well above the others lights its bit
+/// (red 1, green 2, blue 4 as in the ANSI order), bright when near full
+fn nearest_16((r, g, b): (u8, u8, u8)) -> u8 {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    if max - min < 40 {
+        let average = (r as u32 + g as u32 + b as u32) / 3;
+        return match average {
+            0..=0x2f => 0,
+            0x30..=0x8f => 8,
+            0x90..=0xcf => 7,
+            _ => 15,
+        };
+    }
+    let middle = ((max as u32 + min as u32) / 2) as u8;
+    let hue = [(r, 1), (g, 2), (b, 4)].iter().filter(|(v, _)| *v > middle).fold(0, |acc, (_, bit)| acc | bit);
+    if max >= 0xe0 { hue + 8 } else { hue }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ansi(command: impl Command) -> String {
+        let mut out = String::new();
+        command.write_ansi(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_quantize() {
+        let orange = Color::Rgb { r: 255, g: 135, b: 0 };
+        assert_eq!(ColorDepth::TrueColor.quantize(orange), orange);
+        assert_eq!(ColorDepth::Ansi256.quantize(orange), Color::AnsiValue(208));
+        assert_eq!(ColorDepth::Ansi256.quantize(Color::Rgb { r: 0x30, g: 0x30, b: 0x30 }), Color::AnsiValue(236));
+        assert_eq!(ColorDepth::Ansi16.quantize(Color::Rgb { r: 200, g: 80, b: 80 }), Color::AnsiValue(1));
+        assert_eq!(ColorDepth::Ansi16.quantize(Color::AnsiValue(196)), Color::AnsiValue(9));
+        assert_eq!(ColorDepth::Ansi16.quantize(Color::Yellow), Color::AnsiValue(11));
+        assert_eq!(ColorDepth::Ansi16.quantize(Color::Grey), Color::AnsiValue(7));
+        assert_eq!(ColorDepth::Ansi16.quantize(orange), Color::AnsiValue(11));
+        assert_eq!(ColorDepth::Ansi16.quantize(Color::Rgb { r: 40, g: 40, b: 44 }), Color::AnsiValue(0));
+        assert_eq!(ColorDepth::Ansi16.quantize(Color::Reset), Color::Reset);
+    }
+
+    #[test]
+    fn test_dim_blend() {
+        let dim = DimBlend { color: (0, 0, 0), amount: 50, default_fg: (200, 200, 200) };
+        let mut cell = Cell { ch: 'x', fg: Some(Color::Rgb { r: 255, g: 100, b: 0 }), bg: None, attrs: Default::default() };
+        dim.apply(&mut cell);
+        assert_eq!(cell.fg, Some(Color::Rgb { r: 128, g: 50, b: 0 }));
+        assert_eq!(cell.bg, None);
+
+        // Default foreground and indexed colours are resolved first
+        let mut cell = Cell { ch: 'x', fg: None, bg: Some(Color::AnsiValue(15)), attrs: Default::default() };
+        dim.apply(&mut cell);
+        assert_eq!(cell.fg, Some(Color::Rgb { r: 100, g: 100, b: 100 }));
+        assert_eq!(cell.bg, Some(Color::Rgb { r: 128, g: 128, b: 128 }));
+    }
+
+    #[test]
+    fn test_sgr16_commands() {
+        assert_eq!(ansi(SetFg(Color::DarkGreen, ColorDepth::Ansi16)), "\x1b[32m");
+        assert_eq!(ansi(SetBg(Color::Rgb { r: 250, g: 250, b: 250 }, ColorDepth::Ansi16)), "\x1b[107m");
+        assert_eq!(ansi(SetFg(Color::Reset, ColorDepth::Ansi16)), "\x1b[39m");
+        assert_eq!(ansi(SetFg(Color::Rgb { r: 1, g: 2, b: 3 }, ColorDepth::TrueColor)), "\x1b[38;2;1;2;3m");
+        assert!(ColorDepth::parse("88").is_err());
+    }
+}
@@ -2,6 +2,10 @@
 //!
 //! Edit this file to customize keybindings and settings.
 
+use crate::color::{ColorDepth, DimBlend};
+use crate::highlight::HighlightRule;
+use crate::image::ImageMode;
+use crate::trigger::{TriggerAction, TriggerRule};
 use crossterm::event::{KeyCode, KeyModifiers};
 
 // ============================================================================
@@ -17,6 +21,112 @@ pub const MASTER_ADJUST_STEP: f32 = 0.05;
 /// Maximum lines stored in scrollback buffer per pane
 pub const SCROLLBACK_LINES: usize = 10_000;
 
+/// Memory the scrollback of all panes may use together, in megabytes. Past it,
+/// the least recently active panes lose their oldest lines first
+pub const SCROLLBACK_MEMORY_MB: usize = 256;
+
+/// Keep lines that fall out of the scrollback in a compressed file per pane in
+/// $XDG_RUNTIME_DIR (deleted when the pane closes) instead of dropping them,
+/// for unlimited history
+pub const SCROLLBACK_SPILL: bool = false;
+
+/// Longest time a pane may hold a synchronized update (mode 2026) before
+/// truetm shows its contents anyway, in milliseconds
+pub const SYNC_UPDATE_TIMEOUT_MS: u64 = 150;
+
+/// Pass bells from panes on to the outer terminal (so it can beep or set an urgency hint)
+pub const BELL_FORWARD: bool = true;
+
+/// Least time between two bells passed on to the outer terminal, in milliseconds
+pub const BELL_FORWARD_INTERVAL_MS: u64 = 500;
+
+/// How long a window header flashes after its pane rings the bell, in milliseconds
+pub const BELL_FLASH_MS: u64 = 200;
+
+/// Mark tags whose hidden panes print output (new panes start with this setting)
+pub const MONITOR_ACTIVITY: bool = true;
+
+/// Seconds without output before a silence-monitored pane raises an alert
+pub const MONITOR_SILENCE_SECS: u64 = 30;
+
+/// How long status-bar messages stay visible, in milliseconds
+pub const STATUS_MESSAGE_MS: u64 = 4000;
+
+/// Pass notifications from panes (OSC 9 / OSC 777) on to the outer terminal
+pub const NOTIFY_FORWARD: bool = false;
+
+/// Number of notifications kept in the history overlay
+pub const NOTIFICATION_HISTORY: usize = 100;
+
+/// TERM for windows. The truetm-256color entry is compiled into ~/.terminfo on
+/// first run if missing; TERM_FALLBACK is used when it can't be found or compiled
+pub const TERM: &str = "truetm-256color";
+pub const TERM_FALLBACK: &str = "xterm-256color";
+
+/// Colours of the outer terminal: None detects them from COLORTERM and the
+/// terminfo entry for TERM, Some(ColorDepth::Ansi256) or Some(ColorDepth::Ansi16)
+/// fit all colours into that palette (also set with --colors 16|256|truecolor)
+pub const COLOR_DEPTH: Option<ColorDepth> = None;
+
+/// Theme for headers, the status bar, selections and search matches: "dark",
+/// "light", "high-contrast" or the path of a theme file (see `truetm theme`)
+pub const THEME: &str = "dark";
+
+/// Dim inactive windows by blending their colours toward a colour instead of
+/// with SGR dim, which many terminals ignore and which hides the windows' own
+/// dim text. For example, toward a dark background by 40%:
+/// Some(DimBlend { color: (0x1c, 0x1c, 0x1c), amount: 40, default_fg: (0xd0, 0xd0, 0xd0) })
+pub const DIM_BLEND: Option<DimBlend> = None;
+
+/// Outer terminal title ({tag} = viewed tags, {pane_title} = focused window's title).
+/// Empty keeps the plain "truetm" title
+pub const OUTER_TITLE: &str = "truetm: {tag} {pane_title}";
+
+// ============================================================================
+// OUTPUT TRIGGERS
+// ============================================================================
+
+/// Regex rules matched against every completed output line of every pane, e.g.
+/// TriggerRule { pattern: "error|FAILED", actions: &[TriggerAction::Urgent, TriggerAction::Message] }
+/// TriggerRule { pattern: "deploy finished", actions: &[TriggerAction::Command("notify-send \"$TRUETM_MATCH\"")] }
+pub const TRIGGERS: &[TriggerRule] = &[];
+
+/// Shortest time between two runs of one trigger's command, in milliseconds
+/// (matches in between run its other actions only)
+pub const TRIGGER_COMMAND_INTERVAL_MS: u64 = 1000;
+
+/// What a watch added at runtime (Prefix + w) does when it matches
+pub const WATCH_ACTIONS: &[TriggerAction] = &[TriggerAction::Urgent, TriggerAction::Message];
+
+// ============================================================================
+// HIGHLIGHTS
+// ============================================================================
+
+/// Regex rules restyling matched text in every pane, e.g.
+/// HighlightRule { pattern: "ERROR", style: "bold,bg=red" }
+/// HighlightRule { pattern: "WARN(ING)?", style: "fg=yellow" }
+/// Styles combine bold, dim, italic, underline, reverse, strikethrough, fg= and
+/// bg= (colour names, brightred etc., 0-255 or #rrggbb). More are added at
+/// runtime with `truetm highlight`
+pub const HIGHLIGHTS: &[HighlightRule] = &[];
+
+/// How sixel and kitty graphics images from applications are shown:
+/// Passthrough re-draws them on the outer terminal (which must support the
+/// protocol), Placeholder shows a labelled box instead
+pub const IMAGE_MODE: ImageMode = ImageMode::Passthrough;
+
+/// Cell size in pixels (width, height) assumed when the outer terminal doesn't report it
+pub const DEFAULT_CELL_PIXELS: (u16, u16) = (10, 20);
+
+/// Size of a newly floated window, in percent of the screen (width, height)
+pub const FLOAT_SIZE: (u16, u16) = (60, 60);
+
+/// Command the scratchpad runs (with $SHELL -c) when first shown, e.g.
+/// "nvim ~/notes.md"; empty runs the shell. It starts in $HOME, at
+/// SCRATCHPAD_SIZE percent of the screen
+pub const SCRATCHPAD_COMMAND: &str = "";
+pub const SCRATCHPAD_SIZE: (u16, u16) = (80, 70);
+
 // ============================================================================
 // PREFIX KEY
 // ============================================================================
@@ -55,6 +165,17 @@ pub const KEY_TOGGLE_TAG: KeyCode = KeyCode::Char('T');
 pub const KEY_TOGGLE_BROADCAST: KeyCode = KeyCode::Char('a');
 pub const KEY_ENTER_COPY: KeyCode = KeyCode::Char('s');
 pub const KEY_ZOOM: KeyCode = KeyCode::Char('z');
+pub const KEY_TOGGLE_FLOAT: KeyCode = KeyCode::Char('f'); // Float the focused window above the tiling, or tile it again
+pub const KEY_ARRANGE_FLOAT: KeyCode = KeyCode::Char('F'); // Move (hjkl) and resize (HJKL) the focused floating window
+pub const KEY_SCRATCHPAD: KeyCode = KeyCode::Char('p'); // Show or hide the scratchpad
+
+// Monitoring (toggled on the focused window)
+pub const KEY_MONITOR_ACTIVITY: KeyCode = KeyCode::Char('m');
+pub const KEY_MONITOR_SILENCE: KeyCode = KeyCode::Char('M');
+pub const KEY_WATCH: KeyCode = KeyCode::Char('w'); // Prompt for a regex to watch for (empty clears)
+pub const KEY_NOTIFICATIONS: KeyCode = KeyCode::Char('n'); // Toggle notification history
+pub const KEY_SAVE: KeyCode = KeyCode::Char('S'); // Prompt for a file to save the scrollback to (.ans/.html for colours)
+pub const KEY_CLEAR_HISTORY: KeyCode = KeyCode::Char('C'); // Clear the focused window's scrollback
 
 // ============================================================================
 // COPY MODE KEYBINDINGS
@@ -66,5 +187,6 @@ pub const COPY_EXIT_2: KeyCode = KeyCode::Esc;
 
 // All other copy mode keys are standard vim motions (not configurable):
 // Movement: h, j, k, l, 0, $, ^, g, G, H, M, L, PgUp, PgDown
+// Time: @HH:MM (go to when a line scrolled in), S (toggle timestamp gutter)
 // Visual: v (char), V (line)
 // Yank: y (copies selection and exits)
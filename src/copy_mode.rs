@@ -1,5 +1,6 @@
 //! Vim-style copy mode for scrollback navigation and text selection
 
+use crate::render::LineMarks;
 use regex::Regex;
 
 /// Search input mode
@@ -8,6 +9,7 @@ pub enum SearchMode {
     None,
     Forward,  // /
     Backward, // ?
+    Time,     // @ - time to go to
 }
 
 /// Find char mode for f/F/t/T
@@ -171,6 +173,20 @@ impl CopyModeState {
         self.count = None;
     }
 
+    /// Follow a change in the amount of scrollback (e.g. history being cleared),
+    /// pulling positions in lines that no longer exist onto the oldest line
+    pub fn set_scrollback_len(&mut self, scrollback_len: usize) {
+        self.scrollback_len = scrollback_len;
+        let min_y = -(scrollback_len as i32);
+        if let Some(ref mut sel) = self.selection {
+            sel.anchor.y = sel.anchor.y.max(min_y);
+            sel.cursor.y = sel.cursor.y.max(min_y);
+        }
+        self.search_matches.retain(|m| m.y >= min_y);
+        self.clamp_cursor();
+        self.scroll_offset = self.scroll_offset.min(scrollback_len);
+    }
+
     /// Clamp cursor position to valid bounds
     fn clamp_cursor(&mut self) {
         let max_y = (self.buffer_height as i32) - 1;
@@ -317,6 +333,51 @@ impl CopyModeState {
         self.move_cursor(BufferPos::new(self.cursor.x, new_y));
     }
 
+    /// Move to the previous ([) or next (]) shell prompt. Returns true if found
+    pub fn move_to_prompt<F>(&mut self, forward: bool, line_marks: F) -> bool
+    where
+        F: Fn(i32) -> LineMarks,
+    {
+        let min_y = -(self.scrollback_len as i32);
+        let max_y = (self.buffer_height as i32) - 1;
+        let found = if forward {
+            (self.cursor.y + 1..=max_y).find(|&y| line_marks(y).prompt)
+        } else {
+            (min_y..self.cursor.y).rev().find(|&y| line_marks(y).prompt)
+        };
+        match found {
+            Some(y) => {
+                self.move_cursor(BufferPos::new(0, y));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move to the first scrollback line that scrolled in at or after `time`
+    /// (seconds since the Unix epoch), or to the screen if none did (@).
+    /// `line_time` must not decrease from older to newer lines
+    pub fn move_to_time<F>(&mut self, time: u64, line_time: F) -> bool
+    where
+        F: Fn(i32) -> Option<u64>,
+    {
+        if self.scrollback_len == 0 {
+            return false;
+        }
+        // Binary search over the scrollback, oldest line first
+        let (mut lo, mut hi) = (-(self.scrollback_len as i32), 0);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if line_time(mid).is_some_and(|t| t >= time) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        self.move_cursor(BufferPos::new(0, lo));
+        true
+    }
+
     // === Visual Mode ===
 
     /// Toggle character-wise visual mode (v)
@@ -476,6 +537,34 @@ impl CopyModeState {
         self.search_input.clear();
     }
 
+    /// Start typing a time to go to (shares the search input line)
+    pub fn start_time_input(&mut self) {
+        self.search_mode = SearchMode::Time;
+        self.search_input.clear();
+    }
+
+    /// Go to the time typed after @ (HH:MM or HH:MM:SS, the latest such time
+    /// not in the future). Returns true if the input was a valid time
+    pub fn execute_time_input<F>(&mut self, line_time: F) -> bool
+    where
+        F: Fn(i32) -> Option<u64>,
+    {
+        self.search_mode = SearchMode::None;
+        let Some(time_of_day) = parse_time_of_day(&self.search_input) else {
+            return false;
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let midnight = now - local_time_of_day(now) as u64;
+        let mut time = midnight + time_of_day as u64;
+        if time > now {
+            time = time.saturating_sub(24 * 60 * 60);
+        }
+        self.move_to_time(time, line_time);
+        true
+    }
+
     /// Cancel search input
     pub fn cancel_search(&mut self) {
         self.search_mode = SearchMode::None;
@@ -551,7 +640,7 @@ impl CopyModeState {
         F: Fn(i32) -> Vec<char>,
     {
         if let Some((ref pattern, forward)) = self.last_search.clone() {
-            self.search_next_impl(&pattern, forward, get_line)
+            self.search_next_impl(pattern, forward, get_line)
         } else {
             false
         }
@@ -563,7 +652,7 @@ impl CopyModeState {
         F: Fn(i32) -> Vec<char>,
     {
         if let Some((ref pattern, forward)) = self.last_search.clone() {
-            self.search_next_impl(&pattern, !forward, get_line)
+            self.search_next_impl(pattern, !forward, get_line)
         } else {
             false
         }
@@ -698,12 +787,11 @@ impl CopyModeState {
 
     fn find_char_impl(&mut self, c: char, forward: bool, inclusive: bool, line_content: &[char]) -> bool {
         let x = self.cursor.x as usize;
-        let len = line_content.len();
 
         if forward {
             // Search forward from cursor+1
-            for i in (x + 1)..len {
-                if line_content[i] == c {
+            for (i, &ch) in line_content.iter().enumerate().skip(x + 1) {
+                if ch == c {
                     let new_x = if inclusive { i } else { i.saturating_sub(1) };
                     self.move_cursor(BufferPos::new(new_x as u16, self.cursor.y));
                     return true;
@@ -756,6 +844,48 @@ impl CopyModeState {
         }
     }
 
+    /// Select the output of one command (io) or the whole command including its prompt (ao)
+    /// using the shell integration marks. Returns true if selection was successful
+    pub fn select_command_object<F>(&mut self, line_marks: F) -> bool
+    where
+        F: Fn(i32) -> LineMarks,
+    {
+        let modifier = match self.pending_text_object.take() {
+            Some(m) => m,
+            None => return false,
+        };
+
+        let min_y = -(self.scrollback_len as i32);
+        let max_y = (self.buffer_height as i32) - 1;
+
+        // The command block starts at the prompt at or above the cursor and ends
+        // before the next prompt
+        let Some(prompt_y) = (min_y..=self.cursor.y).rev().find(|&y| line_marks(y).prompt) else {
+            return false;
+        };
+        let end_y = (prompt_y + 1..=max_y)
+            .find(|&y| line_marks(y).prompt)
+            .map(|y| y - 1)
+            .unwrap_or(max_y);
+
+        let start_y = if modifier == TextObjectModifier::Inner {
+            match (prompt_y..=end_y).find(|&y| line_marks(y).output) {
+                Some(y) => y,
+                None => return false,
+            }
+        } else {
+            prompt_y
+        };
+
+        self.visual_mode = VisualMode::Line;
+        self.selection = Some(Selection {
+            anchor: BufferPos::new(0, start_y),
+            cursor: BufferPos::new(0, end_y),
+        });
+        self.move_cursor(BufferPos::new(0, end_y));
+        true
+    }
+
     fn select_word_object(&mut self, line_content: &[char], inner: bool, big_word: bool) -> bool {
         let classify = if big_word { CharClass::of_word } else { CharClass::of };
         let x = self.cursor.x as usize;
@@ -810,7 +940,6 @@ impl CopyModeState {
 
     fn select_quote_object(&mut self, line_content: &[char], inner: bool, quote: char) -> bool {
         let x = self.cursor.x as usize;
-        let len = line_content.len();
 
         // Find opening quote (backward from cursor or at cursor)
         let mut open_pos = None;
@@ -828,8 +957,8 @@ impl CopyModeState {
 
         // Find closing quote (forward from opening)
         let mut close_pos = None;
-        for i in (open + 1)..len {
-            if line_content[i] == quote {
+        for (i, &ch) in line_content.iter().enumerate().skip(open + 1) {
+            if ch == quote {
                 close_pos = Some(i);
                 break;
             }
@@ -864,7 +993,6 @@ impl CopyModeState {
 
     fn select_bracket_object(&mut self, line_content: &[char], inner: bool, open: char, close: char) -> bool {
         let x = self.cursor.x as usize;
-        let len = line_content.len();
 
         // Find opening bracket (backward, handling nesting)
         let mut open_pos = None;
@@ -889,10 +1017,10 @@ impl CopyModeState {
         // Find closing bracket (forward, handling nesting)
         let mut close_pos = None;
         let mut depth = 0;
-        for i in (open_idx + 1)..len {
-            if line_content[i] == open {
+        for (i, &ch) in line_content.iter().enumerate().skip(open_idx + 1) {
+            if ch == open {
                 depth += 1;
-            } else if line_content[i] == close {
+            } else if ch == close {
                 if depth == 0 {
                     close_pos = Some(i);
                     break;
@@ -929,6 +1057,38 @@ impl CopyModeState {
     }
 }
 
+/// Seconds since midnight for "HH:MM" or "HH:MM:SS"
+fn parse_time_of_day(input: &str) -> Option<u32> {
+    let mut parts = input.trim().split(':').map(|p| p.parse::<u32>().ok());
+    let hours = parts.next()??;
+    let minutes = parts.next()??;
+    let seconds = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Seconds since local midnight at a Unix time
+fn local_time_of_day(time: u64) -> u32 {
+    let t = time as libc::time_t;
+    // localtime_r only writes the tm it is given
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return (time % (24 * 60 * 60)) as u32;
+    }
+    (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as u32
+}
+
+/// Columns of the timestamp gutter: HH:MM:SS and a space
+pub const TIMESTAMP_GUTTER_WIDTH: u16 = 9;
+
+/// Local wall-clock time of a Unix time as HH:MM:SS (for the timestamp gutter)
+pub fn format_time_of_day(time: u64) -> String {
+    let seconds = local_time_of_day(time);
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1143,4 +1303,95 @@ mod tests {
         assert_eq!(bounds.0, 4); // start x (opening paren)
         assert_eq!(bounds.2, 15); // end x (closing paren)
     }
+
+    /// Marks for a buffer with prompts at -10 and 2, command output starting at -9
+    fn prompt_marks(y: i32) -> LineMarks {
+        LineMarks {
+            prompt: y == -10 || y == 2,
+            output: y == -9,
+            ..LineMarks::default()
+        }
+    }
+
+    #[test]
+    fn test_prompt_motions() {
+        let mut state = CopyModeState::new(80, 24, 100);
+        state.cursor = BufferPos::new(5, 10);
+
+        assert!(state.move_to_prompt(false, prompt_marks));
+        assert_eq!(state.cursor, BufferPos::new(0, 2));
+        assert!(state.move_to_prompt(false, prompt_marks));
+        assert_eq!(state.cursor.y, -10);
+        assert!(!state.move_to_prompt(false, prompt_marks));
+
+        assert!(state.move_to_prompt(true, prompt_marks));
+        assert_eq!(state.cursor.y, 2);
+        assert!(!state.move_to_prompt(true, prompt_marks));
+    }
+
+    #[test]
+    fn test_text_object_command_output() {
+        let mut state = CopyModeState::new(80, 24, 100);
+        state.cursor = BufferPos::new(3, -5);
+        state.start_text_object(TextObjectModifier::Inner);
+        assert!(state.select_command_object(prompt_marks));
+        assert_eq!(state.visual_mode, VisualMode::Line);
+        let bounds = state.get_selection_bounds().unwrap();
+        assert_eq!((bounds.1, bounds.3), (-9, 1));
+
+        state.cursor = BufferPos::new(3, -5);
+        state.start_text_object(TextObjectModifier::Around);
+        assert!(state.select_command_object(prompt_marks));
+        let bounds = state.get_selection_bounds().unwrap();
+        assert_eq!((bounds.1, bounds.3), (-10, 1));
+
+        // The last command has no output mark
+        state.cursor = BufferPos::new(0, 5);
+        state.start_text_object(TextObjectModifier::Inner);
+        assert!(!state.select_command_object(prompt_marks));
+    }
+
+    #[test]
+    fn test_set_scrollback_len() {
+        let mut state = CopyModeState::new(80, 24, 100);
+        state.move_to_top();
+        state.toggle_visual_char();
+        state.move_down();
+        assert_eq!(state.cursor.y, -99);
+        assert_eq!(state.scroll_offset, 100);
+
+        // History cleared - everything moves onto the (now) oldest line
+        state.set_scrollback_len(0);
+        assert_eq!(state.cursor.y, 0);
+        assert_eq!(state.scroll_offset, 0);
+        let sel = state.selection.unwrap();
+        assert_eq!((sel.anchor.y, sel.cursor.y), (0, 0));
+    }
+
+    #[test]
+    fn test_move_to_time() {
+        // 100 scrollback lines that scrolled in one per 10 seconds from t=1000
+        let mut state = CopyModeState::new(80, 24, 100);
+        let line_time = |y: i32| (y < 0).then(|| 1000 + (100 + y) as u64 * 10);
+        assert!(state.move_to_time(1255, line_time));
+        assert_eq!(state.cursor.y, -74); // scrolled in at 1260
+        assert!(state.move_to_time(0, line_time));
+        assert_eq!(state.cursor.y, -100);
+        // Later than every line: onto the screen
+        assert!(state.move_to_time(5000, line_time));
+        assert_eq!(state.cursor.y, 0);
+
+        let mut empty = CopyModeState::new(80, 24, 0);
+        assert!(!empty.move_to_time(1000, line_time));
+    }
+
+    #[test]
+    fn test_parse_time_of_day() {
+        assert_eq!(parse_time_of_day("14:32"), Some(14 * 3600 + 32 * 60));
+        assert_eq!(parse_time_of_day(" 9:05:07 "), Some(9 * 3600 + 5 * 60 + 7));
+        assert_eq!(parse_time_of_day("24:00"), None);
+        assert_eq!(parse_time_of_day("14"), None);
+        assert_eq!(parse_time_of_day("1:2:3:4"), None);
+        assert_eq!(parse_time_of_day("ab:cd"), None);
+    }
 }
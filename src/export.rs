@@ -0,0 +1,279 @@
+//! Saving a pane's scrollback and screen as plain text, ANSI or HTML
+
+use crate::color;
+use crate::render::{Attrs, Cell, ScreenBuffer};
+use crate::spill::SpillReader;
+use crossterm::style::Color;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+/// Output format, picked from the file name's extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Text,
+    Ansi, // .ans / .ansi - text with SGR sequences
+    Html, // .html / .htm - colours as inline truecolor styles
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        match ext.as_str() {
+            "ans" | "ansi" => ExportFormat::Ansi,
+            "html" | "htm" => ExportFormat::Html,
+            _ => ExportFormat::Text,
+        }
+    }
+}
+
+/// Foreground and background of the HTML page, for cells using the defaults
+const HTML_DEFAULT_FG: (u8, u8, u8) = (0xe5, 0xe5, 0xe5);
+const HTML_DEFAULT_BG: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+/// Output is handed to the writer in chunks of about this size
+const FLUSH_BYTES: usize = 64 * 1024;
+
+/// A pane's lines taken on the UI thread, to be written out on another one.
+/// Spilled scrollback stays on disk and is paged in while writing.
+pub struct Snapshot {
+    spilled: Option<SpillReader>,
+    lines: Vec<Vec<Cell>>,
+    title: String,
+}
+
+impl Snapshot {
+    pub fn new(buffer: &ScreenBuffer) -> anyhow::Result<Self> {
+        let spilled = buffer.spill_reader()?;
+        let in_memory = buffer.scrollback_len() - spilled.as_ref().map_or(0, |s| s.len());
+        let mut lines: Vec<Vec<Cell>> = (-(in_memory as i32)..buffer.height() as i32)
+            .map(|y| trimmed((0..buffer.width()).map(|x| buffer.get_at_scroll_offset(x, y)).collect()))
+            .collect();
+        // The empty rows below the last output are left out
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        Ok(Snapshot { spilled, lines, title: buffer.title().unwrap_or("truetm").to_string() })
+    }
+
+    /// Write every line, oldest scrollback line first; returns the number of lines
+    pub fn write(self, out: &mut impl io::Write, format: ExportFormat) -> io::Result<usize> {
+        let mut text = String::new();
+        if format == ExportFormat::Html {
+            let _ = write!(
+                text,
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n\
+                 <body style=\"background-color:{};color:{}\">\n<pre>\n",
+                escape_html(&self.title),
+                hex(HTML_DEFAULT_BG),
+                hex(HTML_DEFAULT_FG)
+            );
+        }
+        let spilled = self.spilled.iter().flat_map(|reader| reader.lines()).map(|line| trimmed(line.cells));
+        let mut count = 0;
+        for line in spilled.chain(self.lines.iter().cloned()) {
+            match format {
+                ExportFormat::Text => {
+                    text.extend(line.iter().map(|c| c.ch));
+                    text.push('\n');
+                }
+                ExportFormat::Ansi => write_ansi_line(&mut text, &line),
+                ExportFormat::Html => write_html_line(&mut text, &line),
+            }
+            count += 1;
+            if text.len() >= FLUSH_BYTES {
+                out.write_all(text.as_bytes())?;
+                text.clear();
+            }
+        }
+        if format == ExportFormat::Html {
+            text.push_str("</pre>\n</body>\n</html>\n");
+        }
+        out.write_all(text.as_bytes())?;
+        out.flush()?;
+        Ok(count)
+    }
+}
+
+/// Trailing cells that would print as nothing are dropped
+fn trimmed(mut line: Vec<Cell>) -> Vec<Cell> {
+    let len = line.iter().rposition(|c| c.ch != ' ' || c.bg.is_some()).map_or(0, |x| x + 1);
+    line.truncate(len);
+    line
+}
+
+fn same_style(a: &Cell, b: &Cell) -> bool {
+    a.fg == b.fg && a.bg == b.bg && a.attrs == b.attrs
+}
+
+fn write_ansi_line(out: &mut String, line: &[Cell]) {
+    let mut current = Cell::default();
+    for cell in line {
+        if !same_style(cell, &current) {
+            out.push_str("\x1b[0");
+            for (attr, code) in [
+                (Attrs::BOLD, 1),
+                (Attrs::DIM, 2),
+                (Attrs::ITALIC, 3),
+                (Attrs::UNDERLINE, 4),
+                (Attrs::REVERSE, 7),
+                (Attrs::STRIKETHROUGH, 9),
+            ] {
+                if cell.attrs.has(attr) {
+                    let _ = write!(out, ";{}", code);
+                }
+            }
+            if let Some(fg) = cell.fg {
+                push_sgr_color(out, fg, 30, 90, 38);
+            }
+            if let Some(bg) = cell.bg {
+                push_sgr_color(out, bg, 40, 100, 48);
+            }
+            out.push('m');
+            current = *cell;
+        }
+        out.push(cell.ch);
+    }
+    if !same_style(&current, &Cell::default()) {
+        out.push_str("\x1b[0m");
+    }
+    out.push('\n');
+}
+
+/// SGR parameters for a colour: `base` + 0-7 for the normal colours, `bright` +
+/// 0-7 for the bright ones, and `extended` (38/48) for indexed and RGB colours
+fn push_sgr_color(out: &mut String, color: Color, base: u8, bright: u8, extended: u8) {
+    let _ = match color {
+        Color::Black => write!(out, ";{}", base),
+        Color::DarkRed => write!(out, ";{}", base + 1),
+        Color::DarkGreen => write!(out, ";{}", base + 2),
+        Color::DarkYellow => write!(out, ";{}", base + 3),
+        Color::DarkBlue => write!(out, ";{}", base + 4),
+        Color::DarkMagenta => write!(out, ";{}", base + 5),
+        Color::DarkCyan => write!(out, ";{}", base + 6),
+        Color::Grey => write!(out, ";{}", base + 7),
+        Color::DarkGrey => write!(out, ";{}", bright),
+        Color::Red => write!(out, ";{}", bright + 1),
+        Color::Green => write!(out, ";{}", bright + 2),
+        Color::Yellow => write!(out, ";{}", bright + 3),
+        Color::Blue => write!(out, ";{}", bright + 4),
+        Color::Magenta => write!(out, ";{}", bright + 5),
+        Color::Cyan => write!(out, ";{}", bright + 6),
+        Color::White => write!(out, ";{}", bright + 7),
+        Color::AnsiValue(n) => write!(out, ";{};5;{}", extended, n),
+        Color::Rgb { r, g, b } => write!(out, ";{};2;{};{};{}", extended, r, g, b),
+        Color::Reset => write!(out, ";{}", base + 9),
+    };
+}
+
+fn write_html_line(out: &mut String, line: &[Cell]) {
+    let mut start = 0;
+    while start < line.len() {
+        let cell = line[start];
+        let end = line[start..].iter().position(|c| !same_style(c, &cell)).map_or(line.len(), |n| start + n);
+        let text: String = line[start..end].iter().map(|c| c.ch).collect();
+        let style = html_style(&cell);
+        if style.is_empty() {
+            out.push_str(&escape_html(&text));
+        } else {
+            let _ = write!(out, "<span style=\"{}\">{}</span>", style, escape_html(&text));
+        }
+        start = end;
+    }
+    out.push('\n');
+}
+
+fn html_style(cell: &Cell) -> String {
+    let (mut fg, mut bg) = (cell.fg, cell.bg);
+    if cell.attrs.has(Attrs::REVERSE) {
+        // Swapped colours need concrete values where the defaults were used
+        let default_fg = Color::Rgb { r: HTML_DEFAULT_FG.0, g: HTML_DEFAULT_FG.1, b: HTML_DEFAULT_FG.2 };
+        let default_bg = Color::Rgb { r: HTML_DEFAULT_BG.0, g: HTML_DEFAULT_BG.1, b: HTML_DEFAULT_BG.2 };
+        (fg, bg) = (Some(bg.unwrap_or(default_bg)), Some(fg.unwrap_or(default_fg)));
+    }
+    let mut style = String::new();
+    if let Some(rgb) = fg.and_then(color::rgb) {
+        let _ = write!(style, "color:{};", hex(rgb));
+    }
+    if let Some(rgb) = bg.and_then(color::rgb) {
+        let _ = write!(style, "background-color:{};", hex(rgb));
+    }
+    if cell.attrs.has(Attrs::BOLD) {
+        style.push_str("font-weight:bold;");
+    }
+    if cell.attrs.has(Attrs::DIM) {
+        style.push_str("opacity:0.6;");
+    }
+    if cell.attrs.has(Attrs::ITALIC) {
+        style.push_str("font-style:italic;");
+    }
+    match (cell.attrs.has(Attrs::UNDERLINE), cell.attrs.has(Attrs::STRIKETHROUGH)) {
+        (true, true) => style.push_str("text-decoration:underline line-through;"),
+        (true, false) => style.push_str("text-decoration:underline;"),
+        (false, true) => style.push_str("text-decoration:line-through;"),
+        (false, false) => {}
+    }
+    style
+}
+
+fn hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_with(data: &[u8]) -> ScreenBuffer {
+        let mut buf = ScreenBuffer::new(12, 3);
+        buf.process(data);
+        buf
+    }
+
+    fn export(buf: &ScreenBuffer, format: ExportFormat) -> (String, usize) {
+        let mut out = Vec::new();
+        let lines = Snapshot::new(buf).unwrap().write(&mut out, format).unwrap();
+        (String::from_utf8(out).unwrap(), lines)
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ExportFormat::from_path(Path::new("log.txt")), ExportFormat::Text);
+        assert_eq!(ExportFormat::from_path(Path::new("log")), ExportFormat::Text);
+        assert_eq!(ExportFormat::from_path(Path::new("log.ANS")), ExportFormat::Ansi);
+        assert_eq!(ExportFormat::from_path(Path::new("/tmp/log.html")), ExportFormat::Html);
+    }
+
+    #[test]
+    fn test_export_text_and_ansi() {
+        // One line scrolls into the scrollback; the empty last row is left out
+        let buf = buffer_with(b"one\r\ntwo\r\n\x1b[1;31mred\x1b[0m ok\r\n");
+        let (text, lines) = export(&buf, ExportFormat::Text);
+        assert_eq!(text, "one\ntwo\nred ok\n");
+        assert_eq!(lines, 3);
+        let (ansi, _) = export(&buf, ExportFormat::Ansi);
+        assert_eq!(ansi, "one\ntwo\n\x1b[0;1;31mred\x1b[0m ok\n");
+    }
+
+    #[test]
+    fn test_export_html() {
+        let buf = buffer_with(b"\x1b]2;a&b\x07<a>\x1b[38;2;1;2;3;48;5;196mx\x1b[0m");
+        let (html, _) = export(&buf, ExportFormat::Html);
+        assert!(html.contains("<title>a&amp;b</title>"));
+        assert!(html.contains("&lt;a&gt;<span style=\"color:#010203;background-color:#ff0000;\">x</span>\n"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Highlight rules - regexes whose matches are restyled in the live view
+
+use crate::config;
+use crate::pane::PaneId;
+use crate::render::{Attrs, Cell};
+use anyhow::{bail, Context, Result};
+use crossterm::style::Color;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// A highlight rule as written in config.rs
+pub struct HighlightRule {
+    pub pattern: &'static str,
+    pub style: &'static str,
+}
+
+/// Colours and attributes laid over matched text; unset colours keep the cell's
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct HighlightStyle {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub attrs: Attrs,
+}
+
+impl HighlightStyle {
+    /// Parse a comma-separated style such as "bold,fg=yellow,bg=#5f0000".
+    /// Colours are names (red, brightred, ...), 0-255 or #rrggbb
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut style = Self::default();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some(("fg", color)) => style.fg = Some(parse_color(color)?),
+                Some(("bg", color)) => style.bg = Some(parse_color(color)?),
+                Some(_) => bail!("Unknown style setting '{}'", part),
+                None => style.attrs.set(match part {
+                    "bold" => Attrs::BOLD,
+                    "dim" => Attrs::DIM,
+                    "italic" => Attrs::ITALIC,
+                    "underline" => Attrs::UNDERLINE,
+                    "reverse" => Attrs::REVERSE,
+                    "strikethrough" => Attrs::STRIKETHROUGH,
+                    _ => bail!("Unknown style attribute '{}'", part),
+                }),
+            }
+        }
+        if style == Self::default() {
+            bail!("Empty highlight style");
+        }
+        Ok(style)
+    }
+
+    /// Lay the style over a cell
+    pub fn apply(&self, cell: &mut Cell) {
+        cell.fg = self.fg.or(cell.fg);
+        cell.bg = self.bg.or(cell.bg);
+        cell.attrs.set(self.attrs.bits());
+    }
+}
+
+fn parse_color(name: &str) -> Result<Color> {
+    const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    if let Some(hex) = name.strip_prefix('#') {
+        let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6);
+        let value = value.with_context(|| format!("Invalid colour '{}'", name))?;
+        return Ok(Color::Rgb { r: (value >> 16) as u8, g: (value >> 8) as u8, b: value as u8 });
+    }
+    if let Ok(index) = name.parse::<u8>() {
+        return Ok(Color::AnsiValue(index));
+    }
+    let (bright, base) = match name.strip_prefix("bright") {
+        Some(base) => (8, base),
+        None => (0, name),
+    };
+    match NAMES.iter().position(|&n| n == base) {
+        Some(index) => Ok(Color::AnsiValue(bright + index as u8)),
+        None => bail!("Invalid colour '{}'", name),
+    }
+}
+
+/// A compiled rule, global or limited to one pane
+struct Highlight {
+    regex: Regex,
+    spec: String,
+    style: HighlightStyle,
+    pane: Option<PaneId>,
+}
+
+/// A highlighted run of columns in a row
+#[derive(Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: u16,
+    pub end: u16,
+    pub style: HighlightStyle,
+}
+
+/// Matches of a rendered row, kept until the row's text changes
+#[derive(Default)]
+struct CachedRow {
+    text: String,
+    spans: Vec<Span>,
+}
+
+/// All highlight rules, with the matches of each pane's rows
+pub struct HighlightSet {
+    rules: Vec<Highlight>,
+    cache: HashMap<PaneId, Vec<CachedRow>>,
+}
+
+impl HighlightSet {
+    /// Compile the global rules from config.rs (invalid ones are logged and skipped)
+    pub fn from_config() -> Self {
+        let mut set = Self { rules: Vec::new(), cache: HashMap::new() };
+        for rule in config::HIGHLIGHTS {
+            if let Err(e) = set.add(None, rule.pattern, rule.style) {
+                log::error!("Ignoring highlight: {:#}", e);
+            }
+        }
+        set
+    }
+
+    /// Add a rule for one pane (or all panes if `pane` is None)
+    pub fn add(&mut self, pane: Option<PaneId>, pattern: &str, spec: &str) -> Result<()> {
+        let regex = Regex::new(pattern).with_context(|| format!("Invalid highlight pattern '{}'", pattern))?;
+        let style = HighlightStyle::parse(spec)?;
+        self.rules.push(Highlight { regex, spec: spec.to_string(), style, pane });
+        self.cache.clear();
+        Ok(())
+    }
+
+    /// Remove the rules of one pane (or the global ones) with a pattern, or all
+    /// of them if `pattern` is None. Returns how many were removed
+    pub fn remove(&mut self, pane: Option<PaneId>, pattern: Option<&str>) -> usize {
+        let before = self.rules.len();
+        self.rules.retain(|h| h.pane != pane || pattern.is_some_and(|p| h.regex.as_str() != p));
+        self.cache.clear();
+        before - self.rules.len()
+    }
+
+    /// Forget a closed pane's rules and cached rows
+    pub fn remove_pane(&mut self, pane: PaneId) {
+        self.rules.retain(|h| h.pane != Some(pane));
+        self.cache.remove(&pane);
+    }
+
+    /// Whether any rule applies to the given pane
+    pub fn applies_to(&self, pane: PaneId) -> bool {
+        self.rules.iter().any(|h| h.pane.is_none() || h.pane == Some(pane))
+    }
+
+    /// The rules applying to a pane, as "pattern (style)", global ones marked
+    pub fn describe(&self, pane: Option<PaneId>) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter(|h| h.pane.is_none() || h.pane == pane)
+            .map(|h| {
+                let scope = if h.pane.is_none() { ", all windows" } else { "" };
+                format!("{} ({}{})", h.regex.as_str(), h.spec, scope)
+            })
+            .collect()
+    }
+
+    /// Highlighted spans of a pane's row, matched again only when its text changed.
+    /// The text has one char per column; later rules win where matches overlap
+    pub fn row_spans(&mut self, pane: PaneId, row: usize, text: &str) -> &[Span] {
+        let rows = self.cache.entry(pane).or_default();
+        if rows.len() <= row {
+            rows.resize_with(row + 1, CachedRow::default);
+        }
+        let cached = &mut rows[row];
+        if cached.text != text {
+            cached.text.clear();
+            cached.text.push_str(text);
+            cached.spans.clear();
+            let rules = self.rules.iter().filter(|h| h.pane.is_none() || h.pane == Some(pane));
+            for rule in rules {
+                for m in rule.regex.find_iter(text).filter(|m| !m.is_empty()) {
+                    // Byte offsets to columns
+                    let start = text[..m.start()].chars().count() as u16;
+                    let end = start + m.as_str().chars().count() as u16;
+                    cached.spans.push(Span { start, end, style: rule.style });
+                }
+            }
+        }
+        &cached.spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_style() {
+        let style = HighlightStyle::parse("bold, fg=yellow,bg=#5f0000").unwrap();
+        assert_eq!(style.fg, Some(Color::AnsiValue(3)));
+        assert_eq!(style.bg, Some(Color::Rgb { r: 0x5f, g: 0, b: 0 }));
+        assert!(style.attrs.has(Attrs::BOLD));
+        assert_eq!(HighlightStyle::parse("fg=brightred").unwrap().fg, Some(Color::AnsiValue(9)));
+        assert_eq!(HighlightStyle::parse("bg=236").unwrap().bg, Some(Color::AnsiValue(236)));
+        assert!(HighlightStyle::parse("fg=purple").is_err());
+        assert!(HighlightStyle::parse("blink").is_err());
+        assert!(HighlightStyle::parse("").is_err());
+    }
+
+    #[test]
+    fn test_row_spans() {
+        let mut set = HighlightSet { rules: Vec::new(), cache: HashMap::new() };
+        set.add(None, "ERROR", "bold,bg=red").unwrap();
+        set.add(Some(PaneId(1)), "WARN", "fg=yellow").unwrap();
+
+        let spans = set.row_spans(PaneId(0), 0, "é ERROR WARN").to_vec();
+        assert_eq!(spans.len(), 1);
+        assert_eq!((spans[0].start, spans[0].end), (2, 7));
+        assert_eq!(set.row_spans(PaneId(1), 0, "é ERROR WARN").len(), 2);
+        // Cached until the text changes
+        assert!(set.row_spans(PaneId(0), 0, "é ERROR WARN") == spans.as_slice());
+        assert!(set.row_spans(PaneId(0), 0, "all good").is_empty());
+
+        assert_eq!(set.remove(Some(PaneId(1)), Some("WARN")), 1);
+        assert_eq!(set.remove(None, None), 1);
+        assert!(!set.applies_to(PaneId(1)));
+    }
+}
@@ -0,0 +1,438 @@
+//! Inline images - sixel and kitty graphics protocol passthrough
+//!
+//! Images are anchored to buffer cells by the screen buffer and scroll with
+//! the content. The compositor re-emits them on the outer terminal, clipped
+//! to the pane, or draws a placeholder in their place.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Largest DCS/APC string kept for image decoding (bigger ones are dropped)
+pub const MAX_SEQUENCE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Maximum number of images anchored in one buffer (oldest are dropped)
+pub const MAX_IMAGES: usize = 64;
+
+/// Image data one buffer may hold: anchored images on both screens plus kitty
+/// uploads. Past it, the oldest are dropped
+pub const MAX_IMAGE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Maximum number of kitty uploads (transmitted but unplaced images) per buffer
+pub const MAX_KITTY_UPLOADS: usize = 64;
+
+/// Kitty escape codes sent to the outer terminal are chunked to this payload size
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// How images drawn by applications are shown on the outer terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Variants are chosen in config.rs
+pub enum ImageMode {
+    /// Re-emit the image, clipped to the pane
+    Passthrough,
+    /// Draw a labelled placeholder over the image's cells
+    Placeholder,
+}
+
+/// Protocol an image arrived in (and is re-emitted with)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageProtocol {
+    Sixel,
+    Kitty,
+}
+
+/// An image anchored to buffer cells
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// Unique across all panes - also the image id used on the outer terminal
+    pub id: u32,
+    /// Id the application gave the image (kitty only, 0 if none) - used for deletes
+    pub data_id: u32,
+    pub protocol: ImageProtocol,
+    /// Sixel: DCS body (params, 'q', data). Kitty: transmission keys ';' base64 payload
+    pub data: Vec<u8>,
+    /// Top row in buffer coordinates (negative = scrollback) and left column
+    pub row: i32,
+    pub col: u16,
+    /// Size in cells
+    pub cols: u16,
+    pub rows: u16,
+    /// Size in pixels (0 if unknown)
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+}
+
+static NEXT_IMAGE_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Allocate an image id unique for this process
+pub fn next_image_id() -> u32 {
+    NEXT_IMAGE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+impl Image {
+    /// Short label for placeholders, e.g. "sixel 320x240"
+    pub fn label(&self) -> String {
+        let kind = match self.protocol {
+            ImageProtocol::Sixel => "sixel",
+            ImageProtocol::Kitty => "image",
+        };
+        if self.pixel_width > 0 && self.pixel_height > 0 {
+            format!("{} {}x{}", kind, self.pixel_width, self.pixel_height)
+        } else {
+            kind.to_string()
+        }
+    }
+
+    /// Escape sequence showing the visible part of this image at the cursor
+    /// skip/keep are (columns, rows) of the image to skip and keep. Returns None
+    /// if the image can't be clipped that way (the caller shows a placeholder)
+    pub fn clipped_sequence(&self, skip: (u16, u16), keep: (u16, u16)) -> Option<Vec<u8>> {
+        match self.protocol {
+            ImageProtocol::Sixel => {
+                // Sixel can only be cut into 6-pixel bands, so no horizontal clipping
+                if skip.0 > 0 || keep.0 < self.cols {
+                    return None;
+                }
+                let body = if skip.1 == 0 && keep.1 >= self.rows {
+                    self.data.clone()
+                } else {
+                    let row_px = self.pixel_height.max(1) as f32 / self.rows.max(1) as f32;
+                    let skip_bands = ((skip.1 as f32 * row_px) / 6.0).ceil() as usize;
+                    let keep_bands = ((keep.1 as f32 * row_px) / 6.0).floor() as usize;
+                    clip_sixel(&self.data, skip_bands, keep_bands)
+                };
+                let mut seq = b"\x1bP".to_vec();
+                seq.extend_from_slice(&body);
+                seq.extend_from_slice(b"\x1b\\");
+                Some(seq)
+            }
+            ImageProtocol::Kitty => {
+                let mut keys = format!("a=p,i={},p=1,q=2,C=1,c={},r={}", self.id, keep.0, keep.1);
+                if skip != (0, 0) || keep != (self.cols, self.rows) {
+                    // Crop the source rect - needs the pixel size to map cells to pixels
+                    if self.pixel_width == 0 || self.pixel_height == 0 {
+                        return None;
+                    }
+                    let col_px = self.pixel_width / self.cols.max(1) as u32;
+                    let row_px = self.pixel_height / self.rows.max(1) as u32;
+                    keys.push_str(&format!(
+                        ",x={},y={},w={},h={}",
+                        skip.0 as u32 * col_px,
+                        skip.1 as u32 * row_px,
+                        keep.0 as u32 * col_px,
+                        keep.1 as u32 * row_px,
+                    ));
+                }
+                Some(format!("\x1b_G{}\x1b\\", keys).into_bytes())
+            }
+        }
+    }
+
+    /// Kitty upload of this image's data to the outer terminal (chunked, quiet)
+    pub fn kitty_transmit_sequence(&self) -> Vec<u8> {
+        let (keys, payload) = split_kitty_body(&self.data);
+        let mut out = Vec::with_capacity(payload.len() + 64);
+        let mut chunks = payload.chunks(KITTY_CHUNK_SIZE).peekable();
+        let mut first = true;
+        loop {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let more = if chunks.peek().is_some() { 1 } else { 0 };
+            out.extend_from_slice(b"\x1b_G");
+            if first {
+                out.extend_from_slice(format!("a=t,q=2,i={},", self.id).as_bytes());
+                out.extend_from_slice(keys);
+                out.extend_from_slice(format!(",m={}", more).as_bytes());
+                first = false;
+            } else {
+                out.extend_from_slice(format!("m={}", more).as_bytes());
+            }
+            out.push(b';');
+            out.extend_from_slice(chunk);
+            out.extend_from_slice(b"\x1b\\");
+            if more == 0 {
+                break;
+            }
+        }
+        out
+    }
+}
+
+/// Kitty sequence removing an image (placements and data) from the outer terminal
+pub fn kitty_delete_sequence(id: u32) -> Vec<u8> {
+    format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", id).into_bytes()
+}
+
+// ============================================================================
+// SIXEL
+// ============================================================================
+
+/// Pixel size of a sixel image from its raster attributes, or by scanning the data
+/// `data` is everything after the 'q' introducer
+pub fn sixel_size(data: &[u8]) -> (u32, u32) {
+    if let Some(rest) = data.strip_prefix(b"\"") {
+        let attrs: Vec<u32> = rest
+            .split(|&b| !b.is_ascii_digit() && b != b';')
+            .next()
+            .unwrap_or(&[])
+            .split(|&b| b == b';')
+            .map(parse_u32)
+            .collect();
+        if let (Some(&w), Some(&h)) = (attrs.get(2), attrs.get(3)) {
+            if w > 0 && h > 0 {
+                return (w, h);
+            }
+        }
+    }
+
+    // No raster attributes - measure the widest band and count bands
+    let mut width = 0u32;
+    let mut x = 0u32;
+    let mut bands = 0u32;
+    let mut band_has_data = false;
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'!' => {
+                // Repeat introducer: !<count><sixel>
+                let start = i + 1;
+                let mut end = start;
+                while end < data.len() && data[end].is_ascii_digit() {
+                    end += 1;
+                }
+                x += parse_u32(&data[start..end]).max(1);
+                band_has_data = true;
+                i = end + 1;
+                width = width.max(x);
+                continue;
+            }
+            b'#' | b'"' => {
+                // Color or raster attributes: skip numeric parameters
+                i += 1;
+                while i < data.len() && (data[i].is_ascii_digit() || data[i] == b';') {
+                    i += 1;
+                }
+                continue;
+            }
+            b'$' => x = 0,
+            b'-' => {
+                bands += 1;
+                band_has_data = false;
+                x = 0;
+            }
+            0x3f..=0x7e => {
+                x += 1;
+                band_has_data = true;
+            }
+            _ => {}
+        }
+        width = width.max(x);
+        i += 1;
+    }
+    if band_has_data {
+        bands += 1;
+    }
+    (width, bands * 6)
+}
+
+/// Keep `keep_bands` sixel bands after skipping `skip_bands`, preserving color definitions
+fn clip_sixel(body: &[u8], skip_bands: usize, keep_bands: usize) -> Vec<u8> {
+    let Some(q) = body.iter().position(|&b| b == b'q') else {
+        return body.to_vec();
+    };
+    let (head, data) = body.split_at(q + 1);
+    let mut out = head.to_vec();
+
+    for (i, band) in data.split(|&b| b == b'-').enumerate() {
+        if i >= skip_bands + keep_bands {
+            break;
+        }
+        if i < skip_bands {
+            // Dropped band: keep palette definitions (#Pc;Pu;Px;Py;Pz) for later bands
+            out.extend(sixel_color_definitions(band));
+            continue;
+        }
+        if i > skip_bands {
+            out.push(b'-');
+        }
+        out.extend_from_slice(band);
+    }
+    out
+}
+
+/// Extract the color definitions (not selections) from a sixel band
+fn sixel_color_definitions(band: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < band.len() {
+        if band[i] == b'#' {
+            let start = i;
+            i += 1;
+            while i < band.len() && (band[i].is_ascii_digit() || band[i] == b';') {
+                i += 1;
+            }
+            let def = &band[start..i];
+            if def.iter().filter(|&&b| b == b';').count() == 4 {
+                out.extend_from_slice(def);
+            }
+        } else {
+            i += 1;
+        }
+    }
+    out
+}
+
+// ============================================================================
+// KITTY GRAPHICS
+// ============================================================================
+
+/// Keys truetm manages itself and strips from transmissions before re-emitting
+const KITTY_MANAGED_KEYS: &[u8] = b"aiIpqmCcrxywhXYzdU";
+
+/// A parsed kitty graphics command: G<key>=<value>,...;<payload>
+#[derive(Debug, Clone, Default)]
+pub struct KittyCommand {
+    pub keys: Vec<(u8, String)>,
+    pub payload: Vec<u8>,
+}
+
+impl KittyCommand {
+    /// Parse an APC body without the leading 'G'
+    pub fn parse(body: &[u8]) -> Self {
+        let (control, payload) = match body.iter().position(|&b| b == b';') {
+            Some(p) => (&body[..p], body[p + 1..].to_vec()),
+            None => (body, Vec::new()),
+        };
+        let keys = control
+            .split(|&b| b == b',')
+            .filter_map(|kv| match kv {
+                [k, b'=', v @ ..] => Some((*k, String::from_utf8_lossy(v).into_owned())),
+                _ => None,
+            })
+            .collect();
+        Self { keys, payload }
+    }
+
+    pub fn get(&self, key: u8) -> Option<&str> {
+        self.keys.iter().find(|(k, _)| *k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_num(&self, key: u8) -> Option<u32> {
+        self.get(key).and_then(|v| v.parse().ok())
+    }
+
+    /// Transmission keys plus payload in the form stored on an `Image`
+    pub fn transmission_body(&self) -> Vec<u8> {
+        let keys: Vec<String> = self
+            .keys
+            .iter()
+            .filter(|(k, _)| !KITTY_MANAGED_KEYS.contains(k))
+            .map(|(k, v)| format!("{}={}", *k as char, v))
+            .collect();
+        let mut body = keys.join(",").into_bytes();
+        body.push(b';');
+        body.extend_from_slice(&self.payload);
+        body
+    }
+
+    /// Pixel size of the transmitted image, if it can be known without decoding
+    pub fn pixel_size(&self) -> (u32, u32) {
+        let format = self.get_num(b'f').unwrap_or(32);
+        if format == 100 {
+            // PNG: width and height are in the IHDR chunk right after the signature
+            if self.get(b't').unwrap_or("d") == "d" && self.get(b'o').is_none() {
+                let header = base64_decode(&self.payload[..self.payload.len().min(32)]);
+                if header.len() >= 24 && header.starts_with(b"\x89PNG") {
+                    let w = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
+                    let h = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+                    return (w, h);
+                }
+            }
+            (0, 0)
+        } else {
+            (self.get_num(b's').unwrap_or(0), self.get_num(b'v').unwrap_or(0))
+        }
+    }
+}
+
+/// Split a stored kitty body into (keys, payload)
+fn split_kitty_body(body: &[u8]) -> (&[u8], &[u8]) {
+    match body.iter().position(|&b| b == b';') {
+        Some(p) => (&body[..p], &body[p + 1..]),
+        None => (body, &[]),
+    }
+}
+
+/// Cells needed to show `pixels` with cells of `cell_pixels` (at least 1)
+pub fn cells_for_pixels(pixels: u32, cell_pixels: u16) -> u16 {
+    let cell = cell_pixels.max(1) as u32;
+    pixels.div_ceil(cell).clamp(1, u16::MAX as u32) as u16
+}
+
+fn parse_u32(digits: &[u8]) -> u32 {
+    digits.iter().take_while(|b| b.is_ascii_digit()).fold(0u32, |n, &d| {
+        n.saturating_mul(10).saturating_add((d - b'0') as u32)
+    })
+}
+
+/// Minimal base64 decoder (stops at padding or invalid input)
+fn base64_decode(input: &[u8]) -> Vec<u8> {
+    let value = |c: u8| -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    };
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &c in input {
+        let Some(v) = value(c) else { break };
+        acc = (acc << 6) | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sixel_size_from_raster_attributes() {
+        assert_eq!(sixel_size(b"\"1;1;64;48#0;2;0;0;0#0~~~~-~~~~"), (64, 48));
+    }
+
+    #[test]
+    fn test_sixel_size_scanned() {
+        // Two bands, the widest is 3 + 5 repeated pixels
+        assert_eq!(sixel_size(b"#1~~~!5~$~-~~"), (8, 12));
+    }
+
+    #[test]
+    fn test_clip_sixel_keeps_colors() {
+        let clipped = clip_sixel(b"0;1q#1;2;100;0;0#1~~-~~-@@", 1, 1);
+        assert_eq!(clipped, b"0;1q#1;2;100;0;0~~".to_vec());
+    }
+
+    #[test]
+    fn test_kitty_command_parse() {
+        let cmd = KittyCommand::parse(b"a=T,f=24,s=10,v=20,i=7;AAAA");
+        assert_eq!(cmd.get(b'a'), Some("T"));
+        assert_eq!(cmd.get_num(b'i'), Some(7));
+        assert_eq!(cmd.pixel_size(), (10, 20));
+        assert_eq!(cmd.transmission_body(), b"f=24,s=10,v=20;AAAA".to_vec());
+    }
+
+    #[test]
+    fn test_kitty_png_size() {
+        // PNG signature + IHDR for a 300x200 image, base64 encoded
+        let cmd = KittyCommand::parse(b"a=T,f=100;iVBORw0KGgoAAAANSUhEUgAAASwAAADI");
+        assert_eq!(cmd.pixel_size(), (300, 200));
+    }
+}
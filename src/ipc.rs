@@ -0,0 +1,253 @@
+//! Control socket - commands sent by `truetm <command>` to the running instance
+//!
+//! The client writes one line with the command and its arguments separated by
+//! NUL bytes, the server answers with one line starting with "ok: " or "error: ".
+
+use anyhow::{bail, Context, Result};
+use std::fs::DirBuilder;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Environment variable telling windows which socket their truetm listens on
+pub const SOCKET_ENV: &str = "TRUETM_SOCKET";
+
+/// How long a client waits for the reply, and the server for the main loop's answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Commands understood over the socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Clear the scrollback of the focused window, or of all windows with a tag (1-9)
+    ClearHistory { tag: Option<u8> },
+    /// Highlight a regex in the focused window, or in all windows
+    Highlight { all: bool, style: String, pattern: String },
+    /// Remove the focused window's (or the global) highlights, all or one pattern's
+    Unhighlight { all: bool, pattern: Option<String> },
+    /// List the highlights applying to the focused window
+    ListHighlights,
+    /// Switch to a built-in theme or a theme file, or show the current one
+    Theme { name: Option<String> },
+}
+
+impl Command {
+    pub fn parse(args: &[&str]) -> Result<Self> {
+        // --all applies highlight commands to every window instead of the focused one
+        let (all, rest) = match args {
+            [command, "--all", rest @ ..] => (true, (*command, rest)),
+            [command, rest @ ..] => (false, (*command, rest)),
+            [] => bail!("No command given"),
+        };
+        match rest {
+            ("clear-history", []) if !all => Ok(Command::ClearHistory { tag: None }),
+            ("clear-history", [tag]) if !all => match tag.parse::<u8>() {
+                Ok(tag @ 1..=9) => Ok(Command::ClearHistory { tag: Some(tag) }),
+                _ => bail!("Invalid tag '{}' (expected 1-9)", tag),
+            },
+            ("highlight", []) if !all => Ok(Command::ListHighlights),
+            ("highlight", [style, pattern @ ..]) if !pattern.is_empty() => Ok(Command::Highlight {
+                all,
+                style: style.to_string(),
+                pattern: pattern.join(" "),
+            }),
+            ("unhighlight", pattern) => Ok(Command::Unhighlight {
+                all,
+                pattern: (!pattern.is_empty()).then(|| pattern.join(" ")),
+            }),
+            ("theme", []) if !all => Ok(Command::Theme { name: None }),
+            ("theme", [name]) if !all => Ok(Command::Theme { name: Some(name.to_string()) }),
+            (command, _) => bail!("Unknown command '{}' or wrong arguments", command),
+        }
+    }
+}
+
+/// A command waiting for the main loop, which answers it with `reply`
+pub struct Request {
+    pub command: Command,
+    reply: Sender<Result<String>>,
+}
+
+impl Request {
+    pub fn reply(self, result: Result<String>) {
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Listening socket in $XDG_RUNTIME_DIR (or the temp directory), removed when dropped
+pub struct Server {
+    path: PathBuf,
+    requests: Receiver<Request>,
+}
+
+impl Server {
+    pub fn start() -> Result<Self> {
+        let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => private_dir()?,
+        };
+        let path = dir.join(format!("truetm-{}.sock", std::process::id()));
+        remove_stale_socket(&path)?;
+        let listener = UnixListener::bind(&path).with_context(|| format!("Failed to listen on {}", path.display()))?;
+
+        let (tx, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = serve(stream, &tx) {
+                    log::warn!("Control socket: {:#}", e);
+                }
+            }
+        });
+        Ok(Self { path, requests })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Next command received, if any
+    pub fn try_recv(&self) -> Option<Request> {
+        self.requests.try_recv().ok()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The shared temp directory is open to everyone, so sockets go in a directory
+/// of our own in it: created with mode 0700, or checked to still be ours and private
+fn private_dir() -> Result<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+    let dir = std::env::temp_dir().join(format!("truetm-{}", uid));
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => return Ok(dir),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+    }
+    let meta = std::fs::symlink_metadata(&dir).with_context(|| format!("Failed to check {}", dir.display()))?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        bail!("{} is not a private directory owned by us", dir.display());
+    }
+    Ok(dir)
+}
+
+/// A socket left by an earlier truetm with our pid is removed; anything else in
+/// its place is left alone
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to check {}", path.display())),
+    };
+    if !meta.file_type().is_socket() || meta.uid() != unsafe { libc::getuid() } {
+        bail!("{} exists and is not our socket", path.display());
+    }
+    std::fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+/// Read one command from a client, pass it to the main loop and write back its answer
+fn serve(stream: UnixStream, requests: &Sender<Request>) -> Result<()> {
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let args: Vec<&str> = line.trim_end_matches('\n').split('\0').filter(|a| !a.is_empty()).collect();
+
+    let result = Command::parse(&args).and_then(|command| {
+        let (reply, answer) = mpsc::channel();
+        requests.send(Request { command, reply }).context("truetm is shutting down")?;
+        answer.recv_timeout(REPLY_TIMEOUT).context("No answer from truetm")?
+    });
+    let response = match result {
+        Ok(message) => format!("ok: {}\n", message),
+        Err(e) => format!("error: {:#}\n", e),
+    };
+    (&stream).write_all(response.as_bytes())?;
+    Ok(())
+}
+
+/// Send a command to the truetm these arguments were run in and return its answer
+pub fn send(args: &[String]) -> Result<String> {
+    let path = std::env::var_os(SOCKET_ENV).with_context(|| format!("{} is not set - not inside truetm?", SOCKET_ENV))?;
+    let stream = UnixStream::connect(&path)
+        .with_context(|| format!("Failed to connect to {}", Path::new(&path).display()))?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT * 2))?;
+
+    // Theme files are read by truetm, which may run in another directory
+    let mut args = args.to_vec();
+    if let [command, file] = args.as_mut_slice() {
+        if command == "theme" {
+            if let Ok(path) = std::fs::canonicalize(&file) {
+                *file = path.display().to_string();
+            }
+        }
+    }
+    (&stream).write_all(format!("{}\n", args.join("\0")).as_bytes())?;
+
+    let mut response = String::new();
+    BufReader::new(&stream).read_line(&mut response)?;
+    let response = response.trim_end();
+    match response.strip_prefix("ok: ") {
+        Some(message) => Ok(message.to_string()),
+        None => bail!("{}", response.strip_prefix("error: ").unwrap_or(response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse(&["clear-history"]).unwrap(), Command::ClearHistory { tag: None });
+        assert_eq!(Command::parse(&["clear-history", "3"]).unwrap(), Command::ClearHistory { tag: Some(3) });
+        assert!(Command::parse(&["clear-history", "0"]).is_err());
+        assert!(Command::parse(&["clear-history", "1", "2"]).is_err());
+        assert!(Command::parse(&["frobnicate"]).is_err());
+        assert!(Command::parse(&[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_highlight_commands() {
+        assert_eq!(
+            Command::parse(&["highlight", "--all", "bold,bg=red", "connection", "refused"]).unwrap(),
+            Command::Highlight { all: true, style: "bold,bg=red".into(), pattern: "connection refused".into() }
+        );
+        assert_eq!(Command::parse(&["highlight"]).unwrap(), Command::ListHighlights);
+        assert!(Command::parse(&["highlight", "fg=red"]).is_err());
+        assert_eq!(
+            Command::parse(&["unhighlight", "WARN"]).unwrap(),
+            Command::Unhighlight { all: false, pattern: Some("WARN".into()) }
+        );
+        assert_eq!(Command::parse(&["unhighlight", "--all"]).unwrap(), Command::Unhighlight { all: true, pattern: None });
+        assert!(Command::parse(&["clear-history", "--all"]).is_err());
+    }
+
+    #[test]
+    fn test_remove_stale_socket() {
+        let dir = private_dir().unwrap();
+        let path = dir.join(format!("test-{}.sock", std::process::id()));
+        // Something that is not a socket is refused and kept
+        std::fs::write(&path, b"").unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+        // An old socket is replaced
+        drop(UnixListener::bind(&path).unwrap());
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+        assert!(remove_stale_socket(&path).is_ok());
+    }
+
+    #[test]
+    fn test_parse_theme_command() {
+        assert_eq!(Command::parse(&["theme"]).unwrap(), Command::Theme { name: None });
+        assert_eq!(Command::parse(&["theme", "light"]).unwrap(), Command::Theme { name: Some("light".into()) });
+        assert!(Command::parse(&["theme", "light", "dark"]).is_err());
+    }
+}
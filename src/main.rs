@@ -1,13 +1,24 @@
 //! dvtr - A truecolor-enabled terminal multiplexer inspired by dvtm
 
+mod cell;
+mod color;
 mod config;
 mod copy_mode;
+mod export;
+mod highlight;
+mod image;
+mod ipc;
 mod layout;
 mod pane;
+mod parser;
 mod render;
+mod spill;
 mod tag;
+mod terminfo;
+mod theme;
+mod trigger;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
     event::{
@@ -16,23 +27,43 @@ use crossterm::{
         EnableMouseCapture, DisableMouseCapture,
     },
     execute, queue,
-    style::ResetColor,
+    style::{Attribute, ResetColor, SetAttribute},
     terminal::{self, EnterAlternateScreen, LeaveAlternateScreen, SetTitle},
 };
 use layout::LayoutManager;
-use pane::{Pane, PaneId, PaneManager, PtyMessage, Rect};
-use render::{Compositor, ScreenBuffer};
+use pane::{Pane, PaneId, PaneManager, PtyMessage, Rect, FLOAT_MIN_HEIGHT, FLOAT_MIN_WIDTH};
+use render::{Compositor, KeyboardFlags, Notification, ScreenBuffer};
+use color::ColorDepth;
 use copy_mode::CopyModeState;
 use tag::TagSet;
-use std::collections::HashMap;
+use highlight::{HighlightSet, HighlightStyle};
+use theme::{SetStyle, Theme};
+use trigger::{TriggerAction, TriggerSet};
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, Write};
 use std::sync::mpsc::{self, Receiver, Sender};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 fn main() -> Result<()> {
     env_logger::init();
 
-    let result = run();
+    // --colors 16|256|truecolor overrides the detected colour depth
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut color_depth = None;
+    if args.first().is_some_and(|a| a == "--colors") {
+        let value = args.get(1).context("--colors needs 16, 256 or truecolor")?;
+        color_depth = Some(ColorDepth::parse(value)?);
+        args.drain(..2);
+    }
+
+    // `truetm <command>` controls the truetm it runs in instead of starting one
+    if !args.is_empty() {
+        println!("{}", ipc::send(&args)?);
+        return Ok(());
+    }
+
+    let result = run(color_depth.unwrap_or_else(ColorDepth::detect));
 
     // Cleanup - position cursor at bottom before leaving alternate screen to avoid blank line
     let _ = execute!(io::stdout(), DisableMouseCapture);
@@ -45,12 +76,42 @@ fn main() -> Result<()> {
 
 /// Pending commands that need a second keypress
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 enum PendingCommand {
     ViewTag,    // 'v' - waiting for tag number
     SetTag,     // 't' - waiting for tag number
     ToggleTag,  // 'T' - waiting for tag number
 }
 
+/// What a status-bar prompt asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptKind {
+    Watch, // 'w' - regex to watch for in the focused pane's output
+    Save,  // 'S' - file to save the focused pane's scrollback and screen to
+}
+
+/// Status-bar text prompt
+#[derive(Debug, Clone)]
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
+/// A notification in the history overlay
+struct NotificationEntry {
+    source: String,
+    text: String,
+    received: Instant,
+}
+
+/// Bell indication drawn in a window header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeaderAlert {
+    None,
+    Urgent, // Rang the bell while unfocused
+    Flash,  // Rang the bell a moment ago
+}
+
 /// Mouse selection state
 #[derive(Debug, Clone)]
 struct MouseSelection {
@@ -62,6 +123,17 @@ struct MouseSelection {
     buf_end_y: u16,
 }
 
+/// A floating pane being moved (dragged by its header) or resized (by its corner)
+#[derive(Debug, Clone, Copy)]
+struct FloatDrag {
+    pane_id: PaneId,
+    resize: bool,
+    // Where the drag started, and the pane's rect then
+    start_x: u16,
+    start_y: u16,
+    start_rect: Rect,
+}
+
 /// Per-tag state (focus and broadcast mode)
 #[derive(Debug, Clone, Default)]
 struct TagState {
@@ -96,20 +168,47 @@ struct App {
     tag_states: HashMap<u8, TagState>,
     // Copy mode - vim-style scrollback navigation and selection
     copy_mode: Option<CopyModeState>,
+    // Copy mode shows when each scrollback line scrolled in
+    show_timestamps: bool,
     // Zoom mode - focused pane is fullscreen
     zoomed_pane: Option<PaneId>,
     // Mouse selection
     mouse_selection: Option<MouseSelection>,
+    // Floating pane dragged with the mouse, and whether keys move/resize the focused one
+    float_drag: Option<FloatDrag>,
+    arranging_float: bool,
+    // Window focused before the scratchpad was shown, focused again when it is hidden
+    scratchpad_return: Option<PaneId>,
+    // Bell to pass on with the next frame, and when the last one was
+    bell_pending: bool,
+    bell_forwarded_at: Option<Instant>,
+    // Outer terminal cell size in pixels (for inline images)
+    cell_pixels: (u16, u16),
+    // Output triggers (global from config, per pane from Prefix + w)
+    triggers: TriggerSet,
+    // Highlight rules for the live view (global from config, more from `truetm highlight`)
+    highlights: HighlightSet,
+    // Transient status-bar message and when it was shown
+    status_message: Option<(String, Instant)>,
+    // Status-bar prompt being typed
+    prompt: Option<Prompt>,
+    // Notifications from panes (newest last) and whether the history overlay is open
+    notifications: VecDeque<NotificationEntry>,
+    show_notifications: bool,
+    // Title last sent to the outer terminal
+    outer_title: String,
+    // Control socket for `truetm <command>`
+    ipc: Option<ipc::Server>,
 }
 
 impl App {
-    fn new(width: u16, height: u16) -> Self {
+    fn new(width: u16, height: u16, color_depth: ColorDepth) -> Self {
         let (pty_tx, pty_rx) = mpsc::channel();
 
         // Collect environment variables
         let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
         let mut env_vars = vec![
-            ("TERM".to_string(), std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string())),
+            ("TERM".to_string(), terminfo::window_term()),
             ("COLORTERM".to_string(), "truecolor".to_string()),
         ];
 
@@ -121,11 +220,27 @@ impl App {
             }
         }
 
+        let theme = Theme::load(config::THEME).unwrap_or_else(|e| {
+            log::error!("Ignoring theme: {:#}", e);
+            Theme::default()
+        });
+
+        let ipc = match ipc::Server::start() {
+            Ok(server) => {
+                env_vars.push((ipc::SOCKET_ENV.to_string(), server.path().display().to_string()));
+                Some(server)
+            }
+            Err(e) => {
+                log::warn!("No control socket: {:#}", e);
+                None
+            }
+        };
+
         Self {
             panes: PaneManager::new(),
             buffers: HashMap::new(),
             layout: LayoutManager::new(),
-            compositor: Compositor::new(width, height - 1), // -1 for status bar
+            compositor: Compositor::new(width, height - 1, color_depth, theme), // -1 for status bar
             pty_tx,
             pty_rx,
             shell,
@@ -142,8 +257,23 @@ impl App {
             broadcast_mode: false,
             tag_states: HashMap::new(),
             copy_mode: None,
+            show_timestamps: false,
             zoomed_pane: None,
             mouse_selection: None,
+            float_drag: None,
+            arranging_float: false,
+            scratchpad_return: None,
+            bell_pending: false,
+            bell_forwarded_at: None,
+            cell_pixels: outer_cell_pixel_size().unwrap_or(config::DEFAULT_CELL_PIXELS),
+            triggers: TriggerSet::from_config(),
+            highlights: HighlightSet::from_config(),
+            status_message: None,
+            prompt: None,
+            notifications: VecDeque::new(),
+            show_notifications: false,
+            outer_title: String::new(),
+            ipc,
         }
     }
 
@@ -192,6 +322,12 @@ impl App {
         }
     }
 
+    /// Whether the current view has no windows (the scratchpad doesn't count)
+    fn view_is_empty(&self) -> bool {
+        let scratchpad = self.panes.scratchpad();
+        self.panes.visible_in_view(self.current_view).iter().all(|&id| Some(id) == scratchpad)
+    }
+
     /// Restore focus and broadcast state from a tag
     fn restore_tag_state(&mut self, tag: u8) {
         if let Some(state) = self.tag_states.get(&tag).cloned() {
@@ -212,11 +348,11 @@ impl App {
         let cwd = self.panes.focused()
             .and_then(|p| p.get_cwd())
             .or_else(|| std::env::current_dir().ok());
-        self.create_pane_with_tags(self.current_view, cwd)
+        self.create_pane_with_tags(self.current_view, cwd, &[])
     }
 
-    /// Create a new pane with specific tags and optional working directory
-    fn create_pane_with_tags(&mut self, tags: TagSet, cwd: Option<std::path::PathBuf>) -> Result<PaneId> {
+    /// Create a new pane with specific tags, optional working directory and arguments for the shell
+    fn create_pane_with_tags(&mut self, tags: TagSet, cwd: Option<std::path::PathBuf>, args: &[&str]) -> Result<PaneId> {
         let id = self.panes.next_id();
 
         // Initial rect - will be updated by layout
@@ -225,10 +361,13 @@ impl App {
 
         // Buffer height is rect.height - 1 to reserve header row
         let buffer_height = rect.height.saturating_sub(1);
-        let pane = Pane::new_with_size(id, rect, tags, &self.shell, &self.env_vars, cwd, self.pty_tx.clone(), rect.width, buffer_height)?;
+        let pane = Pane::new_with_size(id, rect, tags, &self.shell, args, &self.env_vars, cwd, self.pty_tx.clone(), rect.width, buffer_height)?;
         // add() inserts at front (master) and focuses
         self.panes.add(pane);
-        self.buffers.insert(id, ScreenBuffer::new(rect.width, buffer_height));
+        let mut buffer = ScreenBuffer::new(rect.width, buffer_height);
+        buffer.set_cell_pixel_size(self.cell_pixels.0, self.cell_pixels.1);
+        buffer.set_line_capture(self.triggers.applies_to(id));
+        self.buffers.insert(id, buffer);
 
         self.apply_layout()?;
         self.save_tag_state();
@@ -249,9 +388,11 @@ impl App {
 
             self.panes.remove(id);
             self.buffers.remove(&id);
+            self.triggers.remove_pane(id);
+            self.highlights.remove_pane(id);
 
             // If current tag is now empty, go to previous tag in history
-            let went_to_previous = if self.panes.visible_in_view(self.current_view).is_empty() {
+            let went_to_previous = if self.view_is_empty() {
                 self.go_to_previous_tag();
                 true
             } else {
@@ -307,21 +448,22 @@ impl App {
                 return self.apply_layout(); // Re-apply without zoom
             }
         } else {
-            // Normal layout
-            let positions = self.layout.arrange(&pane_ids, area);
+            // Normal layout for tiled panes, floating ones keep their place on screen
+            let positions = self.layout.arrange(&self.panes.tiled_in_view(self.current_view), area);
+            let floating = self.panes.floating_in_view(self.current_view);
 
             // Track which panes are in the layout
-            let layout_pane_ids: Vec<_> = positions.iter().map(|(id, _)| *id).collect();
+            let layout_pane_ids: Vec<_> = positions.iter().map(|(id, _)| *id).chain(floating.iter().copied()).collect();
 
             for (pane_id, rect) in positions {
-                // Buffer/PTY height is rect.height - 1 to reserve header row
-                let buffer_height = rect.height.saturating_sub(1);
-                if let Some(pane) = self.panes.get_mut(pane_id) {
-                    pane.set_rect_with_size(rect, rect.width, buffer_height)?;
-                }
-                if let Some(buffer) = self.buffers.get_mut(&pane_id) {
-                    buffer.resize(rect.width, buffer_height);
-                }
+                self.place_pane(pane_id, rect)?;
+            }
+            for pane_id in floating {
+                let Some(float_rect) = self.panes.get(pane_id).and_then(|p| p.float_rect) else {
+                    continue;
+                };
+                let rect = float_rect.clamped(area, FLOAT_MIN_WIDTH, FLOAT_MIN_HEIGHT);
+                self.place_pane(pane_id, rect)?;
             }
 
             // Hide panes not in layout (e.g., in monocle mode)
@@ -343,11 +485,107 @@ impl App {
         Ok(())
     }
 
+    /// Move a pane to a rect, resizing its PTY and buffer to the content area
+    fn place_pane(&mut self, pane_id: PaneId, rect: Rect) -> Result<()> {
+        let Some(pane) = self.panes.get_mut(pane_id) else {
+            return Ok(());
+        };
+        pane.rect = rect;
+        let content = pane.content_rect();
+        pane.resize(content.width, content.height)?;
+        if let Some(buffer) = self.buffers.get_mut(&pane_id) {
+            buffer.resize(content.width, content.height);
+        }
+        Ok(())
+    }
+
+    /// Float the focused pane in the middle of the screen, or tile it again
+    fn toggle_float(&mut self) -> Result<()> {
+        let Some(pane) = self.panes.focused() else {
+            return Ok(());
+        };
+        if pane.scratchpad {
+            self.show_message("The scratchpad always floats".to_string());
+            return Ok(());
+        }
+        let rect = match pane.float_rect {
+            Some(_) => None,
+            None => {
+                let area = Rect::new(0, 0, self.width, self.height.saturating_sub(1));
+                let (width, height) = config::FLOAT_SIZE;
+                Some(area.centered(area.width * width / 100, area.height * height / 100))
+            }
+        };
+        self.panes.set_floating(pane.id, rect);
+        self.apply_layout()?;
+        self.needs_redraw = true;
+        Ok(())
+    }
+
+    /// Show the scratchpad over the current view (starting it on first use), or hide it
+    fn toggle_scratchpad(&mut self) -> Result<()> {
+        if self.panes.scratchpad_shown() {
+            self.panes.show_scratchpad(false);
+            // Back to the window focused before it was shown
+            let visible = self.panes.visible_in_view(self.current_view);
+            if let Some(id) = self.scratchpad_return.take().filter(|id| visible.contains(id)) {
+                self.panes.focus_by_id(id);
+            }
+        } else {
+            self.scratchpad_return = self.panes.focused().map(|p| p.id);
+            let id = match self.panes.scratchpad() {
+                Some(id) => id,
+                None => {
+                    let cwd = std::env::var_os("HOME").map(std::path::PathBuf::from);
+                    let args = match config::SCRATCHPAD_COMMAND {
+                        "" => vec![],
+                        command => vec!["-c", command],
+                    };
+                    let id = self.create_pane_with_tags(TagSet::NONE, cwd, &args)?;
+                    let area = Rect::new(0, 0, self.width, self.height.saturating_sub(1));
+                    let (width, height) = config::SCRATCHPAD_SIZE;
+                    self.panes.make_scratchpad(id, area.centered(area.width * width / 100, area.height * height / 100));
+                    id
+                }
+            };
+            self.panes.show_scratchpad(true);
+            self.panes.focus_by_id(id);
+        }
+        self.apply_layout()?;
+        self.save_tag_state();
+        self.needs_redraw = true;
+        Ok(())
+    }
+
+    /// Move and resize the focused floating pane by a number of cells
+    fn adjust_float(&mut self, dx: i32, dy: i32, dw: i32, dh: i32) -> Result<()> {
+        let float_rect = self.panes.focused().and_then(|p| p.float_rect);
+        let (Some(rect), Some(pane)) = (float_rect, self.panes.focused_mut()) else {
+            // The floating pane is gone
+            self.arranging_float = false;
+            return Ok(());
+        };
+        let shift = |v: u16, d: i32| (v as i32 + d).clamp(0, u16::MAX as i32) as u16;
+        let area = Rect::new(0, 0, self.width, self.height.saturating_sub(1));
+        let moved = Rect::new(shift(rect.x, dx), shift(rect.y, dy), shift(rect.width, dw), shift(rect.height, dh));
+        pane.float_rect = Some(moved.clamped(area, FLOAT_MIN_WIDTH, FLOAT_MIN_HEIGHT));
+        self.apply_layout()?;
+        self.needs_redraw = true;
+        Ok(())
+    }
+
     /// Handle resize
     fn resize(&mut self, width: u16, height: u16) -> Result<()> {
         self.width = width;
         self.height = height;
         self.compositor.resize(width, height.saturating_sub(1)); // -1 for status bar
+        // Font size may have changed along with the window
+        if let Some((cell_w, cell_h)) = outer_cell_pixel_size() {
+            self.cell_pixels = (cell_w, cell_h);
+            for buffer in self.buffers.values_mut() {
+                buffer.set_cell_pixel_size(cell_w, cell_h);
+            }
+        }
         self.apply_layout()?;
         self.needs_redraw = true;
         Ok(())
@@ -362,7 +600,10 @@ impl App {
                 PtyMessage::Data { pane_id, data } => {
                     if let Some(buffer) = self.buffers.get_mut(&pane_id) {
                         buffer.process(&data);
-                        self.needs_redraw = true;
+                        // A pane in a synchronized update shows a frozen frame - nothing to redraw yet
+                        if !buffer.in_sync_update() {
+                            self.needs_redraw = true;
+                        }
                         had_data = true;
 
                         // Send any terminal responses back to the PTY
@@ -375,10 +616,28 @@ impl App {
                             }
                         }
                     }
+                    self.sync_cleared_scrollback(pane_id);
+                    self.note_output(pane_id);
+                    let lines = self.buffers.get_mut(&pane_id)
+                        .map(|b| b.drain_completed_lines())
+                        .unwrap_or_default();
+                    for line in lines {
+                        self.run_triggers(pane_id, &line);
+                    }
+                    let notifications = self.buffers.get_mut(&pane_id)
+                        .map(|b| b.drain_notifications())
+                        .unwrap_or_default();
+                    for notification in notifications {
+                        self.notify(pane_id, notification);
+                    }
+                    if self.buffers.get_mut(&pane_id).is_some_and(|b| b.take_bell()) {
+                        self.ring_bell(pane_id);
+                    }
                 }
                 PtyMessage::Exit { pane_id } => {
                     self.panes.mark_exited(pane_id);
                 }
+                PtyMessage::Message { text } => self.show_message(text),
             }
         }
 
@@ -388,11 +647,13 @@ impl App {
             let exited_ids: Vec<_> = self.panes.all().iter().filter(|p| p.exited).map(|p| p.id).collect();
             for id in exited_ids {
                 self.buffers.remove(&id);
+                self.triggers.remove_pane(id);
+                self.highlights.remove_pane(id);
             }
             self.panes.remove_exited();
 
             // If current tag is now empty, go to previous tag in history
-            let went_to_previous = if self.panes.visible_in_view(self.current_view).is_empty() {
+            let went_to_previous = if self.view_is_empty() {
                 self.go_to_previous_tag();
                 true
             } else {
@@ -413,11 +674,424 @@ impl App {
             self.needs_redraw = true;
         }
 
+        if had_data {
+            self.enforce_scrollback_budget();
+        }
         had_data
     }
 
+    /// Answer commands sent over the control socket
+    fn process_ipc_requests(&mut self) {
+        while let Some(request) = self.ipc.as_ref().and_then(|ipc| ipc.try_recv()) {
+            let result = match &request.command {
+                ipc::Command::ClearHistory { tag } => self.clear_history(tag.map(|t| t - 1)),
+                ipc::Command::Highlight { all, style, pattern } => self.highlight_scope(*all).and_then(|pane| {
+                    self.highlights.add(pane, pattern, style)?;
+                    self.needs_redraw = true;
+                    Ok(format!("Highlighting /{}/", pattern))
+                }),
+                ipc::Command::Unhighlight { all, pattern } => self.highlight_scope(*all).map(|pane| {
+                    let removed = self.highlights.remove(pane, pattern.as_deref());
+                    self.needs_redraw = true;
+                    format!("Removed {} highlight{}", removed, if removed == 1 { "" } else { "s" })
+                }),
+                ipc::Command::ListHighlights => {
+                    let rules = self.highlights.describe(self.panes.focused().map(|p| p.id));
+                    Ok(if rules.is_empty() { "No highlights".to_string() } else { rules.join("; ") })
+                }
+                ipc::Command::Theme { name: Some(name) } => Theme::load(name).map(|theme| {
+                    let message = format!("Theme {}", theme.name);
+                    self.compositor.set_theme(theme);
+                    self.needs_redraw = true;
+                    message
+                }),
+                ipc::Command::Theme { name: None } => {
+                    let names: Vec<&str> = theme::BUILTIN.iter().map(|(n, _)| *n).collect();
+                    Ok(format!("{} (built-in: {})", self.compositor.theme().name, names.join(", ")))
+                }
+            };
+            request.reply(result);
+        }
+    }
+
+    /// Pane a highlight command applies to: the focused one, or None for all panes
+    fn highlight_scope(&self, all: bool) -> Result<Option<PaneId>> {
+        if all {
+            return Ok(None);
+        }
+        self.panes.focused().map(|p| Some(p.id)).context("No focused window")
+    }
+
+    /// Clear the scrollback of the focused window, or of every window with `tag`
+    fn clear_history(&mut self, tag: Option<u8>) -> Result<String> {
+        let ids: Vec<PaneId> = match tag {
+            Some(tag) => self.panes.all().iter().filter(|p| p.tags.contains(tag)).map(|p| p.id).collect(),
+            None => self.panes.focused().map(|p| p.id).into_iter().collect(),
+        };
+        if ids.is_empty() {
+            bail!("No windows to clear");
+        }
+        for &id in &ids {
+            if let Some(buffer) = self.buffers.get_mut(&id) {
+                buffer.clear_scrollback();
+            }
+            self.sync_cleared_scrollback(id);
+        }
+        self.needs_redraw = true;
+        Ok(match ids.len() {
+            1 => "Cleared history of 1 window".to_string(),
+            n => format!("Cleared history of {} windows", n),
+        })
+    }
+
+    /// Keep copy mode within a scrollback that was just cleared
+    fn sync_cleared_scrollback(&mut self, pane_id: PaneId) {
+        let Some(buffer) = self.buffers.get_mut(&pane_id) else { return };
+        if !buffer.take_scrollback_cleared() {
+            return;
+        }
+        let focused = self.panes.focused().is_some_and(|p| p.id == pane_id);
+        if let (true, Some(copy_state)) = (focused, self.copy_mode.as_mut()) {
+            copy_state.set_scrollback_len(buffer.scrollback_len());
+        }
+        self.compositor.invalidate();
+        self.needs_redraw = true;
+    }
+
+    /// Keep the scrollback of all panes within config::SCROLLBACK_MEMORY_MB,
+    /// trimming the least recently active panes (the focused one last)
+    fn enforce_scrollback_budget(&mut self) {
+        let budget = config::SCROLLBACK_MEMORY_MB * 1024 * 1024;
+        let total: usize = self.buffers.values().map(|b| b.scrollback_bytes()).sum();
+        if total <= budget {
+            return;
+        }
+        let mut excess = total - budget;
+        // A pane being read in copy mode keeps its lines so its positions stay valid
+        let focused = self.panes.focused().map(|p| p.id);
+        let copy_pane = focused.filter(|_| self.copy_mode.is_some());
+        let mut panes: Vec<_> = self.panes.all().iter()
+            .filter(|p| Some(p.id) != copy_pane)
+            .map(|p| (Some(p.id) == focused, p.last_output, p.id))
+            .collect();
+        panes.sort_by_key(|&(is_focused, last_output, _)| (is_focused, last_output));
+        for (_, _, id) in panes {
+            if excess == 0 {
+                break;
+            }
+            if let Some(buffer) = self.buffers.get_mut(&id) {
+                excess = excess.saturating_sub(buffer.trim_scrollback(excess));
+            }
+        }
+    }
+
+    /// Show panes whose synchronized update (mode 2026) has been held too long
+    fn expire_sync_updates(&mut self) {
+        let timeout = Duration::from_millis(config::SYNC_UPDATE_TIMEOUT_MS);
+        for buffer in self.buffers.values_mut() {
+            if buffer.expire_sync_update(timeout) {
+                self.needs_redraw = true;
+            }
+        }
+    }
+
+    /// Flash the header of a pane that rang the bell, mark it urgent unless focused
+    /// and pass the bell on to the outer terminal
+    fn ring_bell(&mut self, pane_id: PaneId) {
+        self.mark_urgent(pane_id);
+        if let Some(pane) = self.panes.get_mut(pane_id) {
+            pane.bell_at = Some(Instant::now());
+        }
+        // Passed on with the next frame, at most once per interval
+        let interval = Duration::from_millis(config::BELL_FORWARD_INTERVAL_MS);
+        let due = match self.bell_forwarded_at {
+            Some(at) => at.elapsed() >= interval,
+            None => true,
+        };
+        if config::BELL_FORWARD && due {
+            self.bell_pending = true;
+            self.bell_forwarded_at = Some(Instant::now());
+        }
+        self.needs_redraw = true;
+    }
+
+    /// Mark a pane urgent unless it is focused and visible
+    fn mark_urgent(&mut self, pane_id: PaneId) {
+        let view = self.current_view;
+        let is_focused = self.panes.focused()
+            .is_some_and(|p| p.id == pane_id && p.tags.intersects(view));
+        if let Some(pane) = self.panes.get_mut(pane_id) {
+            if !is_focused {
+                pane.urgent = true;
+                self.needs_redraw = true;
+            }
+        }
+    }
+
+    /// Run the actions of the output triggers matching a completed line
+    fn run_triggers(&mut self, pane_id: PaneId, line: &str) {
+        let mut urgent = false;
+        let mut message = None;
+        for m in self.triggers.matches(pane_id, line) {
+            for action in m.actions {
+                match *action {
+                    TriggerAction::Urgent => urgent = true,
+                    TriggerAction::Message => message = Some(format!("/{}/ {}", m.pattern, line.trim())),
+                    TriggerAction::Command(command) if m.command_due() => {
+                        if let Err(e) = trigger::run_command(command, pane_id, m.pattern, line) {
+                            log::error!("{:#}", e);
+                        }
+                    }
+                    TriggerAction::Command(_) => {}
+                }
+            }
+        }
+        if urgent {
+            self.mark_urgent(pane_id);
+        }
+        if let Some(message) = message {
+            self.show_message(message);
+        }
+    }
+
+    /// Show a notification from a pane, record it in the history and optionally
+    /// pass it on to the outer terminal
+    fn notify(&mut self, pane_id: PaneId, notification: Notification) {
+        let title = notification.title.as_deref().map(strip_control_chars);
+        let body = strip_control_chars(&notification.body);
+        if config::NOTIFY_FORWARD {
+            let sequence = match &title {
+                Some(title) => format!("\x1b]777;notify;{};{}\x1b\\", title.replace(';', ","), body),
+                None => format!("\x1b]9;{}\x1b\\", body),
+            };
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(sequence.as_bytes());
+            let _ = stdout.flush();
+        }
+
+        let text = match title {
+            Some(title) if !body.is_empty() => format!("{}: {}", title, body),
+            Some(title) => title,
+            None => body,
+        };
+        let source = self.buffers.get(&pane_id)
+            .and_then(|b| b.title())
+            .map(strip_control_chars)
+            .unwrap_or_else(|| format!("pane {}", pane_id.0));
+        self.show_message(text.clone());
+        self.notifications.push_back(NotificationEntry { source, text, received: Instant::now() });
+        if self.notifications.len() > config::NOTIFICATION_HISTORY {
+            self.notifications.pop_front();
+        }
+    }
+
+    /// Collect completed lines only from panes that have triggers
+    fn update_line_capture(&mut self) {
+        for (&id, buffer) in self.buffers.iter_mut() {
+            buffer.set_line_capture(self.triggers.applies_to(id));
+        }
+    }
+
+    /// Show a transient message in the status bar
+    fn show_message(&mut self, message: String) {
+        self.status_message = Some((message, Instant::now()));
+        self.needs_redraw = true;
+    }
+
+    /// Hide the status-bar message once it has been shown long enough
+    fn expire_status_message(&mut self) {
+        let timeout = Duration::from_millis(config::STATUS_MESSAGE_MS);
+        if self.status_message.as_ref().is_some_and(|(_, shown)| shown.elapsed() >= timeout) {
+            self.status_message = None;
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Handle a key while a status-bar prompt is open
+    fn handle_prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                if let Some(prompt) = self.prompt.take() {
+                    self.submit_prompt(prompt);
+                }
+            }
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                prompt.input.push(c);
+            }
+            _ => {}
+        }
+        self.needs_redraw = true;
+    }
+
+    /// Act on a submitted status-bar prompt
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        let Some(pane_id) = self.panes.focused().map(|p| p.id) else {
+            return;
+        };
+        match prompt.kind {
+            PromptKind::Watch => {
+                if prompt.input.is_empty() {
+                    self.triggers.remove_pane(pane_id);
+                    self.show_message("Watches cleared".to_string());
+                } else {
+                    match self.triggers.add(Some(pane_id), &prompt.input, config::WATCH_ACTIONS) {
+                        Ok(()) => self.show_message(format!("Watching for /{}/", prompt.input)),
+                        Err(e) => self.show_message(format!("{:#}", e)),
+                    }
+                }
+                self.update_line_capture();
+            }
+            PromptKind::Save => {
+                if !prompt.input.is_empty() {
+                    let message = match self.save_pane(pane_id, &prompt.input) {
+                        Ok(message) => message,
+                        Err(e) => format!("{:#}", e),
+                    };
+                    self.show_message(message);
+                }
+            }
+        }
+    }
+
+    /// Write a pane's scrollback and screen to a new file, in the format its extension
+    /// asks for; relative paths are taken from the pane's working directory. The
+    /// file is written on another thread, which reports back when done.
+    fn save_pane(&self, pane_id: PaneId, name: &str) -> Result<String> {
+        let buffer = self.buffers.get(&pane_id).context("No such window")?;
+        let mut path = match (name.strip_prefix("~/"), std::env::var_os("HOME")) {
+            (Some(rest), Some(home)) => std::path::Path::new(&home).join(rest),
+            _ => std::path::PathBuf::from(name),
+        };
+        if path.is_relative() {
+            if let Some(cwd) = self.panes.get(pane_id).and_then(|p| p.get_cwd()) {
+                path = cwd.join(path);
+            }
+        }
+        let format = export::ExportFormat::from_path(&path);
+        let snapshot = export::Snapshot::new(buffer)?;
+        let mut file = match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => bail!("{} already exists", path.display()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", path.display())),
+        };
+        let tx = self.pty_tx.clone();
+        let display = path.display().to_string();
+        std::thread::spawn(move || {
+            let text = match snapshot.write(&mut file, format) {
+                Ok(lines) => format!("Saved {} lines to {}", lines, display),
+                Err(e) => format!("Failed to write {}: {}", display, e),
+            };
+            let _ = tx.send(PtyMessage::Message { text });
+        });
+        Ok(format!("Saving to {}", path.display()))
+    }
+
+    /// Record output from a pane for activity and silence monitoring
+    fn note_output(&mut self, pane_id: PaneId) {
+        let view = self.current_view;
+        if let Some(pane) = self.panes.get_mut(pane_id) {
+            pane.last_output = Instant::now();
+            let hidden = !pane.tags.intersects(view);
+            if (pane.monitor_activity && hidden && !pane.activity) || pane.silent {
+                pane.activity |= pane.monitor_activity && hidden;
+                pane.silent = false;
+                self.needs_redraw = true;
+            }
+        }
+    }
+
+    /// Raise silence alerts for watched panes that have been quiet too long
+    fn check_silence(&mut self) {
+        let limit = Duration::from_secs(config::MONITOR_SILENCE_SECS);
+        for pane in self.panes.all_mut() {
+            if pane.monitor_silence && !pane.silent && pane.last_output.elapsed() >= limit {
+                pane.silent = true;
+                self.needs_redraw = true;
+            }
+        }
+    }
+
+    /// Stop flashing window headers once the bell flash time has passed
+    fn expire_bell_flashes(&mut self) {
+        let flash = Duration::from_millis(config::BELL_FLASH_MS);
+        for pane in self.panes.all_mut() {
+            if pane.bell_at.is_some_and(|t| t.elapsed() >= flash) {
+                pane.bell_at = None;
+                self.needs_redraw = true;
+            }
+        }
+    }
+
+    /// Clear alerts the user has seen: activity in visible panes, urgency and
+    /// silence of the focused pane
+    fn clear_seen_alerts(&mut self) {
+        let view = self.current_view;
+        let focused_id = self.panes.focused().map(|p| p.id);
+        for pane in self.panes.all_mut() {
+            if !pane.tags.intersects(view) {
+                continue;
+            }
+            let is_focused = Some(pane.id) == focused_id;
+            if pane.activity || (is_focused && (pane.urgent || pane.silent)) {
+                pane.activity = false;
+                if is_focused {
+                    pane.urgent = false;
+                    if pane.silent {
+                        // Silence is acknowledged - wait another full period before alerting again
+                        pane.silent = false;
+                        pane.last_output = Instant::now();
+                    }
+                }
+                self.needs_redraw = true;
+            }
+        }
+    }
+
     /// Handle keyboard input
     fn handle_key(&mut self, key: KeyEvent) -> Result<()> {
+        // An open status-bar prompt takes all keys
+        if self.prompt.is_some() {
+            self.handle_prompt_key(key);
+            return Ok(());
+        }
+
+        // The notification history overlay closes on Esc, q or its own key
+        if self.show_notifications {
+            if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) || key.code == config::KEY_NOTIFICATIONS {
+                self.show_notifications = false;
+                self.compositor.invalidate();
+                self.needs_redraw = true;
+            }
+            return Ok(());
+        }
+
+        // Moving and resizing a floating pane takes all keys until Esc, Enter or q
+        if self.arranging_float {
+            match key.code {
+                KeyCode::Char('h') | KeyCode::Left => self.adjust_float(-1, 0, 0, 0)?,
+                KeyCode::Char('j') | KeyCode::Down => self.adjust_float(0, 1, 0, 0)?,
+                KeyCode::Char('k') | KeyCode::Up => self.adjust_float(0, -1, 0, 0)?,
+                KeyCode::Char('l') | KeyCode::Right => self.adjust_float(1, 0, 0, 0)?,
+                KeyCode::Char('H') => self.adjust_float(0, 0, -1, 0)?,
+                KeyCode::Char('J') => self.adjust_float(0, 0, 0, 1)?,
+                KeyCode::Char('K') => self.adjust_float(0, 0, 0, -1)?,
+                KeyCode::Char('L') => self.adjust_float(0, 0, 1, 0)?,
+                KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => {
+                    self.arranging_float = false;
+                    self.needs_redraw = true;
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
         // Check for prefix key
         if !self.prefix_mode
             && self.pending_command.is_none()
@@ -436,11 +1110,11 @@ impl App {
                 if let Some(num) = c.to_digit(10) {
                     match pending {
                         PendingCommand::ViewTag => {
-                            if num >= 1 && num <= 9 {
+                            if (1..=9).contains(&num) {
                                 let tag = (num - 1) as u8;
                                 self.switch_to_tag(tag);
                                 // Auto-create pane if tag is empty
-                                if self.panes.visible_in_view(self.current_view).is_empty() {
+                                if self.view_is_empty() {
                                     self.create_pane()?;
                                 }
                                 self.apply_layout()?;
@@ -455,9 +1129,9 @@ impl App {
                             }
                         }
                         PendingCommand::SetTag => {
-                            if num >= 1 && num <= 9 {
+                            if (1..=9).contains(&num) {
                                 let tag = (num - 1) as u8;
-                                if let Some(pane) = self.panes.focused_mut() {
+                                if let Some(pane) = self.panes.focused_mut().filter(|p| !p.scratchpad) {
                                     pane.tags = TagSet::single(tag);
                                 }
                                 self.apply_layout()?;
@@ -465,9 +1139,9 @@ impl App {
                             }
                         }
                         PendingCommand::ToggleTag => {
-                            if num >= 1 && num <= 9 {
+                            if (1..=9).contains(&num) {
                                 let tag = (num - 1) as u8;
-                                if let Some(pane) = self.panes.focused_mut() {
+                                if let Some(pane) = self.panes.focused_mut().filter(|p| !p.scratchpad) {
                                     pane.tags.toggle(tag);
                                     // Ensure pane has at least one tag
                                     if pane.tags.is_empty() {
@@ -490,7 +1164,7 @@ impl App {
             // Check for window focus (1-9 focuses visible window N)
             if let KeyCode::Char(c) = key.code {
                 if let Some(num) = c.to_digit(10) {
-                    if num >= 1 && num <= 9 {
+                    if (1..=9).contains(&num) {
                         // Focus visible window N (1-indexed)
                         let visible = self.panes.visible_in_view(self.current_view);
                         let idx = (num - 1) as usize;
@@ -613,6 +1287,52 @@ impl App {
                         }
                     }
                 }
+                k if k == config::KEY_MONITOR_ACTIVITY => {
+                    if let Some(pane) = self.panes.focused_mut() {
+                        pane.monitor_activity = !pane.monitor_activity;
+                        pane.activity = false;
+                        self.needs_redraw = true;
+                    }
+                }
+                k if k == config::KEY_MONITOR_SILENCE => {
+                    if let Some(pane) = self.panes.focused_mut() {
+                        pane.monitor_silence = !pane.monitor_silence;
+                        pane.silent = false;
+                        pane.last_output = Instant::now();
+                        self.needs_redraw = true;
+                    }
+                }
+                k if k == config::KEY_WATCH => {
+                    self.prompt = Some(Prompt { kind: PromptKind::Watch, input: String::new() });
+                    self.needs_redraw = true;
+                }
+                k if k == config::KEY_SAVE => {
+                    self.prompt = Some(Prompt { kind: PromptKind::Save, input: String::new() });
+                    self.needs_redraw = true;
+                }
+                k if k == config::KEY_CLEAR_HISTORY => {
+                    if let Err(e) = self.clear_history(None) {
+                        self.show_message(format!("{:#}", e));
+                    }
+                }
+                k if k == config::KEY_NOTIFICATIONS => {
+                    self.show_notifications = true;
+                    self.needs_redraw = true;
+                }
+                k if k == config::KEY_SCRATCHPAD => {
+                    self.toggle_scratchpad()?;
+                }
+                k if k == config::KEY_TOGGLE_FLOAT => {
+                    self.toggle_float()?;
+                }
+                k if k == config::KEY_ARRANGE_FLOAT => {
+                    if self.panes.focused().is_some_and(|p| p.float_rect.is_some()) {
+                        self.arranging_float = true;
+                        self.needs_redraw = true;
+                    } else {
+                        self.show_message("Not a floating window".to_string());
+                    }
+                }
                 k if k == config::KEY_ZOOM => {
                     if let Some(focused) = self.panes.focused() {
                         let focused_id = focused.id;
@@ -672,13 +1392,17 @@ impl App {
                         _ => {}
                     }
                 }
-                // Execute search on Enter
+                // Execute search (or go to the typed time) on Enter
                 if key.code == KeyCode::Enter {
                     if let Some(pane) = self.panes.focused() {
                         if let Some(buffer) = self.buffers.get(&pane.id) {
                             let get_line = |y: i32| get_line_content_static(buffer, y);
                             if let Some(ref mut copy_state) = self.copy_mode {
-                                copy_state.execute_search(get_line);
+                                if copy_state.search_mode == copy_mode::SearchMode::Time {
+                                    copy_state.execute_time_input(|y| buffer.line_time(y));
+                                } else {
+                                    copy_state.execute_search(get_line);
+                                }
                             }
                         }
                     }
@@ -720,8 +1444,17 @@ impl App {
             // Handle pending text object (i/a waiting for object type like w, ", (, etc.)
             if pending_text_object {
                 if let KeyCode::Char(c) = key.code {
-                    // Valid text object types: w, W, ", ', `, (, ), b, [, ], {, }, B, <, >
+                    // Valid text object types: w, W, ", ', `, (, ), b, [, ], {, }, B, <, >, o
                     match c {
+                        'o' => {
+                            // Command output, from shell integration marks
+                            if let Some(buffer) = self.panes.focused().and_then(|p| self.buffers.get(&p.id)) {
+                                if let Some(ref mut cs) = self.copy_mode {
+                                    cs.select_command_object(|y| buffer.line_marks(y));
+                                    cs.reset_count();
+                                }
+                            }
+                        }
                         'w' | 'W' | '"' | '\'' | '`' | '(' | ')' | 'b' | '[' | ']' | '{' | '}' | 'B' | '<' | '>' => {
                             if let Some(pane) = self.panes.focused() {
                                 if let Some(buffer) = self.buffers.get(&pane.id) {
@@ -847,6 +1580,19 @@ impl App {
                         copy_state.reset_count();
                     }
 
+                    // Shell prompt navigation: [, ]
+                    KeyCode::Char('[') | KeyCode::Char(']') => {
+                        let forward = key.code == KeyCode::Char(']');
+                        if let Some(buffer) = self.panes.focused().and_then(|p| self.buffers.get(&p.id)) {
+                            for _ in 0..count {
+                                if !copy_state.move_to_prompt(forward, |y| buffer.line_marks(y)) {
+                                    break;
+                                }
+                            }
+                        }
+                        copy_state.reset_count();
+                    }
+
                     // Screen navigation: H, M, L
                     KeyCode::Char('H') => {
                         copy_state.move_to_screen_top();
@@ -901,6 +1647,16 @@ impl App {
                         copy_state.start_search(false);
                     }
 
+                    // Go to a time: @HH:MM[:SS]
+                    KeyCode::Char('@') => {
+                        copy_state.start_time_input();
+                    }
+
+                    // Timestamp gutter
+                    KeyCode::Char('S') => {
+                        self.show_timestamps = !self.show_timestamps;
+                    }
+
                     // Search next/prev: n, N
                     KeyCode::Char('n') => {
                         do_search_next = true;
@@ -1038,33 +1794,70 @@ impl App {
             return Ok(());
         }
 
-        // Forward input to pane(s)
-        let bytes = key_event_to_bytes(&key);
+        // Forward input to pane(s), encoded for the keyboard protocol each one requested
         if self.broadcast_mode {
             // Send to all visible panes
             let visible_ids = self.panes.visible_in_view(self.current_view);
             for id in visible_ids {
+                let bytes = key_event_to_bytes(&key, self.keyboard_flags(id));
                 if let Some(pane) = self.panes.get_mut(id) {
                     pane.write(&bytes)?;
                 }
             }
         } else {
             // Send to focused pane only
-            if let Some(pane) = self.panes.focused_mut() {
-                pane.write(&bytes)?;
+            if let Some(id) = self.panes.focused().map(|p| p.id) {
+                let bytes = key_event_to_bytes(&key, self.keyboard_flags(id));
+                if let Some(pane) = self.panes.get_mut(id) {
+                    pane.write(&bytes)?;
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Kitty keyboard protocol flags a pane's application has pushed
+    fn keyboard_flags(&self, pane_id: PaneId) -> KeyboardFlags {
+        self.buffers.get(&pane_id).map(|b| b.keyboard_flags()).unwrap_or_default()
+    }
+
     /// Handle mouse input
     fn handle_mouse(&mut self, mouse: MouseEvent) -> Result<()> {
         let x = mouse.column;
         let y = mouse.row;
 
         match mouse.kind {
+            MouseEventKind::Drag(MouseButton::Left) if self.float_drag.is_some() => {
+                if let Some(drag) = self.float_drag {
+                    let (dx, dy) = (x as i32 - drag.start_x as i32, y as i32 - drag.start_y as i32);
+                    let shift = |v: u16, d: i32| (v as i32 + d).max(0) as u16;
+                    let start = drag.start_rect;
+                    let rect = if drag.resize {
+                        Rect::new(start.x, start.y, shift(start.width, dx), shift(start.height, dy))
+                    } else {
+                        Rect::new(shift(start.x, dx), shift(start.y, dy), start.width, start.height)
+                    };
+                    let area = Rect::new(0, 0, self.width, self.height.saturating_sub(1));
+                    if let Some(pane) = self.panes.get_mut(drag.pane_id) {
+                        pane.float_rect = Some(rect.clamped(area, FLOAT_MIN_WIDTH, FLOAT_MIN_HEIGHT));
+                    }
+                    self.apply_layout()?;
+                    self.needs_redraw = true;
+                }
+            }
+            MouseEventKind::Up(MouseButton::Left) if self.float_drag.is_some() => {
+                self.float_drag = None;
+            }
             MouseEventKind::Down(MouseButton::Left) => {
+                // A floating pane's header moves it and its bottom right corner resizes it
+                if let Some(drag) = self.float_handle_at(x, y) {
+                    self.float_drag = Some(drag);
+                    self.panes.focus_by_id(drag.pane_id);
+                    self.save_tag_state();
+                    self.needs_redraw = true;
+                    return Ok(());
+                }
                 // Find which pane was clicked
                 if let Some((pane_id, buf_x, buf_y)) = self.pane_at_position(x, y) {
                     // Clear old selection by invalidating compositor
@@ -1153,22 +1946,26 @@ impl App {
 
     /// Find which pane contains a screen position, return pane ID and buffer coordinates
     fn pane_at_position(&self, x: u16, y: u16) -> Option<(PaneId, u16, u16)> {
-        let visible_ids = self.panes.visible_in_view(self.current_view);
-        for &pane_id in &visible_ids {
-            if let Some(pane) = self.panes.get(pane_id) {
-                // Check if position is within pane's content area (excluding header)
-                let content_y_start = pane.rect.y + 1; // Skip header row
-                let content_y_end = pane.rect.y + pane.rect.height;
-                if x >= pane.rect.x && x < pane.rect.x + pane.rect.width
-                    && y >= content_y_start && y < content_y_end
-                {
-                    let buf_x = x - pane.rect.x;
-                    let buf_y = y - content_y_start;
-                    return Some((pane_id, buf_x, buf_y));
-                }
-            }
-        }
-        None
+        // Floating panes first, topmost first
+        let floating = self.panes.floating_in_view(self.current_view);
+        let tiled = self.panes.tiled_in_view(self.current_view);
+        let pane = floating.iter().rev().chain(&tiled)
+            .filter_map(|&id| self.panes.get(id))
+            .find(|p| p.rect.contains(x, y))?;
+        // Only the content area (excluding header and border)
+        let content = pane.content_rect();
+        content.contains(x, y).then(|| (pane.id, x - content.x, y - content.y))
+    }
+
+    /// The floating pane whose header (move) or bottom right corner (resize) is at a position
+    fn float_handle_at(&self, x: u16, y: u16) -> Option<FloatDrag> {
+        let floating = self.panes.floating_in_view(self.current_view);
+        let pane = floating.iter().rev()
+            .filter_map(|&id| self.panes.get(id))
+            .find(|p| p.rect.contains(x, y))?;
+        let rect = pane.rect;
+        let corner = x == rect.x + rect.width - 1 && y == rect.y + rect.height - 1;
+        (y == rect.y || corner).then_some(FloatDrag { pane_id: pane.id, resize: corner, start_x: x, start_y: y, start_rect: rect })
     }
 
     /// Extract selected text from a pane's buffer
@@ -1322,6 +2119,9 @@ impl App {
 
         let mut stdout = io::stdout();
 
+        // Draw the whole frame as one synchronized update to avoid tearing
+        self.compositor.begin_frame(&mut stdout)?;
+
         // Hide cursor during rendering to avoid ghost cursor
         queue!(stdout, Hide)?;
 
@@ -1329,23 +2129,46 @@ impl App {
         let visible_ids = self.panes.visible_in_view(self.current_view);
         let focused_id = self.panes.focused().map(|p| p.id);
 
+        // Tiled panes first, then the floating ones from the bottom up
+        let floating = self.panes.floating_in_view(self.current_view);
+        let float_rects: Vec<Rect> = floating.iter().filter_map(|&id| self.panes.get(id)).map(|p| p.rect).collect();
+        let mut draw_order = self.panes.tiled_in_view(self.current_view);
+        draw_order.extend(&floating);
+        // Headers, borders and gutters are drawn outside the compositor's diff
+        let mut drawn_directly: Vec<Rect> = Vec::new();
+
         // Render only visible panes with window numbers
-        for (win_num, &pane_id) in visible_ids.iter().enumerate() {
+        for &pane_id in &draw_order {
+            let win_num = visible_ids.iter().position(|&id| id == pane_id).unwrap_or(0);
             if let Some(pane) = self.panes.get(pane_id) {
                 // Skip panes with zero-size rect (hidden in monocle mode)
                 if pane.rect.width == 0 || pane.rect.height == 0 {
                     continue;
                 }
+                // Panes are not drawn under the floating panes above them, and a floating
+                // pane is drawn again where the panes below drew outside the diff
+                match floating.iter().position(|&id| id == pane_id) {
+                    Some(level) => {
+                        for area in drawn_directly.iter().filter_map(|r| r.intersection(pane.rect)) {
+                            self.compositor.invalidate_rect(area);
+                        }
+                        self.compositor.set_covered(float_rects[level + 1..].to_vec());
+                    }
+                    None => self.compositor.set_covered(float_rects.clone()),
+                }
                 if let Some(buffer) = self.buffers.get(&pane.id) {
                     // In broadcast mode, all panes are "active"
                     let is_focused = self.broadcast_mode || Some(pane.id) == focused_id;
-                    // Content rect starts at y+1 to leave room for header
-                    let content_rect = Rect::new(
-                        pane.rect.x,
-                        pane.rect.y + 1,
-                        pane.rect.width,
-                        pane.rect.height.saturating_sub(1),
-                    );
+                    let mut content_rect = pane.content_rect();
+                    // The copy mode timestamp gutter pushes the content right
+                    let gutter = self.timestamp_gutter_width(pane);
+                    if gutter > 0 {
+                        let scroll_offset = self.copy_mode.as_ref().map_or(0, |c| c.scroll_offset);
+                        self.draw_timestamp_gutter(&mut stdout, buffer, content_rect, scroll_offset)?;
+                        drawn_directly.push(Rect::new(content_rect.x, content_rect.y, gutter, content_rect.height));
+                        content_rect.x += gutter;
+                        content_rect.width -= gutter;
+                    }
                     // Only apply scroll offset to focused pane in copy mode
                     let offset = if is_focused {
                         self.copy_mode.as_ref().map(|c| c.scroll_offset).unwrap_or(0)
@@ -1393,7 +2216,8 @@ impl App {
                     } else {
                         Vec::new()
                     };
-                    self.compositor.render_pane(&mut stdout, buffer, content_rect, is_focused, offset, selection, &search_matches)?;
+                    let highlights = self.highlights.applies_to(pane.id).then_some((&mut self.highlights, pane.id));
+                    self.compositor.render_pane(&mut stdout, buffer, content_rect, is_focused, offset, selection, &search_matches, highlights)?;
                     // Draw window header with number and title
                     // Show copy mode indicator in header of focused pane
                     let mode_indicator = if is_focused {
@@ -1407,59 +2231,117 @@ impl App {
                     } else {
                         None
                     };
-                    self.draw_window_header(&mut stdout, pane.rect, win_num + 1, buffer.title(), is_focused, mode_indicator)?;
+                    let alert = if pane.bell_at.is_some() {
+                        HeaderAlert::Flash
+                    } else if pane.urgent {
+                        HeaderAlert::Urgent
+                    } else {
+                        HeaderAlert::None
+                    };
+                    // Window number, with markers for the monitors that aren't in their default state
+                    let mut label = if pane.scratchpad { "scratchpad".to_string() } else { (win_num + 1).to_string() };
+                    if pane.monitor_activity != config::MONITOR_ACTIVITY {
+                        label.push(if pane.monitor_activity { '#' } else { '-' });
+                    }
+                    if pane.monitor_silence {
+                        label.push('~');
+                    }
+                    self.draw_window_header(&mut stdout, pane.rect, &label, buffer.last_exit_code(), buffer.title(), is_focused, mode_indicator, alert)?;
+                    drawn_directly.push(Rect::new(pane.rect.x, pane.rect.y, pane.rect.width, 1));
+                    if pane.float_rect.is_some() {
+                        self.draw_float_border(&mut stdout, pane.rect, self.header_style(is_focused, mode_indicator.is_some(), alert))?;
+                        let Rect { x, y, width, height } = pane.rect;
+                        drawn_directly.extend([
+                            Rect::new(x, y, 1, height),
+                            Rect::new(x + width - 1, y, 1, height),
+                            Rect::new(x, y + height - 1, width, 1),
+                        ]);
+                    }
                 }
             }
         }
+        self.compositor.set_covered(Vec::new());
 
-        // Draw separators between visible panes
+        // Draw separators between tiled panes (except under floating ones)
         let content_height = self.height.saturating_sub(1);
-        if visible_ids.len() > 1 && self.layout.current_name() == "[]=" {
+        let tiled_ids = self.panes.tiled_in_view(self.current_view);
+        if tiled_ids.len() > 1 && self.layout.current_name() == "[]=" && self.zoomed_pane.is_none() {
             // Find the master pane (first visible)
-            if let Some(&master_id) = visible_ids.first() {
+            if let Some(&master_id) = tiled_ids.first() {
                 if let Some(master) = self.panes.get(master_id) {
                     let sep_x = master.rect.x + master.rect.width;
                     if sep_x < self.width {
-                        queue!(stdout, ResetColor)?;
-                        for y in 0..content_height {
+                        let style = self.compositor.theme().separator;
+                        queue!(stdout, ResetColor, SetStyle(style, self.compositor.color_depth()))?;
+                        for y in (0..content_height).filter(|&y| !float_rects.iter().any(|r| r.contains(sep_x, y))) {
                             queue!(stdout, MoveTo(sep_x, y))?;
                             write!(stdout, "│")?;
                         }
+                        queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
                     }
                 }
             }
         }
 
+        if self.show_notifications {
+            self.draw_notification_overlay(&mut stdout)?;
+        }
+
         // Render status bar at bottom
         self.render_status_bar(&mut stdout)?;
 
-        // Position cursor in focused pane (if visible and has size)
-        if let Some(pane) = self.panes.focused() {
-            if visible_ids.contains(&pane.id) && pane.rect.width > 0 && pane.rect.height > 0 {
+        // Position cursor in focused pane (if visible and has size, and no overlay covers it)
+        if let Some(pane) = self.panes.focused().filter(|_| !self.show_notifications) {
+            let content = pane.content_rect();
+            // Floating panes above the focused one hide its cursor
+            let level = floating.iter().position(|&id| id == pane.id);
+            let above = &float_rects[level.map_or(0, |l| l + 1)..];
+            let covered = |x: u16, y: u16| above.iter().any(|r| r.contains(x, y));
+            if visible_ids.contains(&pane.id) && content.width > 0 && content.height > 0 {
                 if let Some(ref copy_state) = self.copy_mode {
                     // In copy mode: show copy mode cursor
                     if let Some((cx, cy)) = copy_state.cursor_screen_pos() {
-                        // +1 to account for header row
-                        queue!(stdout, MoveTo(pane.rect.x + cx, pane.rect.y + 1 + cy))?;
-                        queue!(stdout, Show)?;
+                        let gutter = self.timestamp_gutter_width(pane);
+                        let (x, y) = (content.x + gutter + cx, content.y + cy);
+                        queue!(stdout, MoveTo(x, y))?;
+                        if !covered(x, y) {
+                            queue!(stdout, Show)?;
+                        }
                     }
                 } else if let Some(buffer) = self.buffers.get(&pane.id) {
                     // Normal mode: show buffer cursor
                     let (cx, cy) = buffer.cursor();
                     // Clamp cursor to content area bounds
-                    let content_height = pane.rect.height.saturating_sub(1);
-                    let clamped_cx = cx.min(pane.rect.width.saturating_sub(1));
-                    let clamped_cy = cy.min(content_height.saturating_sub(1));
-                    // +1 to account for header row
-                    queue!(stdout, MoveTo(pane.rect.x + clamped_cx, pane.rect.y + 1 + clamped_cy))?;
+                    let x = content.x + cx.min(content.width - 1);
+                    let y = content.y + cy.min(content.height - 1);
+                    queue!(stdout, MoveTo(x, y))?;
                     // Only show cursor if the application wants it visible
-                    if buffer.cursor_visible() {
+                    if buffer.cursor_visible() && !covered(x, y) {
                         queue!(stdout, Show)?;
                     }
                 }
             }
         }
 
+        // Outer terminal title follows the view and the focused window
+        if !config::OUTER_TITLE.is_empty() {
+            let tags: Vec<String> = self.current_view.iter().map(|t| (t + 1).to_string()).collect();
+            let pane_title = self.panes.focused()
+                .and_then(|p| self.buffers.get(&p.id))
+                .and_then(|b| b.title())
+                .unwrap_or("");
+            let title = expand_title_template(config::OUTER_TITLE, &tags.join(","), pane_title);
+            if title != self.outer_title {
+                queue!(stdout, SetTitle(&title))?;
+                self.outer_title = title;
+            }
+        }
+
+        if std::mem::take(&mut self.bell_pending) {
+            stdout.write_all(b"\x07")?;
+        }
+
+        self.compositor.end_frame(&mut stdout)?;
         stdout.flush()?;
         self.needs_redraw = false;
 
@@ -1468,7 +2350,8 @@ impl App {
 
     /// Render the status bar showing tags
     fn render_status_bar(&self, stdout: &mut impl Write) -> Result<()> {
-        use crossterm::style::{Attribute, SetAttribute, SetForegroundColor, Color};
+        let depth = self.compositor.color_depth();
+        let theme = self.compositor.theme();
 
         let status_y = self.height.saturating_sub(1);
         queue!(stdout, MoveTo(0, status_y), ResetColor)?;
@@ -1479,6 +2362,8 @@ impl App {
 
         // Initial padding
         write!(stdout, " ")?;
+        // Columns used so far (to fit the message at the end)
+        let mut used = 1;
 
         // Render only tags that have panes or are currently viewed
         for tag in 0..self.tag_count {
@@ -1494,40 +2379,61 @@ impl App {
                 .map(|p| p.tags.contains(tag))
                 .unwrap_or(false);
 
-            // Style based on state - muted green theme
-            if is_viewed && is_focused_tag {
-                // Viewed and focused pane has this tag - brighter green bold
-                queue!(stdout, SetForegroundColor(Color::Rgb { r: 120, g: 190, b: 120 }), SetAttribute(Attribute::Bold))?;
+            // Style based on state, urgent tags stand out
+            let style = if self.panes.any_in_tag(tag, |p| p.urgent) {
+                theme.tag_urgent
+            } else if is_viewed && is_focused_tag {
+                // Viewed and focused pane has this tag
+                theme.tag_focused
             } else if is_viewed {
-                // Currently viewing this tag - muted green
-                queue!(stdout, SetForegroundColor(Color::Rgb { r: 80, g: 150, b: 80 }))?;
+                theme.tag_viewed
             } else {
-                // Has panes but not viewing - dim green
-                queue!(stdout, SetForegroundColor(Color::Rgb { r: 60, g: 100, b: 60 }))?;
-            }
+                // Has panes but not viewing
+                theme.tag_occupied
+            };
+            queue!(stdout, SetStyle(style, depth))?;
 
             write!(stdout, "{}", tag + 1)?;
             queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
-            write!(stdout, "  ")?;
+
+            // Monitor alerts: # = activity in a hidden pane, ~ = a watched pane went silent
+            if self.panes.any_in_tag(tag, |p| p.silent) {
+                queue!(stdout, SetStyle(theme.silence, depth))?;
+                write!(stdout, "~")?;
+            } else if self.panes.any_in_tag(tag, |p| p.activity) {
+                queue!(stdout, SetStyle(theme.activity, depth))?;
+                write!(stdout, "#")?;
+            } else {
+                write!(stdout, " ")?;
+            }
+            queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
+            write!(stdout, " ")?;
+            used += 3;
         }
 
         // Show layout name
-        queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
+        queue!(stdout, SetStyle(theme.muted, depth))?;
         write!(stdout, "{}", self.layout.current_name())?;
+        used += self.layout.current_name().chars().count();
 
         // Show zoom indicator
         if self.zoomed_pane.is_some() {
-            queue!(stdout, SetForegroundColor(Color::Magenta), SetAttribute(Attribute::Bold))?;
+            queue!(stdout, ResetColor, SetAttribute(Attribute::Reset), SetStyle(theme.zoom, depth))?;
             write!(stdout, " [Z]")?;
+            used += 4;
         }
 
         // Show copy mode prompts in status bar (mode indicator is in window header)
         if let Some(ref copy_state) = self.copy_mode {
-            queue!(stdout, SetForegroundColor(Color::Yellow), SetAttribute(Attribute::Bold))?;
+            queue!(stdout, ResetColor, SetAttribute(Attribute::Reset), SetStyle(theme.copy_mode, depth))?;
 
             // Check for search input mode
             if copy_state.search_mode != copy_mode::SearchMode::None {
-                let prompt = if copy_state.search_mode == copy_mode::SearchMode::Forward { "/" } else { "?" };
+                let prompt = match copy_state.search_mode {
+                    copy_mode::SearchMode::Forward => "/",
+                    copy_mode::SearchMode::Time => "@",
+                    _ => "?",
+                };
                 write!(stdout, " {}{}", prompt, copy_state.search_input)?;
             } else if copy_state.pending_find.is_some() {
                 write!(stdout, " find:")?;
@@ -1543,29 +2449,143 @@ impl App {
             }
         }
 
+        // Status-bar prompt, or the latest message
+        if let Some(ref prompt) = self.prompt {
+            let label = match prompt.kind {
+                PromptKind::Watch => "watch",
+                PromptKind::Save => "save to",
+            };
+            queue!(stdout, ResetColor, SetAttribute(Attribute::Reset), SetStyle(theme.prompt, depth))?;
+            write!(stdout, " {}: {}", label, prompt.input)?;
+        } else if self.arranging_float {
+            queue!(stdout, ResetColor, SetAttribute(Attribute::Reset), SetStyle(theme.prompt, depth))?;
+            write!(stdout, " float: hjkl move, HJKL resize, Esc done")?;
+        } else if let (Some((message, _)), None) = (&self.status_message, &self.copy_mode) {
+            let room = (self.width as usize).saturating_sub(used + 1);
+            queue!(stdout, ResetColor, SetAttribute(Attribute::Reset), SetStyle(theme.message, depth))?;
+            write!(stdout, " {}", truncate_to_width(message, room).0)?;
+        }
+
         queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
 
         Ok(())
     }
 
-    /// Draw window header with number, title, and line
-    fn draw_window_header(&self, stdout: &mut impl Write, rect: Rect, num: usize, title: Option<&str>, is_focused: bool, mode_indicator: Option<&str>) -> Result<()> {
-        use crossterm::style::{SetForegroundColor, Color, Attribute, SetAttribute};
+    /// Width of the timestamp gutter shown left of a pane (only the focused one, in copy mode)
+    fn timestamp_gutter_width(&self, pane: &Pane) -> u16 {
+        let focused = self.panes.focused().is_some_and(|p| p.id == pane.id);
+        let room = pane.rect.width > copy_mode::TIMESTAMP_GUTTER_WIDTH * 2;
+        if self.show_timestamps && self.copy_mode.is_some() && focused && room {
+            copy_mode::TIMESTAMP_GUTTER_WIDTH
+        } else {
+            0
+        }
+    }
 
-        queue!(stdout, MoveTo(rect.x, rect.y))?;
+    /// Draw when each visible line scrolled into the scrollback (blank for screen lines)
+    fn draw_timestamp_gutter(&self, stdout: &mut impl Write, buffer: &ScreenBuffer, rect: Rect, scroll_offset: usize) -> Result<()> {
+        let depth = self.compositor.color_depth();
+
+        queue!(stdout, SetStyle(self.compositor.theme().muted, depth))?;
+        for row in 0..rect.height.min(buffer.height()) {
+            let y = row as i32 - scroll_offset as i32;
+            let time = buffer.line_time(y).map(copy_mode::format_time_of_day).unwrap_or_default();
+            queue!(stdout, MoveTo(rect.x, rect.y + row))?;
+            write!(stdout, "{:<width$}", time, width = copy_mode::TIMESTAMP_GUTTER_WIDTH as usize)?;
+        }
+        queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    /// Draw the notification history (newest first) in a box over the panes
+    fn draw_notification_overlay(&self, stdout: &mut impl Write) -> Result<()> {
+        let depth = self.compositor.color_depth();
+
+        let content_height = self.height.saturating_sub(1);
+        if self.width < 10 || content_height < 4 {
+            return Ok(());
+        }
+        let (x, y) = (2, 1);
+        let width = self.width - 4;
+        let height = content_height - 2;
+        let inner = width as usize - 2;
+
+        let border = SetStyle(self.compositor.theme().prompt, depth);
+        queue!(stdout, ResetColor, border)?;
+        let title = " Notifications ";
+        queue!(stdout, MoveTo(x, y))?;
+        write!(stdout, "┌{}{}┐", title, "─".repeat(inner.saturating_sub(title.len())))?;
+
+        let mut lines: Vec<String> = self.notifications.iter().rev()
+            .map(|n| format!("{:>4} {}  {}", format_age(n.received.elapsed()), n.source, n.text))
+            .collect();
+        if lines.is_empty() {
+            lines.push("No notifications".to_string());
+        }
+        for row in 1..height - 1 {
+            let line = lines.get(row as usize - 1).map(String::as_str).unwrap_or("");
+            let (text, text_width) = truncate_to_width(line, inner);
+            queue!(stdout, MoveTo(x, y + row), border)?;
+            write!(stdout, "│")?;
+            queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
+            write!(stdout, "{}{:pad$}", text, "", pad = inner - text_width)?;
+            queue!(stdout, border)?;
+            write!(stdout, "│")?;
+        }
+        queue!(stdout, MoveTo(x, y + height - 1))?;
+        write!(stdout, "└{}┘", "─".repeat(inner))?;
+        queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
 
-        // Use yellow for entire header when in copy mode, otherwise green/grey
-        let in_copy_mode = mode_indicator.is_some();
+        Ok(())
+    }
+
+    /// Style of a window's header (and border, when floating)
+    fn header_style(&self, is_focused: bool, in_copy_mode: bool, alert: HeaderAlert) -> HighlightStyle {
+        // The whole header takes the copy mode style in copy mode
+        let theme = self.compositor.theme();
         if in_copy_mode {
-            queue!(stdout, SetForegroundColor(Color::Yellow), SetAttribute(Attribute::Bold))?;
+            theme.copy_mode
+        } else if alert == HeaderAlert::Urgent {
+            theme.urgent_header
         } else if is_focused {
-            queue!(stdout, SetForegroundColor(Color::Rgb { r: 120, g: 190, b: 120 }), SetAttribute(Attribute::Bold))?;
+            theme.focused_header
         } else {
-            queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
+            theme.inactive_header
+        }
+    }
+
+    /// Draw the sides and bottom of a floating window's border (the header is its top)
+    fn draw_float_border(&self, stdout: &mut impl Write, rect: Rect, style: HighlightStyle) -> Result<()> {
+        let (right, bottom) = (rect.x + rect.width - 1, rect.y + rect.height - 1);
+        queue!(stdout, ResetColor, SetStyle(style, self.compositor.color_depth()))?;
+        for y in rect.y + 1..bottom {
+            queue!(stdout, MoveTo(rect.x, y))?;
+            write!(stdout, "│")?;
+            queue!(stdout, MoveTo(right, y))?;
+            write!(stdout, "│")?;
+        }
+        queue!(stdout, MoveTo(rect.x, bottom))?;
+        write!(stdout, "└{}┘", "─".repeat(rect.width.saturating_sub(2) as usize))?;
+        queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    /// Draw window header with number, title, and line
+    #[allow(clippy::too_many_arguments)]
+    fn draw_window_header(&self, stdout: &mut impl Write, rect: Rect, label: &str, exit_code: Option<i32>, title: Option<&str>, is_focused: bool, mode_indicator: Option<&str>, alert: HeaderAlert) -> Result<()> {
+        let depth = self.compositor.color_depth();
+
+        queue!(stdout, MoveTo(rect.x, rect.y))?;
+
+        let style = self.header_style(is_focused, mode_indicator.is_some(), alert);
+        queue!(stdout, SetStyle(style, depth))?;
+        // Visual bell - flash the header in reverse video
+        if alert == HeaderAlert::Flash {
+            queue!(stdout, SetAttribute(Attribute::Reverse))?;
         }
 
         // Draw dashes and window number
-        let num_str = format!("──[{}]", num);
+        let num_str = format!("──[{}]", label);
         write!(stdout, "{}", num_str)?;
 
         // Calculate remaining width for title and line
@@ -1573,6 +2593,16 @@ impl App {
 
         // Reserve space for mode indicator if present
         let indicator_len = mode_indicator.map(|s| s.len() + 2).unwrap_or(0); // +2 for brackets
+
+        // Exit status of the last command, if the shell reports it (OSC 133;D)
+        if let Some(code) = exit_code {
+            let status = if code == 0 { "─[✓]".to_string() } else { format!("─[✗{}]", code) };
+            let status_len = status.chars().count();
+            if remaining > status_len + indicator_len {
+                write!(stdout, "{}", status)?;
+                remaining -= status_len;
+            }
+        }
         let available_for_title = remaining.saturating_sub(indicator_len);
 
         // Draw title if present
@@ -1581,14 +2611,9 @@ impl App {
                 write!(stdout, " ")?;
                 remaining -= 1;
                 // Truncate title if too long (leave room for trailing line and indicator)
-                let max_title_len = available_for_title.saturating_sub(2);
-                if title.len() <= max_title_len {
-                    write!(stdout, "{}", title)?;
-                    remaining -= title.len();
-                } else if max_title_len > 3 {
-                    write!(stdout, "{}…", &title[..max_title_len - 1])?;
-                    remaining -= max_title_len;
-                }
+                let (title, title_width) = truncate_to_width(&strip_control_chars(title), available_for_title - 2);
+                write!(stdout, "{}", title)?;
+                remaining -= title_width;
                 write!(stdout, " ")?;
                 remaining = remaining.saturating_sub(1);
             }
@@ -1611,12 +2636,10 @@ impl App {
     }
 }
 
-fn run() -> Result<()> {
-    use std::time::Instant;
-
+fn run(color_depth: ColorDepth) -> Result<()> {
     let (width, height) = terminal::size().context("Failed to get terminal size")?;
 
-    let mut app = App::new(width, height);
+    let mut app = App::new(width, height, color_depth);
 
     // Create initial pane
     app.create_pane()?;
@@ -1643,6 +2666,11 @@ fn run() -> Result<()> {
     while app.running {
         // Process all available PTY output first
         let had_pty_data = app.process_pty_messages();
+        app.process_ipc_requests();
+        app.expire_sync_updates();
+        app.expire_bell_flashes();
+        app.check_silence();
+        app.expire_status_message();
 
         // Check for input with adaptive timeout:
         // - If we have pending renders and frame time elapsed, render now (0ms timeout)
@@ -1666,6 +2694,7 @@ fn run() -> Result<()> {
                 Event::Resize(w, h) => app.resize(w, h)?,
                 _ => {}
             }
+            app.clear_seen_alerts();
         }
 
         // Render if needed and frame time has elapsed (or if we're idle)
@@ -1686,8 +2715,73 @@ fn run() -> Result<()> {
     Ok(())
 }
 
+/// Remove control characters so untrusted text can't inject escape sequences
+fn strip_control_chars(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// Cut text to at most `max_width` columns, ending with "…" if it was cut.
+/// Returns the text and its width
+fn truncate_to_width(text: &str, max_width: usize) -> (String, usize) {
+    let width = UnicodeWidthStr::width(text);
+    if width <= max_width {
+        return (text.to_string(), width);
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+        if used + char_width + 1 > max_width {
+            break;
+        }
+        truncated.push(c);
+        used += char_width;
+    }
+    if max_width > 0 {
+        truncated.push('…');
+        used += 1;
+    }
+    (truncated, used)
+}
+
+/// Fill in the outer title template
+fn expand_title_template(template: &str, tag: &str, pane_title: &str) -> String {
+    let title = template
+        .replace("{tag}", tag)
+        .replace("{pane_title}", &strip_control_chars(pane_title));
+    title.trim_end().to_string()
+}
+
+/// Short age of an event, e.g. "42s", "5m" or "3h"
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}h", secs / 3600)
+    }
+}
+
+/// Cell size of the outer terminal in pixels, if it reports its pixel size
+fn outer_cell_pixel_size() -> Option<(u16, u16)> {
+    let size = terminal::window_size().ok()?;
+    if size.width == 0 || size.height == 0 || size.columns == 0 || size.rows == 0 {
+        return None;
+    }
+    Some((size.width / size.columns, size.height / size.rows))
+}
+
 /// Convert a crossterm KeyEvent to bytes to send to PTY
-fn key_event_to_bytes(key: &KeyEvent) -> Vec<u8> {
+/// Panes that enabled the kitty keyboard protocol get CSI u sequences where needed
+fn key_event_to_bytes(key: &KeyEvent, flags: KeyboardFlags) -> Vec<u8> {
+    if !flags.is_empty() {
+        if let Some(bytes) = kitty_key_sequence(key, flags) {
+            return bytes;
+        }
+    }
+
     // Calculate xterm modifier code: 1 + (shift?1:0) + (alt?2:0) + (ctrl?4:0)
     // Result: 2=Shift, 3=Alt, 4=Shift+Alt, 5=Ctrl, 6=Shift+Ctrl, 7=Alt+Ctrl, 8=all
     let modifier = {
@@ -1783,6 +2877,68 @@ fn key_event_to_bytes(key: &KeyEvent) -> Vec<u8> {
     }
 }
 
+/// Encode a key per the kitty keyboard protocol: CSI code[;modifiers[;text]] u
+/// Returns None for keys whose legacy encoding is already unambiguous
+fn kitty_key_sequence(key: &KeyEvent, flags: KeyboardFlags) -> Option<Vec<u8>> {
+    let all_keys = flags.has(KeyboardFlags::REPORT_ALL_KEYS);
+
+    // Kitty modifier code: 1 + shift(1) + alt(2) + ctrl(4) + super(8) + hyper(16) + meta(32)
+    let mut modifier = 1u8;
+    for (bit, m) in [
+        (1, KeyModifiers::SHIFT),
+        (2, KeyModifiers::ALT),
+        (4, KeyModifiers::CONTROL),
+        (8, KeyModifiers::SUPER),
+        (16, KeyModifiers::HYPER),
+        (32, KeyModifiers::META),
+    ] {
+        if key.modifiers.contains(m) {
+            modifier += bit;
+        }
+    }
+    // Shift alone still produces plain text, any other modifier does not
+    let text_modified = (modifier - 1) & !1 != 0;
+
+    let (code, text) = match key.code {
+        KeyCode::Char(c) => {
+            if !all_keys && !text_modified {
+                return None;
+            }
+            // Key codes are the unshifted key, shift travels in the modifiers
+            let base = if key.modifiers.contains(KeyModifiers::SHIFT) {
+                c.to_lowercase().next().unwrap_or(c)
+            } else {
+                c
+            };
+            (base as u32, (!text_modified).then_some(c))
+        }
+        KeyCode::Esc => (27, None),
+        KeyCode::Enter if all_keys || modifier > 1 => (13, None),
+        KeyCode::Tab if all_keys || modifier > 1 => (9, None),
+        KeyCode::BackTab => {
+            // Shift+Tab, whether or not the terminal also reported shift
+            modifier = ((modifier - 1) | 1) + 1;
+            (9, None)
+        }
+        KeyCode::Backspace if all_keys || modifier > 1 => (127, None),
+        // F1-F4 use CSI forms (F3 is CSI 13 ~ to avoid clashing with cursor reports)
+        KeyCode::F(3) => return Some(csi_tilde_with_modifier(13, modifier, modifier > 1)),
+        KeyCode::F(n @ (1 | 2 | 4)) => return Some(csi_with_modifier(b'P' + n - 1, modifier, modifier > 1)),
+        _ => return None,
+    };
+
+    let mut seq = format!("\x1b[{}", code);
+    let text = text.filter(|_| all_keys && flags.has(KeyboardFlags::REPORT_TEXT));
+    if modifier > 1 || text.is_some() {
+        seq.push_str(&format!(";{}", modifier));
+    }
+    if let Some(t) = text {
+        seq.push_str(&format!(";{}", t as u32));
+    }
+    seq.push('u');
+    Some(seq.into_bytes())
+}
+
 /// Generate CSI sequence with optional modifier: CSI [1;modifier] final
 fn csi_with_modifier(final_byte: u8, modifier: u8, has_modifier: bool) -> Vec<u8> {
     if has_modifier {
@@ -1864,3 +3020,71 @@ fn base64_encode(input: &str) -> String {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_legacy_keys_without_kitty_flags() {
+        let none = KeyboardFlags::default();
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Char('i'), KeyModifiers::CONTROL), none), vec![0x09]);
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Esc, KeyModifiers::NONE), none), vec![0x1b]);
+    }
+
+    #[test]
+    fn test_kitty_disambiguate() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"\x1b[>1u");
+        let flags = buf.keyboard_flags();
+
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Char('i'), KeyModifiers::CONTROL), flags), b"\x1b[105;5u");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Tab, KeyModifiers::NONE), flags), b"\t");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Enter, KeyModifiers::CONTROL), flags), b"\x1b[13;5u");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Esc, KeyModifiers::NONE), flags), b"\x1b[27u");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Char('A'), KeyModifiers::SHIFT), flags), b"A");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Up, KeyModifiers::NONE), flags), b"\x1b[A");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::BackTab, KeyModifiers::NONE), flags), b"\x1b[9;2u");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::BackTab, KeyModifiers::SHIFT), flags), b"\x1b[9;2u");
+    }
+
+    #[test]
+    fn test_kitty_report_all_keys() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"\x1b[>25u");
+        let flags = buf.keyboard_flags();
+
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Char('a'), KeyModifiers::NONE), flags), b"\x1b[97;1;97u");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Char('A'), KeyModifiers::SHIFT), flags), b"\x1b[97;2;65u");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::Enter, KeyModifiers::NONE), flags), b"\x1b[13u");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::F(3), KeyModifiers::NONE), flags), b"\x1b[13~");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::BackTab, KeyModifiers::SHIFT), flags), b"\x1b[9;2u");
+        assert_eq!(key_event_to_bytes(&key(KeyCode::BackTab, KeyModifiers::NONE), flags), b"\x1b[9;2u");
+        let ctrl_shift = KeyModifiers::SHIFT | KeyModifiers::CONTROL;
+        assert_eq!(key_event_to_bytes(&key(KeyCode::BackTab, ctrl_shift), flags), b"\x1b[9;6u");
+    }
+
+    #[test]
+    fn test_notification_text_helpers() {
+        assert_eq!(strip_control_chars("done\x1b]0;x\x07!"), "done]0;x!");
+        assert_eq!(format_age(Duration::from_secs(42)), "42s");
+        assert_eq!(format_age(Duration::from_secs(300)), "5m");
+        assert_eq!(format_age(Duration::from_secs(7300)), "2h");
+    }
+
+    #[test]
+    fn test_title_helpers() {
+        assert_eq!(truncate_to_width("vim main.rs", 20), ("vim main.rs".to_string(), 11));
+        assert_eq!(truncate_to_width("vim main.rs", 6), ("vim m…".to_string(), 6));
+        // Wide and multi-byte characters are cut by columns, never mid-character
+        assert_eq!(truncate_to_width("日本語のタイトル", 7), ("日本語…".to_string(), 7));
+        assert_eq!(truncate_to_width("ééééé", 3), ("éé…".to_string(), 3));
+
+        assert_eq!(expand_title_template("truetm: {tag} {pane_title}", "1,3", "htop"), "truetm: 1,3 htop");
+        assert_eq!(expand_title_template("truetm: {tag} {pane_title}", "2", ""), "truetm: 2");
+    }
+}
@@ -7,13 +7,14 @@ use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
 /// Unique identifier for a pane
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PaneId(pub u32);
 
 /// Rectangle defining a pane's position and size in the terminal
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Rect {
     pub x: u16,
     pub y: u16,
@@ -25,12 +26,45 @@ impl Rect {
     pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
         Self { x, y, width, height }
     }
+
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// The part of this rect that is also in `other`, if any
+    pub fn intersection(&self, other: Rect) -> Option<Rect> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        (x < right && y < bottom).then(|| Rect::new(x, y, right - x, bottom - y))
+    }
+
+    /// A rect of the given size centred in this one
+    pub fn centered(&self, width: u16, height: u16) -> Self {
+        let (width, height) = (width.min(self.width), height.min(self.height));
+        Self::new(self.x + (self.width - width) / 2, self.y + (self.height - height) / 2, width, height)
+    }
+
+    /// Moved and shrunk as needed to lie within `area`, and at least min_width x min_height
+    pub fn clamped(&self, area: Rect, min_width: u16, min_height: u16) -> Self {
+        let width = self.width.max(min_width).min(area.width);
+        let height = self.height.max(min_height).min(area.height);
+        let x = self.x.clamp(area.x, area.x + area.width - width);
+        let y = self.y.clamp(area.y, area.y + area.height - height);
+        Self::new(x, y, width, height)
+    }
 }
 
+/// Smallest size of a floating pane (border, header and a little content)
+pub const FLOAT_MIN_WIDTH: u16 = 10;
+pub const FLOAT_MIN_HEIGHT: u16 = 4;
+
 /// Messages from PTY reader threads
 pub enum PtyMessage {
     Data { pane_id: PaneId, data: Vec<u8> },
     Exit { pane_id: PaneId },
+    // Result of work done off the UI thread, shown in the status bar
+    Message { text: String },
 }
 
 /// A single pane containing a PTY
@@ -38,20 +72,36 @@ pub struct Pane {
     pub id: PaneId,
     pub rect: Rect,
     pub tags: TagSet,
+    // Where the pane floats above the tiling (None = tiled)
+    pub float_rect: Option<Rect>,
+    // The scratchpad has no tags and is shown over any view when toggled on
+    pub scratchpad: bool,
     pty_writer: Box<dyn Write + Send>,
     master: Box<dyn MasterPty + Send>,
     child: Box<dyn Child + Send + Sync>,
     _reader_thread: JoinHandle<()>,
     pub exited: bool,
+    // Rang the bell while not focused (cleared when focused)
+    pub urgent: bool,
+    // When the bell last rang (the window header flashes for a moment)
+    pub bell_at: Option<Instant>,
+    // Activity/silence monitoring and their pending alerts
+    pub monitor_activity: bool,
+    pub monitor_silence: bool,
+    pub activity: bool,
+    pub silent: bool,
+    pub last_output: Instant,
 }
 
 impl Pane {
-    /// Create a new pane with a shell and explicit PTY size
+    /// Create a new pane running a shell (with arguments) and explicit PTY size
+    #[allow(clippy::too_many_arguments)]
     pub fn new_with_size(
         id: PaneId,
         rect: Rect,
         tags: TagSet,
         shell: &str,
+        args: &[&str],
         env_vars: &[(String, String)],
         cwd: Option<PathBuf>,
         pty_tx: Sender<PtyMessage>,
@@ -70,6 +120,7 @@ impl Pane {
 
         // Build command with environment and working directory
         let mut cmd = CommandBuilder::new(shell);
+        cmd.args(args);
         for (key, value) in env_vars {
             cmd.env(key, value);
         }
@@ -120,14 +171,34 @@ impl Pane {
             id,
             rect,
             tags,
+            float_rect: None,
+            scratchpad: false,
             pty_writer,
             master: pair.master,
             child,
             _reader_thread: reader_thread,
             exited: false,
+            urgent: false,
+            bell_at: None,
+            monitor_activity: crate::config::MONITOR_ACTIVITY,
+            monitor_silence: false,
+            activity: false,
+            silent: false,
+            last_output: Instant::now(),
         })
     }
 
+    /// Where the terminal content is drawn: below the header, and inside the
+    /// border for a floating pane
+    pub fn content_rect(&self) -> Rect {
+        let Rect { x, y, width, height } = self.rect;
+        if self.float_rect.is_some() {
+            Rect::new(x + 1, y + 1, width.saturating_sub(2), height.saturating_sub(2))
+        } else {
+            Rect::new(x, y + 1, width, height.saturating_sub(1))
+        }
+    }
+
     /// Get the current working directory of the shell process
     pub fn get_cwd(&self) -> Option<PathBuf> {
         // Get the process ID and read /proc/<pid>/cwd
@@ -172,6 +243,9 @@ pub struct PaneManager {
     panes: Vec<Pane>,
     focus: Option<usize>,
     next_id: u32,
+    // Floating panes, bottom to top
+    float_order: Vec<PaneId>,
+    scratchpad_shown: bool,
 }
 
 impl PaneManager {
@@ -180,6 +254,8 @@ impl PaneManager {
             panes: Vec::new(),
             focus: None,
             next_id: 0,
+            float_order: Vec::new(),
+            scratchpad_shown: false,
         }
     }
 
@@ -225,15 +301,24 @@ impl PaneManager {
         &self.panes
     }
 
-    /// Check if empty
+    /// Get all panes mutably
+    pub fn all_mut(&mut self) -> &mut [Pane] {
+        &mut self.panes
+    }
+
+    /// Check if there are no panes (a scratchpad alone doesn't count)
     pub fn is_empty(&self) -> bool {
-        self.panes.is_empty()
+        self.panes.iter().all(|p| p.scratchpad)
     }
 
     /// Remove a pane by ID
     pub fn remove(&mut self, id: PaneId) -> Option<Pane> {
         if let Some(pos) = self.panes.iter().position(|p| p.id == id) {
             let pane = self.panes.remove(pos);
+            self.float_order.retain(|&f| f != id);
+            if pane.scratchpad {
+                self.scratchpad_shown = false;
+            }
             // Adjust focus
             if self.panes.is_empty() {
                 self.focus = None;
@@ -265,21 +350,88 @@ impl PaneManager {
         }
     }
 
+    /// Whether a pane is shown in a view: it has any of the view's tags, or
+    /// it is the scratchpad and that is toggled on
+    fn in_view(&self, pane: &Pane, view: TagSet) -> bool {
+        pane.tags.intersects(view) || (pane.scratchpad && self.scratchpad_shown)
+    }
+
     /// Get pane IDs visible in the given view (have any of the view's tags)
     /// Returns panes in layout order (first = master, rest = stack)
     pub fn visible_in_view(&self, view: TagSet) -> Vec<PaneId> {
         self.panes
             .iter()
-            .filter(|p| p.tags.intersects(view))
+            .filter(|p| self.in_view(p, view))
             .map(|p| p.id)
             .collect()
     }
 
+    /// Tiled panes visible in the view, in layout order
+    pub fn tiled_in_view(&self, view: TagSet) -> Vec<PaneId> {
+        self.panes
+            .iter()
+            .filter(|p| self.in_view(p, view) && p.float_rect.is_none())
+            .map(|p| p.id)
+            .collect()
+    }
+
+    /// Floating panes visible in the view, bottom to top
+    pub fn floating_in_view(&self, view: TagSet) -> Vec<PaneId> {
+        self.float_order
+            .iter()
+            .copied()
+            .filter(|&id| self.get(id).is_some_and(|p| self.in_view(p, view)))
+            .collect()
+    }
+
+    /// Float a pane at a rect (on top of the other floating panes), or tile it again
+    pub fn set_floating(&mut self, id: PaneId, rect: Option<Rect>) {
+        if let Some(pane) = self.get_mut(id) {
+            pane.float_rect = rect;
+            self.float_order.retain(|&f| f != id);
+            if rect.is_some() {
+                self.float_order.push(id);
+            }
+        }
+    }
+
+    /// The scratchpad pane, if it has been created
+    pub fn scratchpad(&self) -> Option<PaneId> {
+        self.panes.iter().find(|p| p.scratchpad).map(|p| p.id)
+    }
+
+    /// Make a pane the scratchpad, floating at `rect`. It goes last so that the
+    /// other windows keep their numbers
+    pub fn make_scratchpad(&mut self, id: PaneId, rect: Rect) {
+        let Some(pos) = self.panes.iter().position(|p| p.id == id) else {
+            return;
+        };
+        let focused = self.focused().map(|p| p.id);
+        let mut pane = self.panes.remove(pos);
+        pane.scratchpad = true;
+        self.panes.push(pane);
+        self.focus = focused.and_then(|f| self.panes.iter().position(|p| p.id == f));
+        self.set_floating(id, Some(rect));
+    }
+
+    pub fn scratchpad_shown(&self) -> bool {
+        self.scratchpad_shown
+    }
+
+    pub fn show_scratchpad(&mut self, shown: bool) {
+        self.scratchpad_shown = shown;
+    }
+
     /// Check if any pane has the given tag
     pub fn any_with_tag(&self, tag: u8) -> bool {
         self.panes.iter().any(|p| p.tags.contains(tag))
     }
 
+    /// Check if any pane with the given tag matches a predicate (e.g. has an alert)
+    pub fn any_in_tag(&self, tag: u8, pred: impl Fn(&Pane) -> bool) -> bool {
+        self.panes.iter().any(|p| p.tags.contains(tag) && pred(p))
+    }
+
     /// Focus pane in direction (left/right/up/down) within a view
     pub fn focus_direction(&mut self, view: TagSet, dx: i32, dy: i32) {
         let focused_idx = match self.focus {
@@ -288,7 +440,7 @@ impl PaneManager {
         };
 
         let focused_rect = match self.panes.get(focused_idx) {
-            Some(p) if p.tags.intersects(view) => p.rect,
+            Some(p) if self.in_view(p, view) => p.rect,
             _ => return,
         };
 
@@ -300,7 +452,7 @@ impl PaneManager {
         let mut best_dist = i32::MAX;
 
         for (i, pane) in self.panes.iter().enumerate() {
-            if i == focused_idx || !pane.tags.intersects(view) {
+            if i == focused_idx || !self.in_view(pane, view) {
                 continue;
             }
             if pane.rect.width == 0 || pane.rect.height == 0 {
@@ -333,6 +485,7 @@ impl PaneManager {
 
         if let Some(idx) = best_idx {
             self.focus = Some(idx);
+            self.raise_focused();
         }
     }
 
@@ -341,14 +494,14 @@ impl PaneManager {
         // Check if current focus is visible
         if let Some(f) = self.focus {
             if let Some(pane) = self.panes.get(f) {
-                if pane.tags.intersects(view) {
+                if self.in_view(pane, view) {
                     return; // Already focused on a visible pane
                 }
             }
         }
         // Focus first visible pane
         for (i, pane) in self.panes.iter().enumerate() {
-            if pane.tags.intersects(view) {
+            if self.in_view(pane, view) {
                 self.focus = Some(i);
                 return;
             }
@@ -364,7 +517,7 @@ impl PaneManager {
         let visible: Vec<usize> = self.panes
             .iter()
             .enumerate()
-            .filter(|(_, p)| p.tags.intersects(view))
+            .filter(|(_, p)| self.in_view(p, view) && p.float_rect.is_none())
             .map(|(i, _)| i)
             .collect();
 
@@ -388,12 +541,22 @@ impl PaneManager {
         }
     }
 
-    /// Focus a specific pane by ID
+    /// Focus a specific pane by ID (raising it if floating)
     pub fn focus_by_id(&mut self, id: PaneId) {
         if let Some(pos) = self.panes.iter().position(|p| p.id == id) {
             self.focus = Some(pos);
+            self.raise_focused();
         }
     }
+
+    /// Put the focused pane on top of the other floating panes
+    pub fn raise_focused(&mut self) {
+        let Some(id) = self.focused().filter(|p| p.float_rect.is_some()).map(|p| p.id) else {
+            return;
+        };
+        self.float_order.retain(|&f| f != id);
+        self.float_order.push(id);
+    }
 }
 
 impl Default for PaneManager {
@@ -401,3 +564,23 @@ impl Default for PaneManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rect_geometry() {
+        let area = Rect::new(0, 0, 80, 23);
+        assert_eq!(area.centered(40, 11), Rect::new(20, 6, 40, 11));
+        assert!(area.centered(40, 11).contains(20, 6));
+        assert!(!area.centered(40, 11).contains(60, 6));
+
+        // Pushed back inside the area, and grown to the minimum size
+        assert_eq!(Rect::new(70, 20, 20, 2).clamped(area, 10, 4), Rect::new(60, 19, 20, 4));
+        assert_eq!(Rect::new(5, 5, 200, 50).clamped(area, 10, 4), Rect::new(0, 0, 80, 23));
+
+        assert_eq!(area.intersection(Rect::new(70, 20, 20, 5)), Some(Rect::new(70, 20, 10, 3)));
+        assert_eq!(area.intersection(Rect::new(80, 0, 5, 5)), None);
+    }
+}
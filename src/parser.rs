@@ -0,0 +1,693 @@
+//! Table-driven escape sequence parser (after Paul Williams' DEC ANSI parser, as in vte)
+//!
+//! Bytes go through a state machine that calls back into a `Perform` implementation.
+//! The ground state decodes UTF-8 and hands runs of printable text over in one call.
+
+/// Maximum number of CSI/DCS parameters (values including subparameters)
+pub const MAX_PARAMS: usize = 32;
+
+/// Maximum number of intermediate bytes (including private markers like '?')
+const MAX_INTERMEDIATES: usize = 2;
+
+/// Longest OSC string accepted (longer ones are dropped)
+const MAX_OSC_BYTES: usize = 4096;
+
+/// Receives the actions recognised by the parser
+pub trait Perform {
+    /// Print one character
+    fn print(&mut self, c: char);
+
+    /// Print a run of printable characters (no controls)
+    fn print_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.print(c);
+        }
+    }
+
+    /// Execute a C0 or C1 control function
+    fn execute(&mut self, byte: u8);
+
+    /// ESC sequence ended by `byte`
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8);
+
+    /// CSI sequence ended by `action`. Private markers ('?', '>', ...) are intermediates
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], action: u8);
+
+    /// OSC string (without the terminator)
+    fn osc_dispatch(&mut self, data: &[u8]);
+
+    /// DCS header ended by `action`; the data follows through `put` until `unhook`
+    fn hook(&mut self, params: &Params, intermediates: &[u8], action: u8);
+    fn put(&mut self, byte: u8);
+    fn unhook(&mut self);
+    /// DCS string cancelled (by CAN, SUB or an ESC not starting ST) - drop it without acting on it
+    fn dcs_cancel(&mut self);
+
+    /// APC string, passed through byte by byte like DCS data
+    fn apc_start(&mut self);
+    fn apc_put(&mut self, byte: u8);
+    fn apc_end(&mut self);
+    fn apc_cancel(&mut self);
+}
+
+/// CSI/DCS parameters, grouped with their colon-separated subparameters.
+/// Empty parameters are 0
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Params {
+    values: [u16; MAX_PARAMS],
+    len: usize,
+    // Number of values in each group (a parameter plus its subparameters)
+    groups: [u8; MAX_PARAMS],
+    group_count: usize,
+}
+
+impl Params {
+    /// First value of parameter `index`
+    pub fn get(&self, index: usize) -> Option<u16> {
+        self.iter().nth(index).map(|group| group[0])
+    }
+
+    /// Parameter `index`, or `default` if it is missing or 0
+    pub fn arg(&self, index: usize, default: u16) -> u16 {
+        self.get(index).filter(|&v| v != 0).unwrap_or(default)
+    }
+
+    /// Parameters as slices of a value followed by its subparameters
+    pub fn iter(&self) -> impl Iterator<Item = &[u16]> + '_ {
+        let mut start = 0;
+        self.groups[..self.group_count].iter().map(move |&n| {
+            let group = &self.values[start..start + n as usize];
+            start += n as usize;
+            group
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == MAX_PARAMS
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.group_count = 0;
+    }
+
+    /// Add a value, starting a new parameter unless it is a subparameter
+    fn push(&mut self, value: u16, subparam: bool) {
+        self.values[self.len] = value;
+        self.len += 1;
+        if subparam && self.group_count > 0 {
+            self.groups[self.group_count - 1] += 1;
+        } else {
+            self.groups[self.group_count] = 1;
+            self.group_count += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    #[default]
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    DcsPassthrough,
+    DcsIgnore,
+    OscString,
+    ApcString,
+    SosPmString,
+}
+
+/// Escape sequence parser state
+#[derive(Default)]
+pub struct Parser {
+    state: State,
+    intermediates: [u8; MAX_INTERMEDIATES],
+    intermediate_len: usize,
+    params: Params,
+    // Parameter being read, and whether the next one is a subparameter (after ':')
+    param: u16,
+    subparam: bool,
+    osc: Vec<u8>,
+    osc_overflow: bool,
+    // ESC seen in a control string: ST if '\\' follows, otherwise the string is cancelled
+    string_esc: bool,
+    // Partial UTF-8 sequence in the ground state
+    utf8: [u8; 4],
+    utf8_len: usize,
+    utf8_needed: usize,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed bytes through the state machine
+    pub fn advance<P: Perform>(&mut self, performer: &mut P, data: &[u8]) {
+        let mut i = 0;
+        while i < data.len() {
+            if self.state == State::Ground && self.utf8_len == 0 {
+                // Fast path: hand a run of printable text over in one go
+                let run = printable_run(&data[i..]);
+                if run > 0 {
+                    let text = &data[i..i + run];
+                    match std::str::from_utf8(text) {
+                        Ok(s) => {
+                            performer.print_str(s);
+                            i += run;
+                        }
+                        Err(e) => {
+                            let valid = e.valid_up_to();
+                            if let Ok(s) = std::str::from_utf8(&text[..valid]) {
+                                if !s.is_empty() {
+                                    performer.print_str(s);
+                                }
+                            }
+                            i += valid;
+                            // The bad (or incomplete) sequence goes through the byte decoder
+                            self.advance_byte(performer, data[i]);
+                            i += 1;
+                        }
+                    }
+                    continue;
+                }
+            }
+            self.advance_byte(performer, data[i]);
+            i += 1;
+        }
+    }
+
+    fn advance_byte<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        if std::mem::take(&mut self.string_esc) {
+            if byte == b'\\' {
+                self.end_string(performer);
+                self.state = State::Ground;
+                return;
+            }
+            // Any other sequence cancels the string and starts as usual
+            self.abort_string(performer);
+            self.enter_escape();
+        }
+
+        // Transitions from any state
+        match byte {
+            0x18 | 0x1a => {
+                // CAN, SUB - abort the sequence
+                self.abort_string(performer);
+                self.flush_utf8(performer);
+                performer.execute(byte);
+                self.state = State::Ground;
+                return;
+            }
+            0x1b if self.in_string() => {
+                self.string_esc = true;
+                return;
+            }
+            0x1b => {
+                self.flush_utf8(performer);
+                self.enter_escape();
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => self.ground(performer, byte),
+            State::Escape => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::EscapeIntermediate;
+                }
+                b'[' => self.enter_csi(),
+                b']' => self.enter_osc(),
+                b'P' => self.enter_dcs(),
+                b'_' => {
+                    performer.apc_start();
+                    self.state = State::ApcString;
+                }
+                b'X' | b'^' => self.state = State::SosPmString,
+                0x30..=0x7e => {
+                    performer.esc_dispatch(self.intermediates(), byte);
+                    self.state = State::Ground;
+                }
+                0x80..=0x9f => self.c1(performer, byte),
+                _ => {}
+            },
+            State::EscapeIntermediate => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f if !self.collect(byte) => self.state = State::Ground,
+                0x20..=0x2f => {}
+                0x30..=0x7e => {
+                    performer.esc_dispatch(self.intermediates(), byte);
+                    self.state = State::Ground;
+                }
+                0x80..=0x9f => self.c1(performer, byte),
+                _ => {}
+            },
+            State::CsiEntry | State::CsiParam | State::CsiIntermediate | State::CsiIgnore => {
+                self.csi(performer, byte)
+            }
+            State::DcsEntry | State::DcsParam | State::DcsIntermediate | State::DcsIgnore => {
+                self.dcs_header(performer, byte)
+            }
+            State::DcsPassthrough => match byte {
+                0x07 | 0x9c => {
+                    performer.unhook();
+                    self.state = State::Ground;
+                }
+                0x7f => {}
+                _ => performer.put(byte),
+            },
+            State::OscString => match byte {
+                0x07 => {
+                    self.end_string(performer);
+                    self.state = State::Ground;
+                }
+                0x00..=0x1f => {}
+                _ => {
+                    if self.osc.len() < MAX_OSC_BYTES {
+                        self.osc.push(byte);
+                    } else {
+                        self.osc_overflow = true;
+                    }
+                }
+            },
+            State::ApcString => match byte {
+                0x07 | 0x9c => {
+                    performer.apc_end();
+                    self.state = State::Ground;
+                }
+                _ => performer.apc_put(byte),
+            },
+            State::SosPmString => {
+                if matches!(byte, 0x07 | 0x9c) {
+                    self.state = State::Ground;
+                }
+            }
+        }
+    }
+
+    /// Ground state: controls and UTF-8 text (C1 controls arrive UTF-8 encoded)
+    fn ground<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        if self.utf8_needed > 0 {
+            if byte & 0xc0 == 0x80 {
+                self.utf8[self.utf8_len] = byte;
+                self.utf8_len += 1;
+                self.utf8_needed -= 1;
+                if self.utf8_needed == 0 {
+                    let len = std::mem::take(&mut self.utf8_len);
+                    match std::str::from_utf8(&self.utf8[..len]).ok().and_then(|s| s.chars().next()) {
+                        Some(c @ '\u{80}'..='\u{9f}') => self.c1(performer, c as u8),
+                        Some(c) => performer.print(c),
+                        None => performer.print(char::REPLACEMENT_CHARACTER),
+                    }
+                }
+                return;
+            }
+            // Sequence cut short - replace it and handle this byte on its own
+            self.flush_utf8(performer);
+        }
+
+        match byte {
+            0x00..=0x1f => performer.execute(byte),
+            0x20..=0x7e => performer.print(byte as char),
+            0x7f => {}
+            0xc2..=0xdf => self.start_utf8(byte, 1),
+            0xe0..=0xef => self.start_utf8(byte, 2),
+            0xf0..=0xf4 => self.start_utf8(byte, 3),
+            _ => performer.print(char::REPLACEMENT_CHARACTER),
+        }
+    }
+
+    fn csi<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match (self.state, byte) {
+            (_, 0x00..=0x1f) => performer.execute(byte),
+            (_, 0x7f) => {}
+            (State::CsiIgnore, 0x40..=0x7e) => self.state = State::Ground,
+            (State::CsiIgnore, _) => {}
+            (State::CsiEntry | State::CsiParam, b'0'..=b'9' | b';' | b':') => {
+                self.state = if self.param_byte(byte) { State::CsiParam } else { State::CsiIgnore };
+            }
+            (State::CsiEntry, 0x3c..=0x3f) => {
+                // Private marker
+                self.collect(byte);
+                self.state = State::CsiParam;
+            }
+            (_, 0x20..=0x2f) => {
+                self.state = if self.collect(byte) { State::CsiIntermediate } else { State::CsiIgnore };
+            }
+            (_, 0x40..=0x7e) => {
+                if self.finish_params() {
+                    performer.csi_dispatch(&self.params, self.intermediates(), byte);
+                }
+                self.state = State::Ground;
+            }
+            (_, 0x30..=0x3f) => self.state = State::CsiIgnore,
+            (_, 0x80..=0x9f) => self.c1(performer, byte),
+            _ => {}
+        }
+    }
+
+    fn dcs_header<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match (self.state, byte) {
+            (State::DcsIgnore, 0x07 | 0x9c) => self.state = State::Ground,
+            (_, 0x00..=0x1f | 0x7f) => {}
+            (State::DcsIgnore, _) => {}
+            (State::DcsEntry | State::DcsParam, b'0'..=b'9' | b';' | b':') => {
+                self.state = if self.param_byte(byte) { State::DcsParam } else { State::DcsIgnore };
+            }
+            (State::DcsEntry, 0x3c..=0x3f) => {
+                self.collect(byte);
+                self.state = State::DcsParam;
+            }
+            (_, 0x20..=0x2f) => {
+                self.state = if self.collect(byte) { State::DcsIntermediate } else { State::DcsIgnore };
+            }
+            (_, 0x40..=0x7e) => {
+                if self.finish_params() {
+                    performer.hook(&self.params, self.intermediates(), byte);
+                    self.state = State::DcsPassthrough;
+                } else {
+                    self.state = State::DcsIgnore;
+                }
+            }
+            (_, 0x30..=0x3f) => self.state = State::DcsIgnore,
+            (_, 0x80..=0x9f) => self.c1(performer, byte),
+            _ => {}
+        }
+    }
+
+    /// 8-bit C1 control: sequence introducers switch state, the rest are executed
+    fn c1<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            0x90 => self.enter_dcs(),
+            0x9b => self.enter_csi(),
+            0x9d => self.enter_osc(),
+            0x9f => {
+                performer.apc_start();
+                self.state = State::ApcString;
+            }
+            0x98 | 0x9e => self.state = State::SosPmString,
+            0x9c => self.state = State::Ground,
+            _ => {
+                performer.execute(byte);
+                self.state = State::Ground;
+            }
+        }
+    }
+
+    /// A digit, ';' or ':' of a CSI/DCS parameter list; false if there are too many
+    fn param_byte(&mut self, byte: u8) -> bool {
+        match byte {
+            b';' | b':' => {
+                if self.params.is_full() {
+                    return false;
+                }
+                self.params.push(self.param, self.subparam);
+                self.param = 0;
+                self.subparam = byte == b':';
+            }
+            _ => {
+                self.param = self.param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+            }
+        }
+        true
+    }
+
+    /// Add the last parameter; false if there are too many
+    fn finish_params(&mut self) -> bool {
+        if self.params.is_full() {
+            return false;
+        }
+        self.params.push(self.param, self.subparam);
+        true
+    }
+
+    /// Add an intermediate byte; false if there are too many
+    fn collect(&mut self, byte: u8) -> bool {
+        if self.intermediate_len == MAX_INTERMEDIATES {
+            return false;
+        }
+        self.intermediates[self.intermediate_len] = byte;
+        self.intermediate_len += 1;
+        true
+    }
+
+    fn intermediates(&self) -> &[u8] {
+        &self.intermediates[..self.intermediate_len]
+    }
+
+    fn clear(&mut self) {
+        self.intermediate_len = 0;
+        self.params.clear();
+        self.param = 0;
+        self.subparam = false;
+    }
+
+    fn enter_escape(&mut self) {
+        self.clear();
+        self.state = State::Escape;
+    }
+
+    fn enter_csi(&mut self) {
+        self.clear();
+        self.state = State::CsiEntry;
+    }
+
+    fn enter_dcs(&mut self) {
+        self.clear();
+        self.state = State::DcsEntry;
+    }
+
+    fn enter_osc(&mut self) {
+        self.osc.clear();
+        self.osc_overflow = false;
+        self.state = State::OscString;
+    }
+
+    fn in_string(&self) -> bool {
+        matches!(self.state, State::OscString | State::DcsPassthrough | State::ApcString | State::SosPmString)
+    }
+
+    /// Finish a control string that is being terminated (by BEL or ST)
+    fn end_string<P: Perform>(&mut self, performer: &mut P) {
+        match self.state {
+            State::OscString => {
+                if !self.osc_overflow {
+                    performer.osc_dispatch(&self.osc);
+                }
+                self.osc.clear();
+            }
+            State::DcsPassthrough => performer.unhook(),
+            State::ApcString => performer.apc_end(),
+            _ => {}
+        }
+    }
+
+    /// Drop a control string cancelled by CAN, SUB or an ESC that doesn't start ST
+    fn abort_string<P: Perform>(&mut self, performer: &mut P) {
+        match self.state {
+            State::OscString => self.osc.clear(),
+            State::DcsPassthrough => performer.dcs_cancel(),
+            State::ApcString => performer.apc_cancel(),
+            _ => {}
+        }
+    }
+
+    fn start_utf8(&mut self, byte: u8, needed: usize) {
+        self.utf8[0] = byte;
+        self.utf8_len = 1;
+        self.utf8_needed = needed;
+    }
+
+    /// Replace an unfinished UTF-8 sequence
+    fn flush_utf8<P: Perform>(&mut self, performer: &mut P) {
+        if self.utf8_len > 0 {
+            self.utf8_len = 0;
+            self.utf8_needed = 0;
+            performer.print(char::REPLACEMENT_CHARACTER);
+        }
+    }
+}
+
+/// Length of the run of printable bytes (text and UTF-8, but no controls) at the start of data
+fn printable_run(data: &[u8]) -> usize {
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            0x00..=0x1f | 0x7f => break,
+            // U+0080..U+009F are C1 controls
+            0xc2 if matches!(data.get(i + 1), Some(0x80..=0x9f)) => break,
+            _ => i += 1,
+        }
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records what the parser recognised
+    #[derive(Default)]
+    struct Recorder {
+        text: String,
+        runs: usize,
+        actions: Vec<String>,
+    }
+
+    impl Perform for Recorder {
+        fn print(&mut self, c: char) {
+            self.text.push(c);
+        }
+        fn print_str(&mut self, s: &str) {
+            self.runs += 1;
+            self.text.push_str(s);
+        }
+        fn execute(&mut self, byte: u8) {
+            self.actions.push(format!("exec {:02x}", byte));
+        }
+        fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+            self.actions.push(format!("esc {}{}", String::from_utf8_lossy(intermediates), byte as char));
+        }
+        fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], action: u8) {
+            let params: Vec<String> = params
+                .iter()
+                .map(|g| g.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(":"))
+                .collect();
+            self.actions.push(format!(
+                "csi {}{}{}",
+                String::from_utf8_lossy(intermediates),
+                params.join(";"),
+                action as char
+            ));
+        }
+        fn osc_dispatch(&mut self, data: &[u8]) {
+            self.actions.push(format!("osc {}", String::from_utf8_lossy(data)));
+        }
+        fn hook(&mut self, params: &Params, _intermediates: &[u8], action: u8) {
+            self.actions.push(format!("hook {:?} {}", params.get(0), action as char));
+        }
+        fn put(&mut self, byte: u8) {
+            self.text.push(byte as char);
+        }
+        fn unhook(&mut self) {
+            self.actions.push("unhook".to_string());
+        }
+        fn dcs_cancel(&mut self) {
+            self.actions.push("dcs cancel".to_string());
+        }
+        fn apc_start(&mut self) {
+            self.actions.push("apc".to_string());
+        }
+        fn apc_put(&mut self, byte: u8) {
+            self.text.push(byte as char);
+        }
+        fn apc_end(&mut self) {
+            self.actions.push("apc end".to_string());
+        }
+        fn apc_cancel(&mut self) {
+            self.actions.push("apc cancel".to_string());
+        }
+    }
+
+    fn parse(chunks: &[&[u8]]) -> Recorder {
+        let mut parser = Parser::new();
+        let mut recorder = Recorder::default();
+        for chunk in chunks {
+            parser.advance(&mut recorder, chunk);
+        }
+        recorder
+    }
+
+    #[test]
+    fn test_printable_runs() {
+        let r = parse(&[b"hello w\xc3\xb6rld\r\n"]);
+        assert_eq!(r.text, "hello wörld");
+        assert_eq!(r.runs, 1);
+        assert_eq!(r.actions, ["exec 0d", "exec 0a"]);
+    }
+
+    #[test]
+    fn test_utf8_split_and_invalid() {
+        // A character split across reads, then a stray continuation byte
+        let r = parse(&[b"a\xe6\x97", b"\xa5b\x80c"]);
+        assert_eq!(r.text, "a日b\u{fffd}c");
+        // An unfinished sequence interrupted by a control
+        let r = parse(&[b"\xe6\x97\n"]);
+        assert_eq!(r.text, "\u{fffd}");
+        assert_eq!(r.actions, ["exec 0a"]);
+    }
+
+    #[test]
+    fn test_csi_params() {
+        let r = parse(&[b"\x1b[1;;38:2::10:20:30m\x1b[?1049h\x1b[2 q\x1b[>1u\x1b[m"]);
+        assert_eq!(
+            r.actions,
+            ["csi 1;0;38:2:0:10:20:30m", "csi ?1049h", "csi  2q", "csi >1u", "csi 0m"]
+        );
+        // Controls inside a sequence are executed, the sequence carries on
+        let r = parse(&[b"\x1b[2\n;5H"]);
+        assert_eq!(r.actions, ["exec 0a", "csi 2;5H"]);
+        // A misplaced private marker makes the sequence ignored
+        let r = parse(&[b"\x1b[1?hx"]);
+        assert!(r.actions.is_empty());
+        assert_eq!(r.text, "x");
+    }
+
+    #[test]
+    fn test_params_arg() {
+        let mut params = Params::default();
+        params.push(0, false);
+        params.push(7, false);
+        params.push(3, true);
+        assert_eq!(params.arg(0, 1), 1);
+        assert_eq!(params.arg(1, 1), 7);
+        assert_eq!(params.arg(2, 9), 9);
+        assert_eq!(params.iter().nth(1), Some(&[7, 3][..]));
+    }
+
+    #[test]
+    fn test_strings() {
+        let r = parse(&[b"\x1b]2;t\xc3\xaftle\x07\x1b]0;x\x1b\\"]);
+        assert_eq!(r.actions, ["osc 2;tïtle", "osc 0;x"]);
+
+        let r = parse(&[b"\x1bP0;1q#0!5~\x1b", b"\\"]);
+        assert_eq!(r.actions, ["hook Some(0) q", "unhook"]);
+        assert_eq!(r.text, "#0!5~");
+
+        let r = parse(&[b"\x1b_Gi=1;AA", b"AA\x1b\\\x1b^ignored\x1b\\z"]);
+        assert_eq!(r.actions, ["apc", "apc end"]);
+        assert_eq!(r.text, "Gi=1;AAAAz");
+    }
+
+    #[test]
+    fn test_cancelled_strings() {
+        // CAN and SUB drop a string without finishing it
+        let r = parse(&[b"\x1bPq#0~\x18\x1b_Ga=T\x1a\x1b]2;x\x18"]);
+        assert_eq!(r.actions, ["hook Some(0) q", "dcs cancel", "exec 18", "apc", "apc cancel", "exec 1a", "exec 18"]);
+        // So does an ESC that doesn't start ST, and the new sequence goes ahead
+        let r = parse(&[b"\x1bPq#0~\x1b[2J\x1b]2;x\x1b7"]);
+        assert_eq!(r.actions, ["hook Some(0) q", "dcs cancel", "csi 2J", "esc 7"]);
+    }
+
+    #[test]
+    fn test_c1_controls() {
+        // UTF-8 encoded C1: CSI (U+009B) and NEL (U+0085)
+        let r = parse(&[b"\xc2\x9b2J\xc2\x85x"]);
+        assert_eq!(r.actions, ["csi 2J", "exec 85"]);
+        assert_eq!(r.text, "x");
+        // CAN aborts a sequence
+        let r = parse(&[b"\x1b[12\x18A"]);
+        assert_eq!(r.actions, ["exec 18"]);
+        assert_eq!(r.text, "A");
+    }
+}
@@ -1,16 +1,26 @@
 //! Rendering - screen buffers and compositor
 
+use crate::cell::{self, StoredCell, StyleTable};
+use crate::color::{ColorDepth, SetBg, SetFg};
 use crate::config;
-use crate::pane::Rect;
+use crate::highlight::{HighlightSet, Span};
+use crate::image::{self, Image, ImageMode, ImageProtocol, KittyCommand};
+use crate::pane::{PaneId, Rect};
+use crate::parser::{Params, Parser, Perform};
+use crate::spill::{SpillFile, SpillReader};
+use crate::theme::Theme;
 use crossterm::{
     cursor::MoveTo,
     queue,
-    style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor},
+    style::{Color, ResetColor},
+    terminal::{BeginSynchronizedUpdate, EndSynchronizedUpdate},
 };
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Write;
+use std::time::{Duration, Instant};
 
 /// Text attributes as bitflags
-#[derive(Clone, Copy, PartialEq, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub struct Attrs(u8);
 
 impl Attrs {
@@ -36,8 +46,40 @@ impl Attrs {
     pub fn reset(&mut self) {
         self.0 = 0;
     }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+}
+
+/// Kitty keyboard protocol enhancement flags an application requested (CSI > flags u)
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct KeyboardFlags(u8);
+
+impl KeyboardFlags {
+    pub const DISAMBIGUATE: u8 = 1 << 0;
+    pub const REPORT_ALL_KEYS: u8 = 1 << 3;
+    pub const REPORT_TEXT: u8 = 1 << 4;
+
+    /// Flags truetm knows how to encode keys for - others are never reported as active
+    const SUPPORTED: u8 = Self::DISAMBIGUATE | Self::REPORT_ALL_KEYS | Self::REPORT_TEXT;
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
 }
 
+/// Maximum depth of a kitty keyboard flag stack (oldest entries are evicted)
+const KEYBOARD_STACK_LIMIT: usize = 16;
+
 /// A cell in the screen buffer
 #[derive(Clone, Copy, PartialEq)]
 pub struct Cell {
@@ -58,12 +100,77 @@ impl Default for Cell {
     }
 }
 
+/// Character set designated into G0/G1
+#[derive(Clone, Copy, PartialEq, Default)]
+enum Charset {
+    #[default]
+    Ascii,
+    // DEC Special Graphics (line drawing), ESC ( 0
+    DecSpecial,
+}
+
+/// Cursor state saved by DECSC (ESC 7 / CSI s) and restored by DECRC (ESC 8 / CSI u)
+#[derive(Clone, Copy)]
+struct SavedCursor {
+    x: u16,
+    y: u16,
+    fg: Option<Color>,
+    bg: Option<Color>,
+    attrs: Attrs,
+    charsets: [Charset; 2],
+    shift_out: bool,
+    origin_mode: bool,
+    wrap_pending: Option<(u16, u16)>,
+}
+
+/// Most notifications kept between drains (oldest are dropped)
+const MAX_PENDING_NOTIFICATIONS: usize = 16;
+
+/// Maximum number of titles saved with CSI 22 t (as in xterm)
+const MAX_TITLE_STACK: usize = 10;
+
+/// Desktop-style notification sent by an application (OSC 9 / OSC 777)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub title: Option<String>,
+    pub body: String,
+}
+
+/// Most completed lines kept for output triggers between drains (oldest are dropped)
+const MAX_CAPTURED_LINES: usize = 1000;
+
+/// Longest logical line kept for output triggers, in characters
+const MAX_CAPTURED_LINE_CHARS: usize = 4096;
+
+/// Columns between default tab stops
+const TAB_WIDTH: usize = 8;
+
+/// Shell integration marks on a line (OSC 133)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LineMarks {
+    pub prompt: bool,           // A - a prompt starts on this line
+    pub input: bool,            // B - command input starts on this line
+    pub output: bool,           // C - command output starts on this line
+    pub finished: bool,         // D - a command finished on this line
+    pub exit_code: Option<i32>, // Exit code reported with D
+}
+
+/// A line that scrolled off the top of the screen, without its trailing blanks
+struct ScrollbackLine {
+    cells: Box<[StoredCell]>,
+    marks: LineMarks,
+    // When the line entered the scrollback, in seconds since the Unix epoch
+    time: u64,
+}
+
 /// Default scrollback buffer size (number of lines)
 const DEFAULT_SCROLLBACK: usize = config::SCROLLBACK_LINES;
 
 /// Screen buffer for a pane - stores the current display state
 pub struct ScreenBuffer {
-    cells: Vec<Cell>,
+    cells: Vec<StoredCell>,
+    // Styles the stored cells refer to (screens, scrollback and frozen frame)
+    styles: StyleTable,
     width: u16,
     height: u16,
     cursor_x: u16,
@@ -74,42 +181,99 @@ pub struct ScreenBuffer {
     current_fg: Option<Color>,
     current_bg: Option<Color>,
     current_attrs: Attrs,
-    // Parser state
-    parse_state: ParseState,
-    parse_buffer: Vec<u8>,
-    // UTF-8 accumulator
-    utf8_buffer: Vec<u8>,
-    utf8_remaining: u8,
-    // Alternate screen buffer support
-    saved_cells: Option<Vec<Cell>>,
-    saved_cursor: Option<(u16, u16)>,
+    // Escape sequence parser, taken out while it drives this buffer
+    parser: Parser,
+    // Alternate screen buffer support - cells of the screen not being shown
+    saved_cells: Option<Vec<StoredCell>>,
     in_alternate_screen: bool,
-    // Window title (set via OSC sequences)
+    // DECSC slots for the active and inactive screen
+    saved_cursor: Option<SavedCursor>,
+    other_saved_cursor: Option<SavedCursor>,
+    // G0/G1 character sets and whether G1 is shifted in (SO)
+    charsets: [Charset; 2],
+    shift_out: bool,
+    // Kitty keyboard protocol flag stacks (main and alternate screens are independent)
+    keyboard_stack: Vec<KeyboardFlags>,
+    saved_keyboard_stack: Vec<KeyboardFlags>,
+    // Window title (set via OSC sequences) and titles saved with CSI 22 t
     title: Option<String>,
+    title_stack: Vec<Option<String>>,
     // Cursor visibility (controlled by CSI ?25h/l)
     cursor_visible: bool,
     // Scroll region (top and bottom line, 0-indexed, inclusive)
     scroll_top: u16,
     scroll_bottom: u16,
+    // Left and right margins (0-indexed, inclusive) - only settable while DECLRMM is on
+    scroll_left: u16,
+    scroll_right: u16,
+    lr_margin_mode: bool,
+    // DECOM - cursor addressing is relative to the margins
+    origin_mode: bool,
+    // IRM - printed characters shift the rest of the line right
+    insert_mode: bool,
+    // Position the cursor reached after printing in the right margin column;
+    // the next character wraps to the left margin if the cursor is still there
+    wrap_pending: Option<(u16, u16)>,
+    // Last printed character (repeated by REP)
+    last_printed: Option<char>,
+    // BEL received since the app last checked
+    bell: bool,
+    // Completed output lines for output triggers (None = not capturing), and the
+    // wrapped rows of the line in progress
+    captured_lines: Option<VecDeque<String>>,
+    partial_line: String,
+    // Notifications (OSC 9 / OSC 777) not yet picked up by the app
+    notifications: Vec<Notification>,
+    // Shell integration marks per screen row (and for the inactive screen), and the
+    // exit code of the last finished command (None = unknown)
+    row_marks: Vec<LineMarks>,
+    saved_row_marks: Vec<LineMarks>,
+    last_exit_code: Option<i32>,
+    // Tab stops, one entry per column (set via HTS, cleared via TBC)
+    tab_stops: Vec<bool>,
     // Scrollback buffer - lines that scrolled off the top
-    scrollback: std::collections::VecDeque<Vec<Cell>>,
+    scrollback: std::collections::VecDeque<ScrollbackLine>,
     scrollback_limit: usize,
+    // Memory held by the scrollback lines, in bytes
+    scrollback_bytes: usize,
+    // Lines evicted from the scrollback go to a spill file (created on first use)
+    // instead of being dropped; the spilled lines come before the in-memory ones
+    spill_scrollback: bool,
+    spill: Option<SpillFile>,
+    // Scrollback was cleared and views into it need adjusting
+    scrollback_cleared: bool,
+    // Synchronized output (mode 2026) - frozen frame shown while the app redraws
+    sync_snapshot: Option<SyncSnapshot>,
+    // Inline images anchored to cells (main screen images are saved in the alternate screen)
+    images: Vec<Image>,
+    saved_images: Vec<Image>,
+    // Kitty graphics: images transmitted by the app (by its own id) and a chunked upload in
+    // progress, whose chunks are skipped until the last one once it is too big
+    kitty_uploads: VecDeque<(u32, KittyCommand)>,
+    kitty_pending: Option<KittyCommand>,
+    kitty_discarding: bool,
+    // Outer terminal cell size in pixels (for sizing images and answering CSI 14/16 t)
+    cell_pixels: (u16, u16),
+    // DCS or APC string being collected, and whether it exceeded the size limit
+    string_buffer: Vec<u8>,
+    string_overflow: bool,
+    // The DCS being collected is sixel data
+    dcs_sixel: bool,
 }
 
-#[derive(Clone, Copy, PartialEq)]
-enum ParseState {
-    Normal,
-    Escape,
-    Csi,
-    Osc,
-    Dcs,           // Device Control String (ESC P ... ST)
-    CharsetSelect, // ESC ( X, ESC ) X - consume next byte
+/// Screen contents captured when an application begins a synchronized update
+struct SyncSnapshot {
+    cells: Vec<StoredCell>,
+    cursor: (u16, u16),
+    cursor_visible: bool,
+    started: Instant,
 }
 
 impl ScreenBuffer {
     pub fn new(width: u16, height: u16) -> Self {
         Self {
-            cells: vec![Cell::default(); (width as usize) * (height as usize)],
+            cells: vec![StoredCell::BLANK; (width as usize) * (height as usize)],
+            styles: StyleTable::new(),
             width,
             height,
             cursor_x: 0,
@@ -118,19 +282,51 @@ impl ScreenBuffer {
             current_fg: None,
             current_bg: None,
             current_attrs: Attrs::default(),
-            parse_state: ParseState::Normal,
-            parse_buffer: Vec::new(),
-            utf8_buffer: Vec::new(),
-            utf8_remaining: 0,
+            parser: Parser::new(),
             saved_cells: None,
-            saved_cursor: None,
             in_alternate_screen: false,
+            saved_cursor: None,
+            other_saved_cursor: None,
+            charsets: [Charset::Ascii; 2],
+            shift_out: false,
+            keyboard_stack: Vec::new(),
+            saved_keyboard_stack: Vec::new(),
             title: None,
+            title_stack: Vec::new(),
             cursor_visible: true,
             scroll_top: 0,
             scroll_bottom: height.saturating_sub(1),
+            scroll_left: 0,
+            scroll_right: width.saturating_sub(1),
+            lr_margin_mode: false,
+            origin_mode: false,
+            insert_mode: false,
+            wrap_pending: None,
+            last_printed: None,
+            bell: false,
+            captured_lines: None,
+            partial_line: String::new(),
+            notifications: Vec::new(),
+            row_marks: vec![LineMarks::default(); height as usize],
+            saved_row_marks: Vec::new(),
+            last_exit_code: None,
+            tab_stops: default_tab_stops(0, width as usize),
             scrollback: std::collections::VecDeque::new(),
             scrollback_limit: DEFAULT_SCROLLBACK,
+            scrollback_bytes: 0,
+            spill_scrollback: config::SCROLLBACK_SPILL,
+            spill: None,
+            scrollback_cleared: false,
+            sync_snapshot: None,
+            images: Vec::new(),
+            saved_images: Vec::new(),
+            kitty_uploads: VecDeque::new(),
+            kitty_pending: None,
+            kitty_discarding: false,
+            cell_pixels: config::DEFAULT_CELL_PIXELS,
+            string_buffer: Vec::new(),
+            string_overflow: false,
+            dcs_sixel: false,
         }
     }
 
@@ -145,7 +341,7 @@ impl ScreenBuffer {
             return;
         }
 
-        let mut new_cells = vec![Cell::default(); (width as usize) * (height as usize)];
+        let mut new_cells = vec![StoredCell::BLANK; (width as usize) * (height as usize)];
 
         // Copy existing content
         for y in 0..self.height.min(height) {
@@ -157,341 +353,461 @@ impl ScreenBuffer {
         }
 
         self.cells = new_cells;
+        self.row_marks.resize(height as usize, LineMarks::default());
+        // Keep stops in surviving columns, new columns get the defaults
+        let old_width = self.tab_stops.len().min(width as usize);
+        self.tab_stops.truncate(old_width);
+        self.tab_stops.extend(default_tab_stops(old_width, width as usize));
         self.width = width;
         self.height = height;
+        // A frozen frame of the old size can't be shown - the app will redraw anyway
+        self.sync_snapshot = None;
         self.cursor_x = self.cursor_x.min(width.saturating_sub(1));
         self.cursor_y = self.cursor_y.min(height.saturating_sub(1));
-        // Reset scroll region to full screen
+        // Reset scroll region and margins to full screen
         self.scroll_top = 0;
         self.scroll_bottom = height.saturating_sub(1);
+        self.scroll_left = 0;
+        self.scroll_right = width.saturating_sub(1);
     }
 
     /// Process raw bytes from PTY
     pub fn process(&mut self, data: &[u8]) {
-        for &byte in data {
-            match self.parse_state {
-                ParseState::Normal => self.process_normal(byte),
-                ParseState::Escape => self.process_escape(byte),
-                ParseState::Csi => self.process_csi(byte),
-                ParseState::Osc => self.process_osc(byte),
-                ParseState::Dcs => self.process_dcs(byte),
-                ParseState::CharsetSelect => self.process_charset_select(byte),
-            }
-        }
-    }
-
-    fn process_normal(&mut self, byte: u8) {
-        // If we're in the middle of a UTF-8 sequence
-        if self.utf8_remaining > 0 {
-            if byte & 0xC0 == 0x80 {
-                // Valid continuation byte
-                self.utf8_buffer.push(byte);
-                self.utf8_remaining -= 1;
-                if self.utf8_remaining == 0 {
-                    // Complete UTF-8 sequence - copy buffer to avoid borrow issues
-                    let buf = std::mem::take(&mut self.utf8_buffer);
-                    if let Ok(s) = std::str::from_utf8(&buf) {
-                        for ch in s.chars() {
-                            self.put_char(ch);
-                        }
-                    }
-                }
-            } else {
-                // Invalid continuation - discard and process this byte normally
-                self.utf8_buffer.clear();
-                self.utf8_remaining = 0;
-                self.process_normal(byte);
-            }
-            return;
-        }
+        let mut parser = std::mem::take(&mut self.parser);
+        parser.advance(self, data);
+        self.parser = parser;
+    }
 
+    /// C0 and C1 control functions
+    fn execute_control(&mut self, byte: u8) {
         match byte {
-            0x1b => {
-                self.parse_state = ParseState::Escape;
-                self.parse_buffer.clear();
+            0x07 => {
+                // Bell - picked up by the app to mark the pane urgent
+                self.bell = true;
             }
-            0x07 => {} // Bell - ignore
             0x08 => {
                 // Backspace
                 self.cursor_x = self.cursor_x.saturating_sub(1);
             }
             0x09 => {
                 // Tab
-                self.cursor_x = ((self.cursor_x / 8) + 1) * 8;
-                if self.cursor_x >= self.width {
-                    self.cursor_x = self.width.saturating_sub(1);
-                }
+                self.tab_forward(1);
             }
-            0x0a => {
-                // Line feed
+            0x0a..=0x0c => {
+                // Line feed (VT and FF act the same)
+                self.capture_row(true);
                 self.line_feed();
             }
             0x0d => {
                 // Carriage return
-                self.cursor_x = 0;
+                self.carriage_return();
             }
-            // UTF-8 multi-byte sequence starters
-            0xC0..=0xDF => {
-                // 2-byte sequence
-                self.utf8_buffer.clear();
-                self.utf8_buffer.push(byte);
-                self.utf8_remaining = 1;
+            0x0e => {
+                // SO - Shift Out (use G1)
+                self.shift_out = true;
             }
-            0xE0..=0xEF => {
-                // 3-byte sequence
-                self.utf8_buffer.clear();
-                self.utf8_buffer.push(byte);
-                self.utf8_remaining = 2;
+            0x0f => {
+                // SI - Shift In (use G0)
+                self.shift_out = false;
             }
-            0xF0..=0xF7 => {
-                // 4-byte sequence
-                self.utf8_buffer.clear();
-                self.utf8_buffer.push(byte);
-                self.utf8_remaining = 3;
-            }
-            0x20..=0x7E => {
-                // Printable ASCII
-                self.put_char(byte as char);
-            }
-            _ => {} // Ignore other control chars and invalid bytes
-        }
-    }
-
-    fn process_escape(&mut self, byte: u8) {
-        match byte {
-            b'[' => {
-                self.parse_state = ParseState::Csi;
-                self.parse_buffer.clear();
+            0x84 => {
+                // IND - Index (move down, scroll if needed)
+                self.line_feed();
             }
-            b']' => {
-                self.parse_state = ParseState::Osc;
-                self.parse_buffer.clear();
+            0x85 => {
+                // NEL - Next Line
+                self.carriage_return();
+                self.line_feed();
             }
-            b'P' => {
-                // DCS - Device Control String
-                self.parse_state = ParseState::Dcs;
-                self.parse_buffer.clear();
-            }
-            b'_' | b'^' | b'X' => {
-                // APC, PM, SOS - consume until ST, treat like DCS
-                self.parse_state = ParseState::Dcs;
-                self.parse_buffer.clear();
-            }
-            b'(' | b')' | b'*' | b'+' | b'-' | b'.' | b'/' => {
-                // Character set designation - next byte is the charset ID
-                // ESC ( B = G0 to ASCII, ESC ) 0 = G1 to DEC graphics, etc.
-                // ESC - / . / / are for 96-character sets (VT220+)
-                // We need to consume the next byte too
-                self.parse_state = ParseState::CharsetSelect;
+            0x88 => {
+                // HTS - Horizontal Tab Set at the cursor column
+                if let Some(stop) = self.tab_stops.get_mut(self.cursor_x as usize) {
+                    *stop = true;
+                }
             }
-            b'M' => {
-                // Reverse line feed
-                if self.cursor_y > 0 {
+            0x8d => {
+                // RI - Reverse line feed (scroll down at the top margin)
+                if self.cursor_y == self.scroll_top {
+                    if self.cursor_in_lr_margins() {
+                        self.scroll_down();
+                    }
+                } else if self.cursor_y > 0 {
                     self.cursor_y -= 1;
                 }
-                self.parse_state = ParseState::Normal;
             }
-            b'7' | b's' => {
+            _ => {} // Ignore other control chars
+        }
+    }
+
+    fn execute_esc(&mut self, intermediates: &[u8], byte: u8) {
+        match (intermediates, byte) {
+            // IND, NEL, HTS, RI - 7-bit forms of the C1 controls
+            ([], b'D' | b'E' | b'H' | b'M') => self.execute_control(byte + 0x40),
+            ([], b'7' | b's') => {
                 // Save cursor (DECSC or ANSI)
-                self.saved_cursor = Some((self.cursor_x, self.cursor_y));
-                self.parse_state = ParseState::Normal;
+                self.save_cursor();
             }
-            b'8' | b'u' => {
+            ([], b'8' | b'u') => {
                 // Restore cursor (DECRC or ANSI)
-                if let Some((x, y)) = self.saved_cursor {
-                    self.cursor_x = x.min(self.width.saturating_sub(1));
-                    self.cursor_y = y.min(self.height.saturating_sub(1));
-                }
-                self.parse_state = ParseState::Normal;
+                self.restore_cursor();
             }
-            b'\\' => {
-                // ST (String Terminator) - just return to normal
-                self.parse_state = ParseState::Normal;
-            }
-            b'c' => {
+            ([], b'c') => {
                 // RIS - Full Reset
-                self.erase_all();
-                self.cursor_x = 0;
-                self.cursor_y = 0;
-                self.current_fg = None;
-                self.current_bg = None;
-                self.current_attrs.reset();
-                self.parse_state = ParseState::Normal;
+                self.reset();
             }
-            b'D' => {
-                // IND - Index (move down, scroll if needed)
-                self.line_feed();
-                self.parse_state = ParseState::Normal;
+            ([slot @ (b'(' | b')')], charset) => {
+                // Character set designation: ESC ( B = G0 to ASCII, ESC ) 0 = G1 to DEC graphics.
+                // Only G0/G1 and DEC Special Graphics matter - other sets are treated as ASCII
+                let slot = (*slot == b')') as usize;
+                self.charsets[slot] = if charset == b'0' { Charset::DecSpecial } else { Charset::Ascii };
             }
-            b'E' => {
-                // NEL - Next Line
-                self.cursor_x = 0;
-                self.line_feed();
-                self.parse_state = ParseState::Normal;
+            // Keypad modes (DECKPAM/DECKPNM) and other character sets are ignored
+            _ => {}
+        }
+    }
+
+    /// RIS - reset the terminal state (scrollback is kept)
+    fn reset(&mut self) {
+        self.erase_all();
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.current_fg = None;
+        self.current_bg = None;
+        self.current_attrs.reset();
+        self.sync_snapshot = None;
+        self.keyboard_stack.clear();
+        self.saved_keyboard_stack.clear();
+        self.images.clear();
+        self.saved_images.clear();
+        self.kitty_uploads.clear();
+        self.tab_stops = default_tab_stops(0, self.width as usize);
+        self.scroll_top = 0;
+        self.scroll_bottom = self.height.saturating_sub(1);
+        self.scroll_left = 0;
+        self.scroll_right = self.width.saturating_sub(1);
+        self.lr_margin_mode = false;
+        self.origin_mode = false;
+        self.insert_mode = false;
+        self.last_printed = None;
+        self.saved_cursor = None;
+        self.other_saved_cursor = None;
+        self.charsets = [Charset::Ascii; 2];
+        self.shift_out = false;
+        self.saved_row_marks.clear();
+        self.last_exit_code = None;
+        self.title_stack.clear();
+    }
+
+    /// Collect a byte of a DCS or APC string, dropping strings over the size limit
+    fn collect_string(&mut self, byte: u8) {
+        if self.string_overflow {
+            return;
+        }
+        if self.string_buffer.len() < image::MAX_SEQUENCE_BYTES {
+            self.string_buffer.push(byte);
+        } else {
+            self.string_overflow = true;
+            self.string_buffer = Vec::new();
+        }
+    }
+
+    /// DCS Pp... q <sixel data> - the body kept for the image is params, 'q' and the data
+    fn execute_sixel(&mut self) {
+        let body = std::mem::take(&mut self.string_buffer);
+        let Some(final_pos) = body.iter().position(|&b| b == b'q') else {
+            return;
+        };
+        let (pixel_width, pixel_height) = image::sixel_size(&body[final_pos + 1..]);
+        if pixel_width == 0 || pixel_height == 0 {
+            return;
+        }
+        let cols = image::cells_for_pixels(pixel_width, self.cell_pixels.0);
+        let rows = image::cells_for_pixels(pixel_height, self.cell_pixels.1);
+        self.place_image(ImageProtocol::Sixel, body, (cols, rows), (pixel_width, pixel_height));
+        // Sixel leaves the cursor on the image's last row, at its left column
+        let col = self.cursor_x;
+        for _ in 1..rows {
+            self.line_feed();
+        }
+        self.cursor_x = col;
+    }
+
+    fn execute_kitty_graphics(&mut self, cmd: KittyCommand) {
+        // Chunked transmissions (m=1) accumulate until the final chunk (m=0)
+        let more = cmd.get_num(b'm') == Some(1);
+        let mut cmd = match self.kitty_pending.take() {
+            Some(mut pending) => {
+                if !self.kitty_discarding {
+                    pending.payload.extend_from_slice(&cmd.payload);
+                }
+                pending
+            }
+            None => cmd,
+        };
+        if cmd.payload.len() > image::MAX_SEQUENCE_BYTES {
+            cmd.payload = Vec::new();
+            self.kitty_discarding = true;
+        }
+        if more {
+            self.kitty_pending = Some(cmd);
+            return;
+        }
+
+        let app_id = cmd.get_num(b'i');
+        let quiet = cmd.get_num(b'q').unwrap_or(0);
+        if std::mem::take(&mut self.kitty_discarding) {
+            if let Some(id) = app_id.filter(|_| quiet < 2) {
+                let response = format!("\x1b_Gi={};EFBIG:image too large\x1b\\", id);
+                self.response_queue.push(response.into_bytes());
             }
-            b'H' => {
-                // HTS - Horizontal Tab Set (ignore)
-                self.parse_state = ParseState::Normal;
+            return;
+        }
+        let action = cmd.get(b'a').unwrap_or("t").to_string();
+        let ok = match action.as_str() {
+            "q" => true,
+            "t" | "T" => {
+                let placed = if action == "T" { self.place_kitty_image(&cmd) } else { true };
+                if let Some(id) = app_id {
+                    self.kitty_uploads.retain(|(i, _)| *i != id);
+                    if self.kitty_uploads.len() >= image::MAX_KITTY_UPLOADS {
+                        self.kitty_uploads.pop_front();
+                    }
+                    self.make_image_room(cmd.payload.len());
+                    self.kitty_uploads.push_back((id, cmd.clone()));
+                }
+                placed
+            }
+            "p" => {
+                // Place a previously transmitted image, with this command's display keys
+                let upload = app_id.and_then(|id| self.kitty_uploads.iter().find(|(i, _)| *i == id));
+                match upload {
+                    Some((_, upload)) => {
+                        let mut placed = upload.clone();
+                        placed.keys.retain(|(k, _)| !matches!(k, b'c' | b'r' | b'C'));
+                        placed.keys.extend(cmd.keys.iter().filter(|(k, _)| matches!(k, b'c' | b'r' | b'C')).cloned());
+                        self.place_kitty_image(&placed)
+                    }
+                    None => false,
+                }
             }
-            b'=' | b'>' => {
-                // DECKPAM/DECKPNM - Keypad modes (ignore)
-                self.parse_state = ParseState::Normal;
+            "d" => {
+                // Delete all images, or those with the given id (upper case also frees data)
+                match (cmd.get(b'd').unwrap_or("a"), app_id) {
+                    ("i" | "I", Some(id)) => {
+                        self.images.retain(|img| img.protocol != ImageProtocol::Kitty || img.data_id != id);
+                        if cmd.get(b'd') == Some("I") {
+                            self.kitty_uploads.retain(|(i, _)| *i != id);
+                        }
+                    }
+                    ("a" | "A", _) => {
+                        self.images.retain(|img| img.protocol != ImageProtocol::Kitty);
+                    }
+                    _ => {}
+                }
+                return;
             }
-            _ => {
-                self.parse_state = ParseState::Normal;
+            _ => false,
+        };
+
+        // Acknowledge like a kitty terminal would, so apps don't wait for a reply
+        if let Some(id) = app_id {
+            if quiet == 0 || (!ok && quiet == 1) {
+                let status = if ok { "OK" } else { "ENOENT:image not found" };
+                let response = format!("\x1b_Gi={};{}\x1b\\", id, status);
+                self.response_queue.push(response.into_bytes());
             }
         }
     }
 
-    fn process_charset_select(&mut self, _byte: u8) {
-        // Just consume the charset identifier byte (B, 0, A, etc.)
-        // We don't actually change character sets, just ignore
-        self.parse_state = ParseState::Normal;
+    /// Anchor a kitty image at the cursor; returns false if its size is unknown
+    fn place_kitty_image(&mut self, cmd: &KittyCommand) -> bool {
+        let (pixel_width, pixel_height) = cmd.pixel_size();
+        let cols = cmd.get_num(b'c').map(|c| c.clamp(1, u16::MAX as u32) as u16)
+            .or((pixel_width > 0).then(|| image::cells_for_pixels(pixel_width, self.cell_pixels.0)));
+        let rows = cmd.get_num(b'r').map(|r| r.clamp(1, u16::MAX as u32) as u16)
+            .or((pixel_height > 0).then(|| image::cells_for_pixels(pixel_height, self.cell_pixels.1)));
+        let (Some(cols), Some(rows)) = (cols, rows) else {
+            return false;
+        };
+        self.place_image(ImageProtocol::Kitty, cmd.transmission_body(), (cols, rows), (pixel_width, pixel_height));
+        if let Some(last) = self.images.last_mut() {
+            last.data_id = cmd.get_num(b'i').unwrap_or(0);
+        }
+        // Unless C=1, the cursor moves past the image: right by its width, down to its last row
+        if cmd.get_num(b'C') != Some(1) {
+            let col = self.cursor_x;
+            for _ in 1..rows {
+                self.line_feed();
+            }
+            self.cursor_x = (col + cols).min(self.width.saturating_sub(1));
+        }
+        true
     }
 
-    fn process_dcs(&mut self, byte: u8) {
-        // DCS sequences end with ST (ESC \) or BEL
-        if byte == 0x07 {
-            // BEL terminates
-            self.parse_state = ParseState::Normal;
-        } else if byte == 0x1b {
-            // Might be start of ST (ESC \)
-            self.parse_buffer.push(byte);
-        } else if !self.parse_buffer.is_empty() && *self.parse_buffer.last().unwrap() == 0x1b && byte == b'\\' {
-            // ST received, end DCS
-            self.parse_state = ParseState::Normal;
-        } else if byte == 0x9c {
-            // C1 ST
-            self.parse_state = ParseState::Normal;
-        } else {
-            // Consume but don't store (we ignore DCS content)
-            // Clear buffer if it wasn't an ESC
-            if !self.parse_buffer.is_empty() && *self.parse_buffer.last().unwrap() == 0x1b {
-                self.parse_buffer.clear();
-            }
-            // Safety limit
-            if self.parse_buffer.len() > 4096 {
-                self.parse_state = ParseState::Normal;
+    /// Anchor an image at the cursor and blank the cells it covers
+    fn place_image(&mut self, protocol: ImageProtocol, data: Vec<u8>, size: (u16, u16), pixels: (u32, u32)) {
+        let (cols, rows) = size;
+        let blank = self.blank_cell();
+        let width = self.width as usize;
+        for y in self.cursor_y as usize..(self.cursor_y as usize + rows as usize).min(self.height as usize) {
+            for x in self.cursor_x as usize..(self.cursor_x as usize + cols as usize).min(width) {
+                self.cells[y * width + x] = blank;
             }
         }
+        if self.images.len() >= image::MAX_IMAGES {
+            self.images.remove(0);
+        }
+        self.make_image_room(data.len());
+        self.images.push(Image {
+            id: image::next_image_id(),
+            protocol,
+            data,
+            data_id: 0,
+            row: self.cursor_y as i32,
+            col: self.cursor_x,
+            cols,
+            rows,
+            pixel_width: pixels.0,
+            pixel_height: pixels.1,
+        });
     }
 
-    fn process_csi(&mut self, byte: u8) {
-        if byte >= 0x40 && byte <= 0x7e {
-            // Final byte - execute sequence
-            self.parse_buffer.push(byte);
-            self.execute_csi();
-            self.parse_state = ParseState::Normal;
-        } else {
-            self.parse_buffer.push(byte);
-            // Sanity limit
-            if self.parse_buffer.len() > 64 {
-                self.parse_state = ParseState::Normal;
-            }
-        }
-    }
-
-    fn process_osc(&mut self, byte: u8) {
-        // OSC sequences end with BEL (0x07) or ST (ESC \)
-        if byte == 0x07 {
-            self.execute_osc();
-            self.parse_state = ParseState::Normal;
-        } else if byte == 0x1b {
-            // Might be ST
-            self.parse_buffer.push(byte);
-        } else if !self.parse_buffer.is_empty() && *self.parse_buffer.last().unwrap() == 0x1b && byte == b'\\' {
-            // Remove the ESC we added
-            self.parse_buffer.pop();
-            self.execute_osc();
-            self.parse_state = ParseState::Normal;
-        } else {
-            self.parse_buffer.push(byte);
-            if self.parse_buffer.len() > 256 {
-                self.parse_state = ParseState::Normal;
-            }
+    /// Drop the oldest image data until `incoming` more bytes fit in MAX_IMAGE_BYTES:
+    /// images of the other screen first, then kitty uploads, then shown images
+    fn make_image_room(&mut self, incoming: usize) {
+        let bytes = |images: &[Image]| images.iter().map(|img| img.data.len()).sum::<usize>();
+        let mut total = bytes(&self.images)
+            + bytes(&self.saved_images)
+            + self.kitty_uploads.iter().map(|(_, cmd)| cmd.payload.len()).sum::<usize>();
+        while total + incoming > image::MAX_IMAGE_BYTES {
+            let freed = if !self.saved_images.is_empty() {
+                self.saved_images.remove(0).data.len()
+            } else if let Some((_, cmd)) = self.kitty_uploads.pop_front() {
+                cmd.payload.len()
+            } else if !self.images.is_empty() {
+                self.images.remove(0).data.len()
+            } else {
+                break;
+            };
+            total -= freed;
         }
     }
 
-    fn execute_osc(&mut self) {
+    fn execute_osc(&mut self, data: &[u8]) {
         // OSC format: Ps ; Pt where Ps is command number, Pt is parameter text
-        if let Ok(s) = std::str::from_utf8(&self.parse_buffer) {
-            if let Some((cmd, text)) = s.split_once(';') {
-                match cmd {
-                    "0" | "1" | "2" => {
-                        // 0 = set icon name and window title
-                        // 1 = set icon name
-                        // 2 = set window title
-                        self.title = Some(text.to_string());
+        if let Ok(s) = std::str::from_utf8(data) {
+            // 0 = set icon name and window title
+            // 1 = set icon name
+            // 2 = set window title
+            // 9 = notification (iTerm2), 777 = notify;title;body (urxvt/VTE)
+            // Other OSC commands are ignored
+            let notification = match s.split_once(';') {
+                Some(("0" | "1" | "2", text)) => {
+                    self.title = Some(text.to_string());
+                    None
+                }
+                Some(("9", body)) if !is_conemu_command(body) => Some(Notification {
+                    title: None,
+                    body: body.to_string(),
+                }),
+                Some(("133", mark)) => {
+                    self.set_prompt_mark(mark);
+                    None
+                }
+                Some(("777", rest)) => rest.strip_prefix("notify;").map(|rest| {
+                    let (title, body) = rest.split_once(';').unwrap_or((rest, ""));
+                    Notification {
+                        title: Some(title.to_string()),
+                        body: body.to_string(),
                     }
-                    _ => {} // Ignore other OSC commands
+                }),
+                _ => None,
+            };
+            if let Some(notification) = notification {
+                if self.notifications.len() >= MAX_PENDING_NOTIFICATIONS {
+                    self.notifications.remove(0);
                 }
+                self.notifications.push(notification);
             }
         }
-        self.parse_buffer.clear();
     }
 
-    fn execute_csi(&mut self) {
-        if self.parse_buffer.is_empty() {
-            return;
-        }
-
-        let final_byte = *self.parse_buffer.last().unwrap();
-        let params_str = String::from_utf8_lossy(&self.parse_buffer[..self.parse_buffer.len() - 1]);
-
-        // For SGR (m), we need special handling of colon sub-parameters
-        // For other sequences, just parse semicolon-separated values
-        if final_byte == b'm' {
-            let params_owned = params_str.to_string();
-            self.process_sgr_string(&params_owned);
-            return;
+    fn execute_csi(&mut self, params: &Params, intermediates: &[u8], action: u8) {
+        // Sequences with private markers or intermediates
+        match intermediates {
+            [] => {}
+            [b'?'] if matches!(action, b'h' | b'l') => {
+                // DEC private modes, several may be set at once
+                for mode in params.iter() {
+                    self.handle_private_mode(mode[0], action == b'h');
+                }
+                return;
+            }
+            [op @ (b'>' | b'<' | b'=' | b'?')] if action == b'u' => {
+                // Kitty keyboard protocol: push/pop/set/query enhancement flags
+                self.handle_keyboard_flags(*op, params);
+                return;
+            }
+            [b'?', b'$'] if action == b'p' => {
+                // DECRQM - Request private mode status: CSI ? Ps $ p
+                let mode = params.get(0).unwrap_or(0);
+                let status = self.private_mode_status(mode);
+                let response = format!("\x1b[?{};{}$y", mode, status);
+                self.response_queue.push(response.into_bytes());
+                return;
+            }
+            [b'>'] if action == b'c' => {
+                // Secondary DA - no firmware version to report
+                self.response_queue.push(b"\x1b[>0;0;0c".to_vec());
+                return;
+            }
+            // Anything else (XTMODKEYS, DECSTR, ...) is unsupported
+            _ => return,
         }
 
-        let params: Vec<u16> = params_str
-            .split(';')
-            .filter_map(|s| s.parse().ok())
-            .collect();
-
-        match final_byte {
+        match action {
+            b'm' => self.process_sgr(params),
             b'A' => {
                 // Cursor up
-                let n = params.first().copied().unwrap_or(1).max(1);
+                let n = params.arg(0, 1);
                 self.cursor_y = self.cursor_y.saturating_sub(n);
             }
             b'B' => {
                 // Cursor down
-                let n = params.first().copied().unwrap_or(1).max(1);
-                self.cursor_y = (self.cursor_y + n).min(self.height.saturating_sub(1));
+                let n = params.arg(0, 1);
+                self.cursor_y = self.cursor_y.saturating_add(n).min(self.height.saturating_sub(1));
             }
             b'C' => {
                 // Cursor forward
-                let n = params.first().copied().unwrap_or(1).max(1);
-                self.cursor_x = (self.cursor_x + n).min(self.width.saturating_sub(1));
+                let n = params.arg(0, 1);
+                self.cursor_x = self.cursor_x.saturating_add(n).min(self.width.saturating_sub(1));
             }
             b'D' => {
                 // Cursor back
-                let n = params.first().copied().unwrap_or(1).max(1);
+                let n = params.arg(0, 1);
                 self.cursor_x = self.cursor_x.saturating_sub(n);
             }
             b'H' | b'f' => {
                 // Cursor position
-                let row = params.first().copied().unwrap_or(1).max(1) - 1;
-                let col = params.get(1).copied().unwrap_or(1).max(1) - 1;
-                self.cursor_y = row.min(self.height.saturating_sub(1));
-                self.cursor_x = col.min(self.width.saturating_sub(1));
+                let row = params.arg(0, 1) - 1;
+                let col = params.arg(1, 1) - 1;
+                self.set_cursor_row(row);
+                self.set_cursor_col(col);
             }
             b'J' => {
                 // Erase in display
-                let mode = params.first().copied().unwrap_or(0);
+                let mode = params.get(0).unwrap_or(0);
                 match mode {
                     0 => self.erase_below(),
                     1 => self.erase_above(),
-                    2 | 3 => self.erase_all(),
+                    2 => self.erase_all(),
+                    3 => self.clear_scrollback(),
                     _ => {}
                 }
             }
             b'K' => {
                 // Erase in line
-                let mode = params.first().copied().unwrap_or(0);
+                let mode = params.get(0).unwrap_or(0);
                 match mode {
                     0 => self.erase_line_right(),
                     1 => self.erase_line_left(),
@@ -501,61 +817,89 @@ impl ScreenBuffer {
             }
             b'L' => {
                 // Insert lines
-                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                let n = params.arg(0, 1) as usize;
                 self.insert_lines(n);
             }
+            b'b' => {
+                // REP - Repeat the last printed character
+                let n = params.arg(0, 1) as usize;
+                if let Some(ch) = self.last_printed {
+                    self.put_str(&ch.to_string().repeat(n));
+                }
+            }
             b'M' => {
                 // Delete lines
-                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                let n = params.arg(0, 1) as usize;
                 self.delete_lines(n);
             }
             b'r' => {
                 // DECSTBM - Set Top and Bottom Margins (scroll region)
-                let top = params.first().copied().unwrap_or(1).max(1) - 1;
-                let bottom = params.get(1).copied().unwrap_or(self.height).max(1) - 1;
+                let top = params.arg(0, 1) - 1;
+                let bottom = params.arg(1, self.height) - 1;
                 if top < bottom && bottom < self.height {
                     self.scroll_top = top;
                     self.scroll_bottom = bottom;
                     // Cursor moves to home position after setting scroll region
-                    self.cursor_x = 0;
-                    self.cursor_y = 0;
+                    self.cursor_home();
+                }
+            }
+            b's' if self.lr_margin_mode => {
+                // DECSLRM - Set Left and Right Margins (only while DECLRMM is on)
+                let left = params.arg(0, 1) - 1;
+                let right = params.arg(1, self.width) - 1;
+                if left < right && right < self.width {
+                    self.scroll_left = left;
+                    self.scroll_right = right;
+                    self.cursor_home();
                 }
             }
-            b'G' => {
+            b'G' | b'`' => {
                 // Cursor horizontal absolute (column)
-                let col = params.first().copied().unwrap_or(1).max(1) - 1;
-                self.cursor_x = col.min(self.width.saturating_sub(1));
+                let col = params.arg(0, 1) - 1;
+                self.set_cursor_col(col);
             }
             b's' => {
-                // Save cursor position (ANSI)
-                self.saved_cursor = Some((self.cursor_x, self.cursor_y));
+                // Save cursor (ANSI SCOSC, same as DECSC)
+                self.save_cursor();
             }
             b'u' => {
-                // Restore cursor position (ANSI)
-                if let Some((x, y)) = self.saved_cursor {
-                    self.cursor_x = x.min(self.width.saturating_sub(1));
-                    self.cursor_y = y.min(self.height.saturating_sub(1));
+                // Restore cursor (ANSI SCORC, same as DECRC)
+                self.restore_cursor();
+            }
+            b'I' => {
+                // CHT - Cursor Horizontal Forward Tabulation
+                let n = params.arg(0, 1);
+                self.tab_forward(n);
+            }
+            b'Z' => {
+                // CBT - Cursor Backward Tabulation
+                let n = params.arg(0, 1);
+                self.tab_backward(n);
+            }
+            b'g' => {
+                // TBC - Tab Clear: 0 = at cursor, 3 = all
+                match params.get(0).unwrap_or(0) {
+                    0 => {
+                        if let Some(stop) = self.tab_stops.get_mut(self.cursor_x as usize) {
+                            *stop = false;
+                        }
+                    }
+                    3 => self.tab_stops.fill(false),
+                    _ => {}
                 }
             }
             b'd' => {
                 // Cursor vertical absolute (line)
-                let row = params.first().copied().unwrap_or(1).max(1) - 1;
-                self.cursor_y = row.min(self.height.saturating_sub(1));
-            }
-            b'h' | b'l' => {
-                // Set/reset mode
-                let is_set = final_byte == b'h';
-                // Check for private mode (? prefix)
-                if params_str.starts_with('?') {
-                    let mode: Option<u16> = params_str[1..].parse().ok();
-                    if let Some(mode) = mode {
-                        self.handle_private_mode(mode, is_set);
-                    }
-                }
+                let row = params.arg(0, 1) - 1;
+                self.set_cursor_row(row);
+            }
+            b'h' | b'l' if params.iter().any(|mode| mode[0] == 4) => {
+                // IRM - Insert/Replace mode
+                self.insert_mode = action == b'h';
             }
             b'X' => {
                 // Erase characters (replace with spaces)
-                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                let n = params.arg(0, 1) as usize;
                 let blank = self.blank_cell();
                 let y = self.cursor_y as usize;
                 let width = self.width as usize;
@@ -568,62 +912,31 @@ impl ScreenBuffer {
             }
             b'P' => {
                 // Delete characters (shift left)
-                let n = params.first().copied().unwrap_or(1).max(1) as usize;
-                let y = self.cursor_y as usize;
-                let width = self.width as usize;
-                let x = self.cursor_x as usize;
-                let blank = self.blank_cell();
-                // Shift cells left
-                for i in x..width.saturating_sub(n) {
-                    self.cells[y * width + i] = self.cells[y * width + i + n];
-                }
-                // Fill end with blanks
-                for i in width.saturating_sub(n)..width {
-                    self.cells[y * width + i] = blank;
-                }
+                let n = params.arg(0, 1) as usize;
+                self.delete_chars(n);
             }
             b'@' => {
                 // Insert characters (shift right)
-                let n = params.first().copied().unwrap_or(1).max(1) as usize;
-                let y = self.cursor_y as usize;
-                let width = self.width as usize;
-                let x = self.cursor_x as usize;
-                let blank = self.blank_cell();
-                // Shift cells right
-                for i in (x + n..width).rev() {
-                    self.cells[y * width + i] = self.cells[y * width + i - n];
-                }
-                // Fill with blanks
-                for i in x..(x + n).min(width) {
-                    self.cells[y * width + i] = blank;
-                }
-            }
-            b'q' => {
-                // DECSCUSR - Set cursor style (ignore, we manage cursor ourselves)
-                // CSI Ps SP q - but SP is space (0x20), handled separately
-            }
-            b' ' => {
-                // Could be start of CSI Ps SP q sequence - already consumed
-                // The 'q' would be the final byte, but we got ' ' as final
-                // This means malformed sequence, ignore
+                let n = params.arg(0, 1) as usize;
+                self.insert_blank_chars(n);
             }
             b'S' => {
                 // SU - Scroll Up (pan down)
-                let n = params.first().copied().unwrap_or(1).max(1);
+                let n = params.arg(0, 1);
                 for _ in 0..n {
                     self.scroll_up();
                 }
             }
             b'T' => {
                 // SD - Scroll Down (pan up)
-                let n = params.first().copied().unwrap_or(1).max(1);
+                let n = params.arg(0, 1);
                 for _ in 0..n {
                     self.scroll_down();
                 }
             }
             b'n' => {
                 // DSR - Device Status Report
-                let param = params.first().copied().unwrap_or(0);
+                let param = params.get(0).unwrap_or(0);
                 match param {
                     5 => {
                         // Status report - respond with "OK"
@@ -631,46 +944,196 @@ impl ScreenBuffer {
                     }
                     6 => {
                         // Cursor position report - respond with CSI row;col R
+                        // (relative to the margins in origin mode)
+                        let (row, col) = if self.origin_mode {
+                            (
+                                self.cursor_y.saturating_sub(self.scroll_top),
+                                self.cursor_x.saturating_sub(self.scroll_left),
+                            )
+                        } else {
+                            (self.cursor_y, self.cursor_x)
+                        };
+                        let response = format!("\x1b[{};{}R", row + 1, col + 1);
+                        self.response_queue.push(response.into_bytes());
+                    }
+                    _ => {}
+                }
+            }
+            b't' => {
+                // Window manipulation (XTWINOPS) - size reports used by image tools and
+                // the title stack (the icon name alone, Ps2 = 1, isn't tracked)
+                match params.get(0).unwrap_or(0) {
+                    22 if params.get(1).unwrap_or(0) != 1 => {
+                        if self.title_stack.len() >= MAX_TITLE_STACK {
+                            self.title_stack.remove(0);
+                        }
+                        self.title_stack.push(self.title.clone());
+                    }
+                    23 if params.get(1).unwrap_or(0) != 1 => {
+                        if let Some(title) = self.title_stack.pop() {
+                            self.title = title;
+                        }
+                    }
+                    14 => {
+                        // Text area size in pixels
                         let response = format!(
-                            "\x1b[{};{}R",
-                            self.cursor_y + 1,
-                            self.cursor_x + 1
+                            "\x1b[4;{};{}t",
+                            self.height as u32 * self.cell_pixels.1 as u32,
+                            self.width as u32 * self.cell_pixels.0 as u32
                         );
                         self.response_queue.push(response.into_bytes());
                     }
+                    16 => {
+                        // Cell size in pixels
+                        let response = format!("\x1b[6;{};{}t", self.cell_pixels.1, self.cell_pixels.0);
+                        self.response_queue.push(response.into_bytes());
+                    }
+                    18 => {
+                        // Text area size in characters
+                        let response = format!("\x1b[8;{};{}t", self.height, self.width);
+                        self.response_queue.push(response.into_bytes());
+                    }
                     _ => {}
                 }
             }
-            b'c' => {
+            b'c' if params.get(0).unwrap_or(0) == 0 => {
                 // DA - Device Attributes
-                if params.first().copied().unwrap_or(0) == 0 {
-                    // Primary DA - report as VT100
-                    self.response_queue.push(b"\x1b[?1;0c".to_vec());
-                }
+                // Primary DA - report as VT100
+                self.response_queue.push(b"\x1b[?1;0c".to_vec());
+            }
+            _ => {}
+        }
+    }
+
+    /// Record an OSC 133 shell integration mark on the cursor row
+    fn set_prompt_mark(&mut self, mark: &str) {
+        let mut parts = mark.split(';');
+        let kind = parts.next().unwrap_or("");
+        let Some(marks) = self.row_marks.get_mut(self.cursor_y as usize) else {
+            return;
+        };
+        match kind {
+            "A" => marks.prompt = true,
+            "B" => marks.input = true,
+            "C" => {
+                // A command is running - the previous one's status no longer applies
+                marks.output = true;
+                self.last_exit_code = None;
+            }
+            "D" => {
+                let exit_code = parts.next().and_then(|code| code.parse().ok());
+                marks.finished = true;
+                marks.exit_code = exit_code;
+                self.last_exit_code = exit_code;
             }
             _ => {}
         }
     }
 
+    /// Move the cursor to the home position (the margins' corner in origin mode)
+    fn cursor_home(&mut self) {
+        self.set_cursor_row(0);
+        self.set_cursor_col(0);
+    }
+
+    /// Move the cursor to a row, relative to the top margin in origin mode
+    fn set_cursor_row(&mut self, row: u16) {
+        self.cursor_y = if self.origin_mode {
+            self.scroll_top.saturating_add(row).min(self.scroll_bottom)
+        } else {
+            row.min(self.height.saturating_sub(1))
+        };
+    }
+
+    /// Move the cursor to a column, relative to the left margin in origin mode
+    fn set_cursor_col(&mut self, col: u16) {
+        self.cursor_x = if self.origin_mode {
+            self.scroll_left.saturating_add(col).min(self.scroll_right)
+        } else {
+            col.min(self.width.saturating_sub(1))
+        };
+    }
+
+    fn cursor_in_lr_margins(&self) -> bool {
+        self.cursor_x >= self.scroll_left && self.cursor_x <= self.scroll_right
+    }
+
+    /// Move the cursor to the left margin, unless already left of it
+    fn carriage_return(&mut self) {
+        self.cursor_x = if self.cursor_x >= self.scroll_left { self.scroll_left } else { 0 };
+    }
+
+    /// Move the cursor to the nth next tab stop (or the last column, or the
+    /// right margin when inside the margins)
+    fn tab_forward(&mut self, n: u16) {
+        let last = if self.cursor_in_lr_margins() { self.scroll_right } else { self.width.saturating_sub(1) } as usize;
+        let mut x = self.cursor_x as usize;
+        for _ in 0..n {
+            x = (x + 1..last)
+                .find(|&col| self.tab_stops.get(col).copied().unwrap_or(false))
+                .unwrap_or(last);
+        }
+        self.cursor_x = x as u16;
+    }
+
+    /// Move the cursor to the nth previous tab stop (or the first column)
+    fn tab_backward(&mut self, n: u16) {
+        let mut x = self.cursor_x as usize;
+        for _ in 0..n {
+            x = (0..x)
+                .rev()
+                .find(|&col| self.tab_stops.get(col).copied().unwrap_or(false))
+                .unwrap_or(0);
+        }
+        self.cursor_x = x as u16;
+    }
+
     fn handle_private_mode(&mut self, mode: u16, is_set: bool) {
         match mode {
+            6 => {
+                // DECOM - Origin mode, cursor homes to the new origin
+                self.origin_mode = is_set;
+                self.cursor_home();
+            }
+            69 => {
+                // DECLRMM - Enable left/right margins (disabling resets them)
+                self.lr_margin_mode = is_set;
+                if !is_set {
+                    self.scroll_left = 0;
+                    self.scroll_right = self.width.saturating_sub(1);
+                }
+            }
             25 => {
                 // Cursor visibility
                 self.cursor_visible = is_set;
             }
             1049 => {
-                // Alternate screen buffer with saved cursor
+                // Alternate screen buffer, saving the cursor and clearing the screen on entry
+                if is_set {
+                    if !self.in_alternate_screen {
+                        self.save_cursor();
+                        self.enter_alternate_screen();
+                        self.erase_all();
+                    }
+                } else if self.in_alternate_screen {
+                    self.leave_alternate_screen();
+                    self.restore_cursor();
+                }
+            }
+            47 => {
+                // Alternate screen buffer, contents kept between switches
                 if is_set {
                     self.enter_alternate_screen();
                 } else {
                     self.leave_alternate_screen();
                 }
             }
-            47 | 1047 => {
-                // Alternate screen buffer (without cursor save)
+            1047 => {
+                // Alternate screen buffer, cleared when leaving it
                 if is_set {
                     self.enter_alternate_screen();
-                } else {
+                } else if self.in_alternate_screen {
+                    self.erase_all();
                     self.leave_alternate_screen();
                 }
             }
@@ -693,328 +1156,560 @@ impl ScreenBuffer {
                 // DECCKM - Application cursor keys mode
                 // Changes arrow key sequences - we just ignore
             }
+            2026 => {
+                // Synchronized output - freeze what we show until the update ends
+                if is_set {
+                    self.begin_sync_update();
+                } else {
+                    self.sync_snapshot = None;
+                }
+            }
             _ => {} // Ignore other private modes
         }
     }
 
-    fn enter_alternate_screen(&mut self) {
-        if self.in_alternate_screen {
-            return;
-        }
-        // Save current screen and cursor
-        self.saved_cells = Some(self.cells.clone());
-        self.saved_cursor = Some((self.cursor_x, self.cursor_y));
-        self.in_alternate_screen = true;
-        // Clear the screen for the alternate buffer
-        self.erase_all();
-        self.cursor_x = 0;
-        self.cursor_y = 0;
+    /// DECRPM status for a private mode: 1 = set, 2 = reset, 0 = not recognized
+    fn private_mode_status(&self, mode: u16) -> u8 {
+        let is_set = match mode {
+            6 => self.origin_mode,
+            25 => self.cursor_visible,
+            69 => self.lr_margin_mode,
+            47 | 1047 | 1049 => self.in_alternate_screen,
+            2026 => self.sync_snapshot.is_some(),
+            _ => return 0,
+        };
+        if is_set { 1 } else { 2 }
     }
 
-    fn leave_alternate_screen(&mut self) {
-        if !self.in_alternate_screen {
+    fn begin_sync_update(&mut self) {
+        // Nested begin keeps the original snapshot (and its timeout)
+        if self.sync_snapshot.is_some() {
             return;
         }
-        // Restore saved screen and cursor
-        if let Some(saved) = self.saved_cells.take() {
-            // Only restore if dimensions match
-            if saved.len() == self.cells.len() {
-                self.cells = saved;
-            } else {
-                // Dimensions changed, just clear
-                self.erase_all();
-            }
-        } else {
-            self.erase_all();
-        }
-        if let Some((x, y)) = self.saved_cursor.take() {
-            self.cursor_x = x.min(self.width.saturating_sub(1));
-            self.cursor_y = y.min(self.height.saturating_sub(1));
-        } else {
-            self.cursor_x = 0;
-            self.cursor_y = 0;
-        }
-        self.in_alternate_screen = false;
+        self.sync_snapshot = Some(SyncSnapshot {
+            cells: self.cells.clone(),
+            cursor: (self.cursor_x, self.cursor_y),
+            cursor_visible: self.cursor_visible,
+            started: Instant::now(),
+        });
     }
 
-    /// Process SGR string with proper colon sub-parameter handling
-    fn process_sgr_string(&mut self, params_str: &str) {
-        if params_str.is_empty() {
-            // ESC[m = reset
-            self.current_fg = None;
-            self.current_bg = None;
-            self.current_attrs.reset();
-            return;
+    /// Whether a synchronized update is in progress (rendering shows the frozen frame)
+    pub fn in_sync_update(&self) -> bool {
+        self.sync_snapshot.is_some()
+    }
+
+    /// Release a synchronized update held longer than `timeout`
+    /// Returns true if the frozen frame was dropped and the pane needs a redraw
+    pub fn expire_sync_update(&mut self, timeout: Duration) -> bool {
+        match self.sync_snapshot {
+            Some(ref snap) if snap.started.elapsed() >= timeout => {
+                self.sync_snapshot = None;
+                true
+            }
+            _ => false,
         }
+    }
 
-        // Handle colon sub-parameters by converting to semicolons
-        // But special-case underline styles (4:N) to just set underline
-        let mut processed = String::with_capacity(params_str.len());
-        for segment in params_str.split(';') {
-            if !processed.is_empty() {
-                processed.push(';');
-            }
-
-            if segment.contains(':') {
-                // Has colon sub-parameters
-                let parts: Vec<&str> = segment.split(':').collect();
-                if let Some(first) = parts.first() {
-                    match *first {
-                        "4" => {
-                            // Underline style - check if disabling (4:0) or enabling
-                            if parts.get(1) == Some(&"0") {
-                                processed.push_str("24"); // Turn off underline
-                            } else {
-                                processed.push_str("4"); // Just enable underline
-                            }
-                        }
-                        "58" => {
-                            // Underline color - ignore entirely
-                        }
-                        "38" | "48" => {
-                            // Color with colon separator - convert to semicolon format
-                            processed.push_str(&parts.join(";"));
-                        }
-                        _ => {
-                            // Unknown - just use first part
-                            processed.push_str(first);
-                        }
+    fn handle_keyboard_flags(&mut self, op: u8, params: &Params) {
+        match op {
+            b'>' => {
+                // Push flags
+                let flags = KeyboardFlags(params.get(0).unwrap_or(0) as u8 & KeyboardFlags::SUPPORTED);
+                if self.keyboard_stack.len() >= KEYBOARD_STACK_LIMIT {
+                    self.keyboard_stack.remove(0);
+                }
+                self.keyboard_stack.push(flags);
+            }
+            b'<' => {
+                // Pop N entries (default 1)
+                let n = params.arg(0, 1) as usize;
+                let keep = self.keyboard_stack.len().saturating_sub(n);
+                self.keyboard_stack.truncate(keep);
+            }
+            b'=' => {
+                // Modify current flags: mode 1 = replace, 2 = set bits, 3 = clear bits
+                let flags = params.get(0).unwrap_or(0) as u8 & KeyboardFlags::SUPPORTED;
+                let mode = params.arg(1, 1);
+                if self.keyboard_stack.is_empty() {
+                    self.keyboard_stack.push(KeyboardFlags::default());
+                }
+                if let Some(current) = self.keyboard_stack.last_mut() {
+                    match mode {
+                        1 => current.0 = flags,
+                        2 => current.0 |= flags,
+                        3 => current.0 &= !flags,
+                        _ => {}
                     }
                 }
-            } else {
-                processed.push_str(segment);
             }
+            b'?' => {
+                // Query current flags
+                let response = format!("\x1b[?{}u", self.keyboard_flags().0);
+                self.response_queue.push(response.into_bytes());
+            }
+            _ => {}
         }
+    }
 
-        // Now parse as normal semicolon-separated params
-        let params: Vec<u16> = processed
-            .split(';')
-            .filter_map(|s| s.parse().ok())
-            .collect();
+    /// DECSC - save cursor position, attributes, charsets and origin mode
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            fg: self.current_fg,
+            bg: self.current_bg,
+            attrs: self.current_attrs,
+            charsets: self.charsets,
+            shift_out: self.shift_out,
+            origin_mode: self.origin_mode,
+            wrap_pending: self.wrap_pending,
+        });
+    }
 
-        self.process_sgr(&params);
+    /// DECRC - restore the saved cursor state (or reset it if nothing was saved)
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor.unwrap_or(SavedCursor {
+            x: 0,
+            y: 0,
+            fg: None,
+            bg: None,
+            attrs: Attrs::default(),
+            charsets: [Charset::Ascii; 2],
+            shift_out: false,
+            origin_mode: false,
+            wrap_pending: None,
+        });
+        self.cursor_x = saved.x.min(self.width.saturating_sub(1));
+        self.cursor_y = saved.y.min(self.height.saturating_sub(1));
+        self.current_fg = saved.fg;
+        self.current_bg = saved.bg;
+        self.current_attrs = saved.attrs;
+        self.charsets = saved.charsets;
+        self.shift_out = saved.shift_out;
+        self.origin_mode = saved.origin_mode;
+        self.wrap_pending = saved.wrap_pending;
     }
 
-    fn process_sgr(&mut self, params: &[u16]) {
-        if params.is_empty() {
-            // Reset all
-            self.current_fg = None;
-            self.current_bg = None;
-            self.current_attrs.reset();
+    /// Swap the shown screen with the inactive one (cells, images, DECSC slot)
+    fn swap_screens(&mut self) {
+        let blank = vec![StoredCell::BLANK; self.cells.len()];
+        // The inactive screen is discarded if the size changed since it was shown
+        let other = self.saved_cells.take().filter(|cells| cells.len() == blank.len()).unwrap_or(blank);
+        self.saved_cells = Some(std::mem::replace(&mut self.cells, other));
+        std::mem::swap(&mut self.images, &mut self.saved_images);
+        std::mem::swap(&mut self.saved_cursor, &mut self.other_saved_cursor);
+        let marks = vec![LineMarks::default(); self.row_marks.len()];
+        let other_marks = Some(std::mem::take(&mut self.saved_row_marks))
+            .filter(|m| m.len() == marks.len())
+            .unwrap_or(marks);
+        self.saved_row_marks = std::mem::replace(&mut self.row_marks, other_marks);
+    }
+
+    fn enter_alternate_screen(&mut self) {
+        if self.in_alternate_screen {
+            return;
+        }
+        self.swap_screens();
+        self.in_alternate_screen = true;
+        self.saved_keyboard_stack = std::mem::take(&mut self.keyboard_stack);
+    }
+
+    fn leave_alternate_screen(&mut self) {
+        if !self.in_alternate_screen {
             return;
         }
+        self.swap_screens();
+        self.in_alternate_screen = false;
+        self.keyboard_stack = std::mem::take(&mut self.saved_keyboard_stack);
+    }
 
-        let mut i = 0;
-        while i < params.len() {
-            match params[i] {
-                0 => {
+    fn process_sgr(&mut self, params: &Params) {
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            match param {
+                [0] => {
                     // Reset all
                     self.current_fg = None;
                     self.current_bg = None;
                     self.current_attrs.reset();
                 }
-                1 => self.current_attrs.set(Attrs::BOLD),
-                2 => self.current_attrs.set(Attrs::DIM),
-                3 => self.current_attrs.set(Attrs::ITALIC),
-                4 => self.current_attrs.set(Attrs::UNDERLINE),
-                5 | 6 => {} // Blink - ignore
-                7 => self.current_attrs.set(Attrs::REVERSE),
-                8 => {} // Hidden/invisible - ignore
-                9 => self.current_attrs.set(Attrs::STRIKETHROUGH),
-                21 => self.current_attrs.clear(Attrs::BOLD), // Double underline or bold off
-                22 => {
+                [1] => self.current_attrs.set(Attrs::BOLD),
+                [2] => self.current_attrs.set(Attrs::DIM),
+                [3] => self.current_attrs.set(Attrs::ITALIC),
+                // Underline styles (4:N) are drawn as a plain underline, 4:0 turns it off
+                [4, 0] => self.current_attrs.clear(Attrs::UNDERLINE),
+                [4, ..] => self.current_attrs.set(Attrs::UNDERLINE),
+                [5 | 6] => {} // Blink - ignore
+                [7] => self.current_attrs.set(Attrs::REVERSE),
+                [8] => {} // Hidden/invisible - ignore
+                [9] => self.current_attrs.set(Attrs::STRIKETHROUGH),
+                [21] => self.current_attrs.clear(Attrs::BOLD), // Double underline or bold off
+                [22] => {
                     self.current_attrs.clear(Attrs::BOLD);
                     self.current_attrs.clear(Attrs::DIM);
                 }
-                23 => self.current_attrs.clear(Attrs::ITALIC),
-                24 => self.current_attrs.clear(Attrs::UNDERLINE),
-                27 => self.current_attrs.clear(Attrs::REVERSE),
-                29 => self.current_attrs.clear(Attrs::STRIKETHROUGH),
-                30..=37 => self.current_fg = Some(ansi_to_color(params[i] - 30)),
-                38 => {
+                [23] => self.current_attrs.clear(Attrs::ITALIC),
+                [24] => self.current_attrs.clear(Attrs::UNDERLINE),
+                [27] => self.current_attrs.clear(Attrs::REVERSE),
+                [29] => self.current_attrs.clear(Attrs::STRIKETHROUGH),
+                [n @ 30..=37] => self.current_fg = Some(ansi_to_color(n - 30)),
+                [38, sub @ ..] => {
                     // Extended foreground
-                    if i + 2 < params.len() && params[i + 1] == 5 {
-                        // 256 color
-                        self.current_fg = Some(Color::AnsiValue(params[i + 2] as u8));
-                        i += 2;
-                    } else if i + 4 < params.len() && params[i + 1] == 2 {
-                        // Truecolor
-                        self.current_fg = Some(Color::Rgb {
-                            r: params[i + 2] as u8,
-                            g: params[i + 3] as u8,
-                            b: params[i + 4] as u8,
-                        });
-                        i += 4;
+                    if let Some(color) = extended_color(sub, &mut params) {
+                        self.current_fg = Some(color);
                     }
                 }
-                39 => self.current_fg = None,
-                40..=47 => self.current_bg = Some(ansi_to_color(params[i] - 40)),
-                48 => {
+                [39] => self.current_fg = None,
+                [n @ 40..=47] => self.current_bg = Some(ansi_to_color(n - 40)),
+                [48, sub @ ..] => {
                     // Extended background
-                    if i + 2 < params.len() && params[i + 1] == 5 {
-                        // 256 color
-                        self.current_bg = Some(Color::AnsiValue(params[i + 2] as u8));
-                        i += 2;
-                    } else if i + 4 < params.len() && params[i + 1] == 2 {
-                        // Truecolor
-                        self.current_bg = Some(Color::Rgb {
-                            r: params[i + 2] as u8,
-                            g: params[i + 3] as u8,
-                            b: params[i + 4] as u8,
-                        });
-                        i += 4;
+                    if let Some(color) = extended_color(sub, &mut params) {
+                        self.current_bg = Some(color);
                     }
                 }
-                49 => self.current_bg = None,
-                90..=97 => self.current_fg = Some(ansi_to_bright_color(params[i] - 90)),
-                100..=107 => self.current_bg = Some(ansi_to_bright_color(params[i] - 100)),
+                [49] => self.current_bg = None,
+                [58, sub @ ..] => {
+                    // Underline colour - consumed but not drawn
+                    extended_color(sub, &mut params);
+                }
+                [n @ 90..=97] => self.current_fg = Some(ansi_to_bright_color(n - 90)),
+                [n @ 100..=107] => self.current_bg = Some(ansi_to_bright_color(n - 100)),
                 _ => {}
             }
-            i += 1;
         }
     }
 
     fn put_char(&mut self, ch: char) {
-        if self.cursor_x >= self.width {
-            self.cursor_x = 0;
-            self.line_feed();
+        self.wrap_if_pending();
+        if self.insert_mode {
+            self.insert_blank_chars(1);
         }
+        let ch = if self.charsets[self.shift_out as usize] == Charset::DecSpecial {
+            dec_special_char(ch)
+        } else {
+            ch
+        };
 
         let idx = (self.cursor_y as usize) * (self.width as usize) + (self.cursor_x as usize);
         if idx < self.cells.len() {
-            self.cells[idx] = Cell {
-                ch,
-                fg: self.current_fg,
-                bg: self.current_bg,
-                attrs: self.current_attrs,
-            };
+            let style = self.style_id(self.current_fg, self.current_bg, self.current_attrs);
+            self.cells[idx] = StoredCell { ch, style };
         }
+        self.last_printed = Some(ch);
+        let at_right_margin = self.cursor_x == self.scroll_right;
         self.cursor_x += 1;
+        if at_right_margin {
+            self.wrap_pending = Some((self.cursor_x, self.cursor_y));
+        }
+    }
+
+    /// A run of printable text: like put_char for each character, but the style
+    /// is looked up once per row and the cells are written directly
+    fn put_str(&mut self, s: &str) {
+        let special = self.charsets[self.shift_out as usize] == Charset::DecSpecial;
+        if self.insert_mode || special || self.cells.is_empty() {
+            s.chars().for_each(|ch| self.put_char(ch));
+            return;
+        }
+        let mut chars = s.chars().peekable();
+        while chars.peek().is_some() {
+            self.wrap_if_pending();
+            // A wrap may have compacted the style table, so the id is looked up again
+            let style = self.style_id(self.current_fg, self.current_bg, self.current_attrs);
+            let last = if self.cursor_x <= self.scroll_right { self.scroll_right } else { self.width - 1 };
+            let row = self.cursor_y as usize * self.width as usize;
+            while self.cursor_x <= last {
+                let Some(ch) = chars.next() else {
+                    break;
+                };
+                self.cells[row + self.cursor_x as usize] = StoredCell { ch, style };
+                self.last_printed = Some(ch);
+                if self.cursor_x == self.scroll_right {
+                    self.wrap_pending = Some((self.cursor_x + 1, self.cursor_y));
+                }
+                self.cursor_x += 1;
+            }
+        }
+    }
+
+    /// Move to the start of the next line if the last character filled the row
+    fn wrap_if_pending(&mut self) {
+        let wrap_at_margin = self.wrap_pending.take() == Some((self.cursor_x, self.cursor_y));
+        if wrap_at_margin || self.cursor_x >= self.width {
+            self.capture_row(false);
+            self.cursor_x = if wrap_at_margin { self.scroll_left } else { 0 };
+            self.line_feed();
+        }
+    }
+
+    /// Add the cursor row to the line being captured for output triggers,
+    /// committing the line if it ended with a line feed (rather than a wrap)
+    fn capture_row(&mut self, complete: bool) {
+        if self.in_alternate_screen {
+            return;
+        }
+        let Some(lines) = self.captured_lines.as_mut() else {
+            return;
+        };
+        let width = self.width as usize;
+        let start = self.cursor_y as usize * width;
+        let room = MAX_CAPTURED_LINE_CHARS.saturating_sub(self.partial_line.chars().count());
+        self.partial_line
+            .extend(self.cells[start..start + width].iter().map(|c| c.ch).take(room));
+        if complete {
+            let line = std::mem::take(&mut self.partial_line);
+            lines.push_back(line.trim_end().to_string());
+            if lines.len() > MAX_CAPTURED_LINES {
+                lines.pop_front();
+            }
+        }
     }
 
     fn line_feed(&mut self) {
-        if self.cursor_y >= self.scroll_bottom {
-            // At bottom of scroll region - scroll up
-            self.scroll_up();
+        if self.cursor_y == self.scroll_bottom {
+            // At bottom of scroll region - scroll up (only inside the left/right margins)
+            if self.cursor_in_lr_margins() {
+                self.scroll_up();
+            }
         } else if self.cursor_y + 1 < self.height {
             self.cursor_y += 1;
         }
     }
 
+    /// Column range covered by the left/right margins
+    fn margin_columns(&self) -> std::ops::Range<usize> {
+        self.scroll_left as usize..(self.scroll_right as usize + 1).min(self.width as usize)
+    }
+
+    /// Whether the left/right margins span the whole line
+    fn full_width_margins(&self) -> bool {
+        self.margin_columns() == (0..self.width as usize)
+    }
+
+    /// Copy a row's margin columns onto another row
+    fn copy_row_in_margins(&mut self, src: usize, dst: usize) {
+        let width = self.width as usize;
+        let cols = self.margin_columns();
+        self.cells
+            .copy_within(src * width + cols.start..src * width + cols.end, dst * width + cols.start);
+    }
+
+    /// Blank a row's margin columns
+    fn clear_row_in_margins(&mut self, row: usize) {
+        let width = self.width as usize;
+        let cols = self.margin_columns();
+        let blank = self.blank_cell();
+        for cell in &mut self.cells[row * width + cols.start..row * width + cols.end] {
+            *cell = blank;
+        }
+    }
+
     fn scroll_up(&mut self) {
-        // Scroll within the scroll region only
+        // Scroll within the scroll region (and left/right margins) only
         let width = self.width as usize;
         let top = self.scroll_top as usize;
         let bottom = self.scroll_bottom as usize;
+        let full_width = self.full_width_margins();
+
+        if !full_width {
+            // Partial-width scroll: nothing leaves the screen into scrollback (and
+            // line marks stay put)
+            for y in top..bottom {
+                self.copy_row_in_margins(y + 1, y);
+            }
+            self.clear_row_in_margins(bottom);
+            return;
+        }
 
         // Save top line to scrollback if scrolling from top and not in alternate screen
         if top == 0 && !self.in_alternate_screen {
-            // Use slice copy for efficiency
-            let line = self.cells[0..width].to_vec();
-            self.scrollback.push_back(line);
+            let row = &self.cells[0..width];
+            let len = row.iter().rposition(|&c| c != StoredCell::BLANK).map_or(0, |x| x + 1);
+            self.scrollback_bytes += cell::line_bytes(len);
+            self.scrollback.push_back(ScrollbackLine {
+                cells: row[..len].into(),
+                marks: self.row_marks[0],
+                time: unix_now(),
+            });
             // Trim scrollback if over limit
             if self.scrollback.len() > self.scrollback_limit {
-                self.scrollback.pop_front();
+                self.pop_scrollback();
             }
         }
 
+        // Images in the scrolled region move up with the text, as do those in the
+        // scrollback when a line goes there
+        let to_scrollback = top == 0 && !self.in_alternate_screen;
+        let oldest_row = if to_scrollback { -(self.scrollback_len() as i32) } else { top as i32 };
+        for img in &mut self.images {
+            let below_top = to_scrollback || img.row >= top as i32 - (img.rows as i32 - 1);
+            if below_top && img.row <= bottom as i32 {
+                img.row -= 1;
+            }
+        }
+        self.images.retain(|img| img.row + img.rows as i32 > oldest_row);
+
         // Move lines up within scroll region using copy_within for efficiency
         let src_start = (top + 1) * width;
         let src_end = (bottom + 1) * width;
         let dst_start = top * width;
         self.cells.copy_within(src_start..src_end, dst_start);
+        self.row_marks[top..=bottom].rotate_left(1);
+        self.row_marks[bottom] = LineMarks::default();
 
         // Clear bottom line of scroll region
-        let blank = self.blank_cell();
-        let last_row = bottom * width;
-        for cell in &mut self.cells[last_row..last_row + width] {
-            *cell = blank;
-        }
+        self.clear_row_in_margins(bottom);
     }
 
     fn scroll_down(&mut self) {
-        // Scroll down within the scroll region only
-        let width = self.width as usize;
+        // Scroll down within the scroll region (and left/right margins) only
         let top = self.scroll_top as usize;
         let bottom = self.scroll_bottom as usize;
 
         // Move lines down within scroll region (must go in reverse to avoid overwriting)
-        // copy_within doesn't work here because regions overlap in the wrong direction
         for y in (top + 1..=bottom).rev() {
-            let src = (y - 1) * width;
-            let dst = y * width;
-            self.cells.copy_within(src..src + width, dst);
+            self.copy_row_in_margins(y - 1, y);
         }
-        // Clear top line of scroll region
-        let blank = self.blank_cell();
-        let first_row = top * width;
-        for cell in &mut self.cells[first_row..first_row + width] {
-            *cell = blank;
+        // Images and line marks in the scrolled region move down with the text
+        if self.full_width_margins() {
+            self.row_marks[top..=bottom].rotate_right(1);
+            self.row_marks[top] = LineMarks::default();
+            for img in &mut self.images {
+                if img.row >= top as i32 && img.row <= bottom as i32 {
+                    img.row += 1;
+                }
+            }
+            self.images.retain(|img| img.row <= bottom as i32);
         }
+
+        // Clear top line of scroll region
+        self.clear_row_in_margins(top);
     }
 
     fn insert_lines(&mut self, n: usize) {
         let y = self.cursor_y as usize;
-        let width = self.width as usize;
         let bottom = self.scroll_bottom as usize;
 
-        // Only operate within scroll region and if cursor is in region
-        if y < self.scroll_top as usize || y > bottom {
+        // Only operate if cursor is inside the scroll region and margins
+        if y < self.scroll_top as usize || y > bottom || !self.cursor_in_lr_margins() {
             return;
         }
+        let n = n.min(bottom + 1 - y);
 
         // Move lines down within scroll region (reverse order to avoid overwriting)
-        for row in (y..=bottom.saturating_sub(n)).rev() {
-            let src = row * width;
-            let dst = (row + n) * width;
-            self.cells.copy_within(src..src + width, dst);
+        for row in (y..bottom + 1 - n).rev() {
+            self.copy_row_in_margins(row, row + n);
         }
         // Clear inserted lines
-        let blank = self.blank_cell();
-        for row in y..(y + n).min(bottom + 1) {
-            let start = row * width;
-            for cell in &mut self.cells[start..start + width] {
-                *cell = blank;
-            }
+        for row in y..y + n {
+            self.clear_row_in_margins(row);
+        }
+        if self.full_width_margins() {
+            self.row_marks[y..=bottom].rotate_right(n);
+            self.row_marks[y..y + n].fill(LineMarks::default());
         }
+        self.cursor_x = self.scroll_left;
     }
 
     fn delete_lines(&mut self, n: usize) {
         let y = self.cursor_y as usize;
-        let width = self.width as usize;
         let bottom = self.scroll_bottom as usize;
 
-        // Only operate within scroll region and if cursor is in region
-        if y < self.scroll_top as usize || y > bottom {
+        // Only operate if cursor is inside the scroll region and margins
+        if y < self.scroll_top as usize || y > bottom || !self.cursor_in_lr_margins() {
             return;
         }
+        let n = n.min(bottom + 1 - y);
 
-        // Move lines up within scroll region using copy_within
-        let src_start = (y + n) * width;
-        let src_end = (bottom + 1) * width;
-        let dst_start = y * width;
-        if src_start < src_end {
-            self.cells.copy_within(src_start..src_end, dst_start);
+        // Move lines up within scroll region
+        for row in y..bottom + 1 - n {
+            self.copy_row_in_margins(row + n, row);
         }
-
         // Clear bottom lines of scroll region
+        for row in bottom + 1 - n..=bottom {
+            self.clear_row_in_margins(row);
+        }
+        if self.full_width_margins() {
+            self.row_marks[y..=bottom].rotate_left(n);
+            self.row_marks[bottom + 1 - n..=bottom].fill(LineMarks::default());
+        }
+        self.cursor_x = self.scroll_left;
+    }
+
+    /// Right edge (exclusive) for character shifts: the right margin if the cursor is inside it
+    fn char_shift_end(&self) -> usize {
+        if self.cursor_in_lr_margins() {
+            self.scroll_right as usize + 1
+        } else {
+            self.width as usize
+        }
+    }
+
+    /// Insert blank cells at the cursor, shifting the rest of the line right (ICH, IRM)
+    fn insert_blank_chars(&mut self, n: usize) {
+        let y = self.cursor_y as usize;
+        let width = self.width as usize;
+        let x = self.cursor_x as usize;
+        let end = self.char_shift_end();
+        if x >= end {
+            return;
+        }
         let blank = self.blank_cell();
-        for row in (bottom + 1).saturating_sub(n)..=bottom {
-            let start = row * width;
-            for cell in &mut self.cells[start..start + width] {
-                *cell = blank;
-            }
+        // Shift cells right
+        for i in (x + n..end).rev() {
+            self.cells[y * width + i] = self.cells[y * width + i - n];
+        }
+        // Fill with blanks
+        for i in x..(x + n).min(end) {
+            self.cells[y * width + i] = blank;
+        }
+    }
+
+    /// Delete cells at the cursor, shifting the rest of the line left (DCH)
+    fn delete_chars(&mut self, n: usize) {
+        let y = self.cursor_y as usize;
+        let width = self.width as usize;
+        let x = self.cursor_x as usize;
+        let end = self.char_shift_end();
+        if x >= end {
+            return;
+        }
+        let blank = self.blank_cell();
+        // Shift cells left
+        for i in x..end.saturating_sub(n) {
+            self.cells[y * width + i] = self.cells[y * width + i + n];
+        }
+        // Fill end with blanks
+        for i in end.saturating_sub(n).max(x)..end {
+            self.cells[y * width + i] = blank;
         }
     }
 
     /// Create a blank cell with current background color (for erase operations)
-    fn blank_cell(&self) -> Cell {
-        Cell {
-            ch: ' ',
-            fg: None,
-            bg: self.current_bg,
-            attrs: Attrs::default(),
+    fn blank_cell(&mut self) -> StoredCell {
+        let style = self.style_id(None, self.current_bg, Attrs::default());
+        StoredCell { ch: ' ', style }
+    }
+
+    /// Interned id of a style. A full style table is compacted (when enough
+    /// styles came and went since the last time); if it is still full the
+    /// style's colours are fitted to 16 colours
+    fn style_id(&mut self, fg: Option<Color>, bg: Option<Color>, attrs: Attrs) -> u16 {
+        if let Some(id) = self.styles.intern(fg, bg, attrs) {
+            return id;
         }
+        if self.styles.compact_due() {
+            let mut stores: Vec<&mut [StoredCell]> = vec![&mut self.cells];
+            stores.extend(self.saved_cells.as_deref_mut());
+            stores.extend(self.sync_snapshot.as_mut().map(|snap| snap.cells.as_mut_slice()));
+            stores.extend(self.scrollback.iter_mut().map(|line| &mut line.cells[..]));
+            self.styles.compact(&mut stores);
+            if let Some(id) = self.styles.intern(fg, bg, attrs) {
+                return id;
+            }
+        }
+        self.styles.intern_fitted(fg, bg, attrs)
     }
 
     fn erase_all(&mut self) {
@@ -1022,6 +1717,9 @@ impl ScreenBuffer {
         for cell in &mut self.cells {
             *cell = blank;
         }
+        // Images on screen go with the text, those fully in scrollback stay
+        self.images.retain(|img| img.row + img.rows as i32 <= 0);
+        self.row_marks.fill(LineMarks::default());
     }
 
     fn erase_below(&mut self) {
@@ -1078,15 +1776,22 @@ impl ScreenBuffer {
         }
     }
 
-    /// Get cursor position
+    /// Get cursor position (frozen during a synchronized update)
     pub fn cursor(&self) -> (u16, u16) {
-        (self.cursor_x, self.cursor_y)
+        match self.sync_snapshot {
+            Some(ref snap) => snap.cursor,
+            None => (self.cursor_x, self.cursor_y),
+        }
     }
 
-    /// Get a cell
+    /// Get a cell (from the frozen frame during a synchronized update)
     pub fn get(&self, x: u16, y: u16) -> Cell {
         let idx = (y as usize) * (self.width as usize) + (x as usize);
-        self.cells.get(idx).copied().unwrap_or_default()
+        let cells = match self.sync_snapshot {
+            Some(ref snap) => &snap.cells,
+            None => &self.cells,
+        };
+        self.styles.resolve(cells.get(idx).copied().unwrap_or_default())
     }
 
     pub fn width(&self) -> u16 {
@@ -1097,17 +1802,165 @@ impl ScreenBuffer {
         self.height
     }
 
+    /// Start or stop collecting completed output lines (for output triggers)
+    pub fn set_line_capture(&mut self, enabled: bool) {
+        if enabled != self.captured_lines.is_some() {
+            self.captured_lines = enabled.then(VecDeque::new);
+            self.partial_line.clear();
+        }
+    }
+
+    /// Take the output lines completed since the last call
+    pub fn drain_completed_lines(&mut self) -> Vec<String> {
+        self.captured_lines.as_mut().map(|lines| lines.drain(..).collect()).unwrap_or_default()
+    }
+
+    /// Take the notifications received since the last call
+    pub fn drain_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    /// Whether the application rang the bell since the last call (clears the flag)
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
     pub fn title(&self) -> Option<&str> {
         self.title.as_deref()
     }
 
+    /// Images anchored in this buffer (rows are buffer coordinates, negative = scrollback)
+    pub fn images(&self) -> &[Image] {
+        &self.images
+    }
+
+    /// Set the outer terminal's cell size in pixels (used to size images)
+    pub fn set_cell_pixel_size(&mut self, width: u16, height: u16) {
+        if width > 0 && height > 0 {
+            self.cell_pixels = (width, height);
+        }
+    }
+
+    /// Kitty keyboard protocol flags currently requested by the application
+    pub fn keyboard_flags(&self) -> KeyboardFlags {
+        self.keyboard_stack.last().copied().unwrap_or_default()
+    }
+
     pub fn cursor_visible(&self) -> bool {
-        self.cursor_visible
+        match self.sync_snapshot {
+            Some(ref snap) => snap.cursor_visible,
+            None => self.cursor_visible,
+        }
     }
 
-    /// Get number of lines in scrollback buffer
+    /// Get number of lines in scrollback buffer (including spilled ones)
     pub fn scrollback_len(&self) -> usize {
-        self.scrollback.len()
+        self.spilled_len() + self.scrollback.len()
+    }
+
+    /// Reader of the spilled scrollback lines, if any were spilled
+    pub fn spill_reader(&self) -> anyhow::Result<Option<SpillReader>> {
+        self.spill.as_ref().map(|spill| spill.reader()).transpose()
+    }
+
+    fn spilled_len(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.len())
+    }
+
+    /// Cell of scrollback line `index` (0 = oldest), paged in from the spill file if needed
+    fn scrollback_cell(&self, index: usize, x: usize) -> Cell {
+        let spilled = self.spilled_len();
+        let cell = match index.checked_sub(spilled) {
+            Some(index) => self.scrollback.get(index)
+                .and_then(|line| line.cells.get(x))
+                .map(|&cell| self.styles.resolve(cell)),
+            None => self.spill.as_ref()
+                .and_then(|spill| spill.line(index, |line| line.cells.get(x).copied()))
+                .flatten(),
+        };
+        cell.unwrap_or_default()
+    }
+
+    /// Marks of scrollback line `index` (0 = oldest)
+    fn scrollback_marks(&self, index: usize) -> LineMarks {
+        let spilled = self.spilled_len();
+        let marks = match index.checked_sub(spilled) {
+            Some(index) => self.scrollback.get(index).map(|line| line.marks),
+            None => self.spill.as_ref().and_then(|spill| spill.line(index, |line| line.marks)),
+        };
+        marks.unwrap_or_default()
+    }
+
+    /// When scrollback line `index` (0 = oldest) entered the scrollback
+    fn scrollback_time(&self, index: usize) -> Option<u64> {
+        let spilled = self.spilled_len();
+        match index.checked_sub(spilled) {
+            Some(index) => self.scrollback.get(index).map(|line| line.time),
+            None => self.spill.as_ref().and_then(|spill| spill.line(index, |line| line.time)),
+        }
+    }
+
+    /// Memory held by the scrollback, in bytes
+    pub fn scrollback_bytes(&self) -> usize {
+        self.scrollback_bytes
+    }
+
+    /// Drop the oldest scrollback lines until at least `bytes` are freed (or the
+    /// scrollback is empty); returns the bytes freed
+    pub fn trim_scrollback(&mut self, bytes: usize) -> usize {
+        let before = self.scrollback_bytes;
+        while before - self.scrollback_bytes < bytes && self.pop_scrollback() {}
+        // Images anchored in the dropped lines go with them (spilled lines keep theirs)
+        let oldest_row = -(self.scrollback_len() as i32);
+        self.images.retain(|img| img.row + img.rows as i32 > oldest_row);
+        before - self.scrollback_bytes
+    }
+
+    /// Drop all scrollback, spilled lines included (ED 3 or a clear-history command)
+    pub fn clear_scrollback(&mut self) {
+        self.scrollback.clear();
+        self.scrollback_bytes = 0;
+        self.spill = None;
+        self.images.retain(|img| img.row + img.rows as i32 > 0);
+        self.scrollback_cleared = true;
+    }
+
+    /// Whether the scrollback was cleared since the last call
+    pub fn take_scrollback_cleared(&mut self) -> bool {
+        std::mem::take(&mut self.scrollback_cleared)
+    }
+
+    /// Drop the oldest in-memory scrollback line (spilling it if enabled),
+    /// returning false if there was none
+    fn pop_scrollback(&mut self) -> bool {
+        let Some(line) = self.scrollback.pop_front() else {
+            return false;
+        };
+        self.scrollback_bytes -= cell::line_bytes(line.cells.len());
+        if self.spill_scrollback {
+            self.spill_line(line);
+        }
+        true
+    }
+
+    fn spill_line(&mut self, line: ScrollbackLine) {
+        if self.spill.is_none() {
+            match SpillFile::create() {
+                Ok(spill) => self.spill = Some(spill),
+                Err(e) => {
+                    log::warn!("Scrollback spilling disabled: {:#}", e);
+                    self.spill_scrollback = false;
+                    return;
+                }
+            }
+        }
+        let styles = &self.styles;
+        let cells = line.cells.iter().map(|&cell| styles.resolve(cell));
+        if let Some(Err(e)) = self.spill.as_mut().map(|spill| spill.push(cells, line.marks, line.time)) {
+            // Lines spilled so far stay readable, later ones are dropped
+            log::warn!("Scrollback spilling disabled: {:#}", e);
+            self.spill_scrollback = false;
+        }
     }
 
     /// Get a cell at a buffer Y coordinate where negative values mean scrollback
@@ -1123,16 +1976,40 @@ impl ScreenBuffer {
             }
         } else {
             // Scrollback: y = -1 is most recent, y = -scrollback_len is oldest
-            let scrollback_idx = (self.scrollback.len() as i32 + y) as usize;
-            if let Some(line) = self.scrollback.get(scrollback_idx) {
-                if x < line.len() {
-                    return line[x];
-                }
+            if let Ok(scrollback_idx) = usize::try_from(self.scrollback_len() as i64 + y as i64) {
+                return self.scrollback_cell(scrollback_idx, x);
             }
         }
         Cell::default()
     }
 
+    /// Shell integration marks of a line at a buffer Y coordinate (negative = scrollback)
+    pub fn line_marks(&self, y: i32) -> LineMarks {
+        if y >= 0 {
+            self.row_marks.get(y as usize).copied().unwrap_or_default()
+        } else {
+            let scrollback_idx = self.scrollback_len() as i64 + y as i64;
+            usize::try_from(scrollback_idx)
+                .map(|idx| self.scrollback_marks(idx))
+                .unwrap_or_default()
+        }
+    }
+
+    /// When the line at a buffer Y coordinate scrolled into the scrollback, in
+    /// seconds since the Unix epoch (None for lines still on screen)
+    pub fn line_time(&self, y: i32) -> Option<u64> {
+        if y >= 0 {
+            return None;
+        }
+        let scrollback_idx = self.scrollback_len() as i64 + y as i64;
+        usize::try_from(scrollback_idx).ok().and_then(|idx| self.scrollback_time(idx))
+    }
+
+    /// Exit code of the last command that reported one via OSC 133;D
+    pub fn last_exit_code(&self) -> Option<i32> {
+        self.last_exit_code
+    }
+
     /// Get a cell with scroll offset (for viewing scrollback)
     /// scroll_offset is how many lines back from current view (0 = live view)
     pub fn get_scrolled(&self, x: u16, y: u16, scroll_offset: usize) -> Cell {
@@ -1142,7 +2019,7 @@ impl ScreenBuffer {
         }
 
         let height = self.height as usize;
-        let scrollback_len = self.scrollback.len();
+        let scrollback_len = self.scrollback_len();
 
         // Calculate which line we're looking at
         // scroll_offset lines back means: the visible screen is scrolled up
@@ -1152,13 +2029,7 @@ impl ScreenBuffer {
 
         if y < scroll_offset && scroll_offset <= scrollback_len {
             // This row is in the scrollback buffer
-            let scrollback_idx = scrollback_len - scroll_offset + y;
-            if let Some(line) = self.scrollback.get(scrollback_idx) {
-                if x < line.len() {
-                    return line[x];
-                }
-            }
-            Cell::default()
+            self.scrollback_cell(scrollback_len - scroll_offset + y, x)
         } else if y >= scroll_offset {
             // This row is in the current screen buffer
             let screen_y = y - scroll_offset;
@@ -1173,6 +2044,155 @@ impl ScreenBuffer {
     }
 }
 
+impl Perform for ScreenBuffer {
+    fn print(&mut self, c: char) {
+        self.put_char(c);
+    }
+
+    fn print_str(&mut self, s: &str) {
+        self.put_str(s);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        self.execute_control(byte);
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+        self.execute_esc(intermediates, byte);
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], action: u8) {
+        self.execute_csi(params, intermediates, action);
+    }
+
+    fn osc_dispatch(&mut self, data: &[u8]) {
+        self.execute_osc(data);
+    }
+
+    fn hook(&mut self, params: &Params, intermediates: &[u8], action: u8) {
+        // Only sixel (DCS Pp... q) is kept, with its parameters rebuilt in front of the data
+        self.string_buffer.clear();
+        self.string_overflow = false;
+        self.dcs_sixel = action == b'q' && intermediates.is_empty();
+        if self.dcs_sixel {
+            let params: Vec<String> = params.iter().map(|p| p[0].to_string()).collect();
+            self.string_buffer.extend_from_slice(params.join(";").as_bytes());
+            self.string_buffer.push(b'q');
+        }
+    }
+
+    fn put(&mut self, byte: u8) {
+        if self.dcs_sixel {
+            self.collect_string(byte);
+        }
+    }
+
+    fn unhook(&mut self) {
+        if std::mem::take(&mut self.dcs_sixel) && !self.string_overflow {
+            self.execute_sixel();
+        }
+        self.string_buffer.clear();
+    }
+
+    fn dcs_cancel(&mut self) {
+        self.dcs_sixel = false;
+        self.string_buffer.clear();
+    }
+
+    fn apc_start(&mut self) {
+        self.string_buffer.clear();
+        self.string_overflow = false;
+    }
+
+    fn apc_put(&mut self, byte: u8) {
+        self.collect_string(byte);
+    }
+
+    fn apc_end(&mut self) {
+        // Kitty graphics: APC G <control data> ; <payload> ST
+        let body = std::mem::take(&mut self.string_buffer);
+        if !self.string_overflow && body.first() == Some(&b'G') {
+            self.execute_kitty_graphics(KittyCommand::parse(&body[1..]));
+        }
+    }
+
+    fn apc_cancel(&mut self) {
+        self.string_buffer.clear();
+    }
+}
+
+/// ConEmu reuses OSC 9 for numbered commands (e.g. 9;4;... progress) - not notifications
+fn is_conemu_command(body: &str) -> bool {
+    body.split_once(';')
+        .is_some_and(|(n, _)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Map a character to its DEC Special Graphics (line drawing) glyph
+fn dec_special_char(ch: char) -> char {
+    match ch {
+        '`' => '◆',
+        'a' => '▒',
+        'f' => '°',
+        'g' => '±',
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'o' => '⎺',
+        'p' => '⎻',
+        'q' => '─',
+        'r' => '⎼',
+        's' => '⎽',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'y' => '≤',
+        'z' => '≥',
+        '{' => 'π',
+        '|' => '≠',
+        '}' => '£',
+        '~' => '·',
+        _ => ch,
+    }
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Default tab stops (every TAB_WIDTH columns) for columns start..end
+fn default_tab_stops(start: usize, end: usize) -> Vec<bool> {
+    (start..end).map(|col| col > 0 && col % TAB_WIDTH == 0).collect()
+}
+
+/// Colour of an SGR 38/48/58 parameter: 5;N or 2;R;G;B, given as the colon
+/// subparameters in `sub` or else taken from the following parameters
+fn extended_color<'a>(sub: &[u16], params: &mut impl Iterator<Item = &'a [u16]>) -> Option<Color> {
+    let rgb = |r: u16, g: u16, b: u16| Color::Rgb { r: r as u8, g: g as u8, b: b as u8 };
+    match sub {
+        [5, n, ..] => Some(Color::AnsiValue(*n as u8)),
+        // 38:2:CS:R:G:B carries a colour space id before the components
+        [2, _, r, g, b, ..] | [2, r, g, b] => Some(rgb(*r, *g, *b)),
+        [_, ..] => None,
+        [] => match params.next()?[0] {
+            5 => Some(Color::AnsiValue(params.next()?[0] as u8)),
+            2 => {
+                let r = params.next()?[0];
+                let g = params.next()?[0];
+                let b = params.next()?[0];
+                Some(rgb(r, g, b))
+            }
+            _ => None,
+        },
+    }
+}
+
 fn ansi_to_color(n: u16) -> Color {
     match n {
         0 => Color::Black,
@@ -1201,6 +2221,39 @@ fn ansi_to_bright_color(n: u16) -> Color {
     }
 }
 
+/// Visible parts of a buffer's images for a pane showing `cols` x `rows` cells
+fn visible_images(buffer: &ScreenBuffer, scroll_offset: usize, cols: u16, rows: u16) -> Vec<ImageView<'_>> {
+    buffer
+        .images()
+        .iter()
+        .filter_map(|image| {
+            let top = image.row + scroll_offset as i32;
+            let visible_top = top.max(0);
+            let visible_bottom = (top + image.rows as i32).min(rows as i32);
+            let visible_right = (image.col as u32 + image.cols as u32).min(cols as u32);
+            if visible_top >= visible_bottom || image.col >= cols {
+                return None;
+            }
+            let width = (visible_right - image.col as u32) as u16;
+            let height = (visible_bottom - visible_top) as u16;
+            let sequence = match config::IMAGE_MODE {
+                ImageMode::Passthrough => {
+                    image.clipped_sequence((0, (visible_top - top) as u16), (width, height))
+                }
+                ImageMode::Placeholder => None,
+            };
+            Some(ImageView {
+                image,
+                x: image.col,
+                y: visible_top as u16,
+                width,
+                height,
+                sequence,
+            })
+        })
+        .collect()
+}
+
 /// Cell with focus state for differential rendering
 #[derive(Clone, Copy, PartialEq)]
 struct RenderedCell {
@@ -1217,28 +2270,112 @@ impl Default for RenderedCell {
     }
 }
 
+/// Visible part of an image within a pane (pane-local screen coordinates)
+struct ImageView<'a> {
+    image: &'a Image,
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    // Sequence drawing the clipped image, None if shown as a placeholder
+    sequence: Option<Vec<u8>>,
+}
+
+impl ImageView<'_> {
+    fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Placeholder cell: the label on the first row, shading elsewhere
+    fn placeholder_cell(&self, x: u16, y: u16) -> Cell {
+        let label = format!("[{}]", self.image.label());
+        let ch = if y == self.y {
+            label.chars().nth((x - self.x) as usize).unwrap_or('░')
+        } else {
+            '░'
+        };
+        let mut attrs = Attrs::default();
+        attrs.set(Attrs::DIM);
+        Cell { ch, fg: None, bg: None, attrs }
+    }
+}
+
+/// Image id and on-screen placement (x, y, width, height) of a shown image
+type ImageSignature = (u32, u16, u16, u16, u16);
+
 /// Compositor renders all pane buffers to the terminal
 pub struct Compositor {
     width: u16,
     height: u16,
     // Track what's currently on screen to minimize updates
     last_frame: Vec<RenderedCell>,
+    // Images shown per pane (keyed by content rect origin) to skip unchanged re-emits
+    shown_images: HashMap<(u16, u16), Vec<ImageSignature>>,
+    // Kitty images uploaded to the outer terminal, and placed this frame / last frame
+    kitty_uploaded: HashSet<u32>,
+    kitty_placed: HashSet<u32>,
+    kitty_placed_last: HashSet<u32>,
+    // Colours are fitted to what the outer terminal can show
+    color_depth: ColorDepth,
+    theme: Theme,
+    // Areas the next pane is drawn under (floating panes above it), left alone
+    covered: Vec<Rect>,
 }
 
 impl Compositor {
-    pub fn new(width: u16, height: u16) -> Self {
+    pub fn new(width: u16, height: u16, color_depth: ColorDepth, theme: Theme) -> Self {
         Self {
             width,
             height,
+            color_depth,
+            theme,
+            covered: Vec::new(),
             last_frame: vec![RenderedCell::default(); (width as usize) * (height as usize)],
+            shown_images: HashMap::new(),
+            kitty_uploaded: HashSet::new(),
+            kitty_placed: HashSet::new(),
+            kitty_placed_last: HashSet::new(),
         }
     }
 
+    /// Colour depth of the outer terminal
+    pub fn color_depth(&self) -> ColorDepth {
+        self.color_depth
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Switch themes, redrawing everything with the new one
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.invalidate();
+    }
+
     pub fn resize(&mut self, width: u16, height: u16) {
         self.width = width;
         self.height = height;
         // Fill with a marker that forces redraw
         self.last_frame = vec![RenderedCell::default(); (width as usize) * (height as usize)];
+        self.shown_images.clear();
+    }
+
+    /// Start a frame on the outer terminal (synchronized output, mode 2026)
+    /// Terminals without support ignore it; others show the frame atomically
+    pub fn begin_frame<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        queue!(writer, BeginSynchronizedUpdate)
+    }
+
+    /// Finish a frame started with `begin_frame`
+    /// Kitty images that were not placed in this frame are removed from the terminal
+    pub fn end_frame<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        for &id in self.kitty_placed_last.difference(&self.kitty_placed) {
+            writer.write_all(&image::kitty_delete_sequence(id))?;
+            self.kitty_uploaded.remove(&id);
+        }
+        self.kitty_placed_last = std::mem::take(&mut self.kitty_placed);
+        queue!(writer, EndSynchronizedUpdate)
     }
 
     /// Mark the entire frame as dirty (forces full redraw)
@@ -1246,11 +2383,27 @@ impl Compositor {
         for cell in &mut self.last_frame {
             cell.cell.ch = '\x00'; // Invalid char forces redraw
         }
+        self.shown_images.clear();
+    }
+
+    /// Forget what is shown in part of the screen, so it is drawn again
+    pub fn invalidate_rect(&mut self, rect: Rect) {
+        for y in rect.y..(rect.y + rect.height).min(self.height) {
+            for x in rect.x..(rect.x + rect.width).min(self.width) {
+                self.last_frame[y as usize * self.width as usize + x as usize].cell.ch = '\x00';
+            }
+        }
+    }
+
+    /// Areas that the panes rendered next must not draw over
+    pub fn set_covered(&mut self, rects: Vec<Rect>) {
+        self.covered = rects;
     }
 
     /// Render a pane's buffer to the given rect with differential updates
     /// selection: Optional (start_x, start_y, end_x, end_y) in screen coordinates
     /// search_matches: List of (x, screen_y, len) for each search match to highlight
+    #[allow(clippy::too_many_arguments)]
     pub fn render_pane<W: Write>(
         &mut self,
         writer: &mut W,
@@ -1260,6 +2413,7 @@ impl Compositor {
         scroll_offset: usize,
         selection: Option<(u16, u16, u16, u16)>,
         search_matches: &[(u16, u16, u16)],
+        mut highlights: Option<(&mut HighlightSet, PaneId)>,
     ) -> std::io::Result<()> {
         use crossterm::style::{Attribute, SetAttribute};
 
@@ -1282,6 +2436,31 @@ impl Compositor {
             false
         };
 
+        let visible_rows = rect.height.min(buffer.height());
+        let visible_cols = rect.width.min(buffer.width());
+        let mut images = visible_images(buffer, scroll_offset, visible_cols, visible_rows);
+        // Pixels can't be clipped around the panes above, so partly covered images
+        // are shown as placeholders until they are uncovered
+        for view in &mut images {
+            let area = Rect::new(rect.x + view.x, rect.y + view.y, view.width, view.height);
+            if self.covered.iter().any(|r| r.intersection(area).is_some()) {
+                view.sequence = None;
+            }
+        }
+        let signature: Vec<ImageSignature> = images
+            .iter()
+            .filter(|v| v.sequence.is_some())
+            .map(|v| (v.image.id, v.x, v.y, v.width, v.height))
+            .collect();
+
+        // Pixels of images that moved or went away stay on the terminal until their cells are drawn again
+        let shown = self.shown_images.remove(&(rect.x, rect.y)).unwrap_or_default();
+        for &(_, x, y, width, height) in shown.iter().filter(|s| !signature.contains(s)) {
+            self.invalidate_rect(Rect::new(rect.x + x, rect.y + y, width, height));
+        }
+        // Images with a cell drawn over them (sixel pixels are replaced by it)
+        let mut overwritten = vec![false; images.len()];
+
         let mut last_fg: Option<Color> = None;
         let mut last_bg: Option<Color> = None;
         let mut last_attrs = Attrs::default();
@@ -1290,12 +2469,36 @@ impl Compositor {
         let mut need_move = true;
         let mut attrs_applied = false;
 
-        for y in 0..rect.height.min(buffer.height()) {
+        let mut row_text = String::new();
+
+        for y in 0..visible_rows {
             let screen_y = rect.y + y;
 
-            for x in 0..rect.width.min(buffer.width()) {
+            // Highlight rule matches of the row (cached while its text is unchanged)
+            let spans: &[Span] = match highlights {
+                Some((ref mut set, pane)) => {
+                    row_text.clear();
+                    row_text.extend((0..visible_cols).map(|x| buffer.get_scrolled(x, y, scroll_offset).ch));
+                    set.row_spans(pane, y as usize, &row_text)
+                }
+                None => &[],
+            };
+
+            for x in 0..visible_cols {
                 let screen_x = rect.x + x;
-                let cell = buffer.get_scrolled(x, y, scroll_offset);
+                if self.covered.iter().any(|r| r.contains(screen_x, screen_y)) {
+                    need_move = true;
+                    continue;
+                }
+                let mut cell = buffer.get_scrolled(x, y, scroll_offset);
+                for span in spans.iter().filter(|s| x >= s.start && x < s.end) {
+                    span.style.apply(&mut cell);
+                }
+
+                // Images that can't be re-emitted are drawn as placeholders
+                if let Some(view) = images.iter().find(|v| v.sequence.is_none() && v.contains(x, y)) {
+                    cell = view.placeholder_cell(x, y);
+                }
 
                 // Check if this cell is selected
                 let is_selected = sel_bounds.map(|(sx, sy, ex, ey)| {
@@ -1315,6 +2518,16 @@ impl Compositor {
                 // Check if this cell is in a search match
                 let is_match = is_in_search_match(x, y);
 
+                // Selection and search matches are drawn in the theme's styles
+                if is_selected {
+                    self.theme.selection.apply(&mut cell);
+                } else if is_match {
+                    self.theme.search_match.apply(&mut cell);
+                }
+                if let (false, Some(dim)) = (focused, &config::DIM_BLEND) {
+                    dim.apply(&mut cell);
+                }
+
                 // Check if this cell needs updating
                 let screen_idx = (screen_y as usize) * (self.width as usize) + (screen_x as usize);
                 if screen_idx < self.last_frame.len() {
@@ -1329,6 +2542,11 @@ impl Compositor {
                 }
 
                 // Cell changed - emit it
+                for (i, view) in images.iter().enumerate() {
+                    if view.contains(x, y) {
+                        overwritten[i] = true;
+                    }
+                }
                 if need_move {
                     queue!(writer, MoveTo(screen_x, screen_y))?;
                     need_move = false;
@@ -1354,32 +2572,27 @@ impl Compositor {
                     if cell.attrs.has(Attrs::BOLD) {
                         queue!(writer, SetAttribute(Attribute::Bold))?;
                     }
-                    if cell.attrs.has(Attrs::DIM) || !focused {
+                    if cell.attrs.has(Attrs::DIM) || (!focused && config::DIM_BLEND.is_none()) {
                         queue!(writer, SetAttribute(Attribute::Dim))?;
                     }
                     if cell.attrs.has(Attrs::ITALIC) {
                         queue!(writer, SetAttribute(Attribute::Italic))?;
                     }
-                    if cell.attrs.has(Attrs::UNDERLINE) || is_match {
-                        // Use underline for search match highlighting
+                    if cell.attrs.has(Attrs::UNDERLINE) {
                         queue!(writer, SetAttribute(Attribute::Underlined))?;
                     }
-                    if cell.attrs.has(Attrs::REVERSE) || is_selected {
-                        // Use reverse for selection highlighting
+                    if cell.attrs.has(Attrs::REVERSE) {
                         queue!(writer, SetAttribute(Attribute::Reverse))?;
                     }
                     if cell.attrs.has(Attrs::STRIKETHROUGH) {
                         queue!(writer, SetAttribute(Attribute::CrossedOut))?;
                     }
 
-                    // Apply colors - search matches use yellow foreground
-                    if is_match && !is_selected {
-                        queue!(writer, SetForegroundColor(Color::Yellow))?;
-                    } else if let Some(fg) = cell.fg {
-                        queue!(writer, SetForegroundColor(fg))?;
+                    if let Some(fg) = cell.fg {
+                        queue!(writer, SetFg(fg, self.color_depth))?;
                     }
                     if let Some(bg) = cell.bg {
-                        queue!(writer, SetBackgroundColor(bg))?;
+                        queue!(writer, SetBg(bg, self.color_depth))?;
                     }
 
                     last_fg = cell.fg;
@@ -1406,6 +2619,605 @@ impl Compositor {
         queue!(writer, SetAttribute(Attribute::Reset))?;
         queue!(writer, ResetColor)?;
 
+        self.render_images(writer, &images, rect, &shown, &overwritten)?;
+        self.shown_images.insert((rect.x, rect.y), signature);
+
+        Ok(())
+    }
+
+    /// Re-emit a pane's images on top of its cells, unless already shown in place
+    /// Sixel images are also redrawn when a cell was drawn over them; kitty placements sit above text
+    fn render_images<W: Write>(
+        &mut self,
+        writer: &mut W,
+        images: &[ImageView],
+        rect: Rect,
+        shown: &[ImageSignature],
+        overwritten: &[bool],
+    ) -> std::io::Result<()> {
+        for (view, &overwritten) in images.iter().zip(overwritten) {
+            let Some(ref sequence) = view.sequence else {
+                continue;
+            };
+            let moved = !shown.contains(&(view.image.id, view.x, view.y, view.width, view.height));
+            let redraw = match view.image.protocol {
+                ImageProtocol::Sixel => moved || overwritten,
+                ImageProtocol::Kitty => {
+                    self.kitty_placed.insert(view.image.id);
+                    moved || !self.kitty_uploaded.contains(&view.image.id)
+                }
+            };
+            if !redraw {
+                continue;
+            }
+            if view.image.protocol == ImageProtocol::Kitty && self.kitty_uploaded.insert(view.image.id) {
+                writer.write_all(&view.image.kitty_transmit_sequence())?;
+            }
+            queue!(writer, MoveTo(rect.x + view.x, rect.y + view.y))?;
+            writer.write_all(sequence)?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row_text(buf: &ScreenBuffer, y: u16) -> String {
+        (0..buf.width()).map(|x| buf.get(x, y).ch).collect::<String>().trim_end().to_string()
+    }
+
+    #[test]
+    fn test_sync_update_freezes_frame() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"old");
+
+        buf.process(b"\x1b[?2026h\r\x1b[Knew");
+        assert!(buf.in_sync_update());
+        assert_eq!(row_text(&buf, 0), "old");
+        assert_eq!(buf.cursor(), (3, 0));
+
+        buf.process(b"!\x1b[?2026l");
+        assert!(!buf.in_sync_update());
+        assert_eq!(row_text(&buf, 0), "new!");
+    }
+
+    #[test]
+    fn test_sync_update_timeout() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"\x1b[?2026hpartial");
+        assert!(!buf.expire_sync_update(Duration::from_secs(60)));
+        assert_eq!(row_text(&buf, 0), "");
+
+        assert!(buf.expire_sync_update(Duration::ZERO));
+        assert_eq!(row_text(&buf, 0), "partial");
+    }
+
+    #[test]
+    fn test_keyboard_flag_stack() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        assert!(buf.keyboard_flags().is_empty());
+
+        buf.process(b"\x1b[>1u\x1b[>9u");
+        assert!(buf.keyboard_flags().has(KeyboardFlags::REPORT_ALL_KEYS));
+
+        buf.process(b"\x1b[?u");
+        assert_eq!(buf.drain_responses(), vec![b"\x1b[?9u".to_vec()]);
+
+        buf.process(b"\x1b[<u");
+        assert!(!buf.keyboard_flags().has(KeyboardFlags::REPORT_ALL_KEYS));
+        assert!(buf.keyboard_flags().has(KeyboardFlags::DISAMBIGUATE));
+
+        buf.process(b"\x1b[=8;2u");
+        assert!(buf.keyboard_flags().has(KeyboardFlags::REPORT_ALL_KEYS));
+
+        buf.process(b"\x1b[<5u");
+        assert!(buf.keyboard_flags().is_empty());
+    }
+
+    #[test]
+    fn test_keyboard_flags_per_screen() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"\x1b[>1u\x1b[?1049h");
+        assert!(buf.keyboard_flags().is_empty());
+
+        buf.process(b"\x1b[>8u\x1b[?1049l");
+        assert!(buf.keyboard_flags().has(KeyboardFlags::DISAMBIGUATE));
+        assert!(!buf.keyboard_flags().has(KeyboardFlags::REPORT_ALL_KEYS));
+    }
+
+    #[test]
+    fn test_sixel_image_anchored_and_scrolled() {
+        let mut buf = ScreenBuffer::new(20, 5);
+        buf.set_cell_pixel_size(10, 20);
+        buf.process(b"x\r\n\x1bP0;1q\"1;1;30;40#0~~~\x1b\\");
+
+        let img = &buf.images()[0];
+        assert_eq!(img.protocol, ImageProtocol::Sixel);
+        assert_eq!((img.row, img.col, img.cols, img.rows), (1, 0, 3, 2));
+        // Cursor ends on the image's last row
+        assert_eq!(buf.cursor(), (0, 2));
+
+        buf.process(b"\r\n\r\n\r\n\r\n");
+        assert_eq!(buf.images()[0].row, -1);
+        assert_eq!(buf.scrollback_len(), 2);
+    }
+
+    #[test]
+    fn test_kitty_image_transmit_and_place() {
+        let mut buf = ScreenBuffer::new(20, 5);
+        buf.process(b"\x1b_Ga=t,i=5,f=24,s=20,v=40,m=1;AAAA\x1b\\\x1b_Gm=0;AAAA\x1b\\");
+        assert_eq!(buf.drain_responses(), vec![b"\x1b_Gi=5;OK\x1b\\".to_vec()]);
+        assert!(buf.images().is_empty());
+
+        buf.process(b"\x1b_Ga=p,i=5,q=2\x1b\\");
+        let img = &buf.images()[0];
+        assert_eq!((img.cols, img.rows), (2, 2));
+        assert_eq!(img.data, b"f=24,s=20,v=40;AAAAAAAA".to_vec());
+        assert_eq!(buf.cursor(), (2, 1));
+
+        buf.process(b"\x1b_Ga=d,d=i,i=5\x1b\\");
+        assert!(buf.images().is_empty());
+    }
+
+    #[test]
+    fn test_kitty_upload_too_big() {
+        let mut buf = ScreenBuffer::new(20, 5);
+        let chunk = vec![b'A'; image::MAX_SEQUENCE_BYTES / 2 + 1];
+        buf.process(b"\x1b_Ga=T,i=7,f=24,s=20,v=40,m=1;");
+        for _ in 0..3 {
+            buf.process(&chunk);
+            buf.process(b"\x1b\\\x1b_Gm=1;");
+        }
+        buf.process(b"AAAA\x1b\\\x1b_Gm=0;AAAA\x1b\\");
+        // The rest of the upload is skipped, not taken for new commands
+        assert_eq!(buf.drain_responses(), vec![b"\x1b_Gi=7;EFBIG:image too large\x1b\\".to_vec()]);
+        assert!(buf.images().is_empty());
+
+        // The next upload starts afresh
+        buf.process(b"\x1b_Ga=T,i=8,f=24,s=20,v=40;AAAA\x1b\\");
+        assert_eq!(buf.drain_responses(), vec![b"\x1b_Gi=8;OK\x1b\\".to_vec()]);
+        assert_eq!(buf.images().len(), 1);
+    }
+
+    #[test]
+    fn test_erase_display_removes_images() {
+        let mut buf = ScreenBuffer::new(20, 5);
+        buf.process(b"\x1bPq#0~~\x1b\\");
+        assert_eq!(buf.images().len(), 1);
+        buf.process(b"\x1b[2J");
+        assert!(buf.images().is_empty());
+    }
+
+    #[test]
+    fn test_cancelled_image() {
+        let mut buf = ScreenBuffer::new(20, 5);
+        buf.process(b"\x1bPq#0~~\x18\x1b_Ga=T,f=24,s=10,v=20;AAAA\x1b[m");
+        assert!(buf.images().is_empty());
+        assert!(buf.drain_responses().is_empty());
+    }
+
+    #[test]
+    fn test_image_memory_bound() {
+        let mut buf = ScreenBuffer::new(20, 5);
+        let big = image::MAX_IMAGE_BYTES / 3 + 1;
+        for _ in 0..3 {
+            buf.place_image(ImageProtocol::Sixel, vec![0; big], (1, 1), (1, 1));
+        }
+        assert_eq!(buf.images().len(), 2);
+    }
+
+    #[test]
+    fn test_image_redraw() {
+        let mut buf = ScreenBuffer::new(10, 4);
+        buf.set_cell_pixel_size(10, 20);
+        buf.process(b"\x1bPq\"1;1;20;20#0~~\x1b\\");
+        let mut comp = Compositor::new(10, 4, ColorDepth::TrueColor, Theme::default());
+        let render = |comp: &mut Compositor, buf: &ScreenBuffer| {
+            let mut out = Vec::new();
+            comp.render_pane(&mut out, buf, Rect::new(0, 0, 10, 4), true, 0, None, &[], None).unwrap();
+            String::from_utf8_lossy(&out).into_owned()
+        };
+        assert!(render(&mut comp, &buf).contains("\x1bP"));
+
+        // Output elsewhere doesn't re-send the sixel
+        buf.process(b"\x1b[3;5Hx");
+        assert!(!render(&mut comp, &buf).contains("\x1bP"));
+
+        // Once the image is gone its blank cells are drawn again over the pixels
+        buf.process(b"\x1b[2J");
+        assert!(render(&mut comp, &buf).contains("\x1b[1;1H"));
+
+        // A partly covered image is a placeholder until it is uncovered
+        buf.process(b"\x1b[H\x1bPq\"1;1;20;20#0~~\x1b\\");
+        comp.set_covered(vec![Rect::new(1, 0, 3, 3)]);
+        let out = render(&mut comp, &buf);
+        assert!(!out.contains("\x1bP") && out.contains('['));
+        comp.set_covered(Vec::new());
+        assert!(render(&mut comp, &buf).contains("\x1bP"));
+    }
+
+    #[test]
+    fn test_tab_stops() {
+        let mut buf = ScreenBuffer::new(40, 2);
+        buf.process(b"\t");
+        assert_eq!(buf.cursor(), (8, 0));
+
+        // Clear all, set custom stops at 3 and 10
+        buf.process(b"\x1b[3g\r\x1b[4G\x1bH\x1b[11G\x1bH\r");
+        buf.process(b"\t");
+        assert_eq!(buf.cursor(), (3, 0));
+        buf.process(b"\t");
+        assert_eq!(buf.cursor(), (10, 0));
+        // No more stops - tab stops at the last column
+        buf.process(b"\t");
+        assert_eq!(buf.cursor(), (39, 0));
+
+        // CBT and CHT with counts
+        buf.process(b"\x1b[2Z");
+        assert_eq!(buf.cursor(), (3, 0));
+        buf.process(b"\x1b[Z\x1b[2I");
+        assert_eq!(buf.cursor(), (10, 0));
+
+        // TBC 0 clears the stop under the cursor
+        buf.process(b"\x1b[0g\r\t");
+        assert_eq!(buf.cursor(), (3, 0));
+    }
+
+    #[test]
+    fn test_tab_stops_resize() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        buf.process(b"\x1b[3g\x1b[3G\x1bH\r");
+        buf.resize(30, 2);
+        buf.process(b"\t");
+        assert_eq!(buf.cursor(), (2, 0));
+        // Columns added by the resize get default stops
+        buf.process(b"\t");
+        assert_eq!(buf.cursor(), (16, 0));
+    }
+
+    #[test]
+    fn test_origin_mode_addressing() {
+        let mut buf = ScreenBuffer::new(20, 10);
+        buf.process(b"\x1b[3;7r\x1b[?6h");
+        assert_eq!(buf.cursor(), (0, 2));
+        buf.process(b"\x1b[2;5H");
+        assert_eq!(buf.cursor(), (4, 3));
+        // Rows clamp to the bottom margin
+        buf.process(b"\x1b[99d");
+        assert_eq!(buf.cursor(), (4, 6));
+        buf.process(b"\x1b[6n");
+        assert_eq!(buf.drain_responses(), vec![b"\x1b[5;5R".to_vec()]);
+        buf.process(b"\x1b[?6$p");
+        assert_eq!(buf.drain_responses(), vec![b"\x1b[?6;1$y".to_vec()]);
+    }
+
+    #[test]
+    fn test_left_right_margins() {
+        let mut buf = ScreenBuffer::new(10, 4);
+        // Without DECLRMM, CSI s saves the cursor
+        buf.process(b"\x1b[2;3H\x1b[s\x1b[H\x1b[u");
+        assert_eq!(buf.cursor(), (2, 1));
+
+        buf.process(b"\x1b[4;1HXXXXXXXXXX");
+        buf.process(b"\x1b[?69h\x1b[3;6s");
+        assert_eq!(buf.cursor(), (0, 0));
+        buf.process(b"\x1b[1;3Habcdefg");
+        // Text wraps at the right margin back to the left margin
+        assert_eq!(row_text(&buf, 0), "  abcd");
+        assert_eq!(row_text(&buf, 1), "  efg");
+
+        // Scrolling only moves the margin columns
+        buf.process(b"\x1b[4;3H\n");
+        assert_eq!(row_text(&buf, 0), "  efg");
+        assert_eq!(row_text(&buf, 3), "XX    XXXX");
+        assert_eq!(buf.scrollback_len(), 0);
+
+        // Lines inserted outside the margins are ignored
+        buf.process(b"\x1b[1;1H\x1b[L");
+        assert_eq!(row_text(&buf, 0), "  efg");
+        buf.process(b"\x1b[1;4H\x1b[L");
+        assert_eq!(row_text(&buf, 0), "");
+        assert_eq!(row_text(&buf, 1), "  efg");
+        assert_eq!(buf.cursor(), (2, 0));
+        buf.process(b"\x1b[M");
+        assert_eq!(row_text(&buf, 0), "  efg");
+
+        // NEL returns to the left margin, tabs stop at the right one
+        buf.process(b"\x1b[1;4H\x1b\x45");
+        assert_eq!(buf.cursor(), (2, 1));
+        buf.process(b"\t\t");
+        assert_eq!(buf.cursor(), (5, 1));
+        buf.process(b"\x1b[2;8H\t\t");
+        assert_eq!(buf.cursor(), (9, 1));
+    }
+
+    #[test]
+    fn test_maximal_parameters() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"\x1b[2;2H\x1b[65535B\x1b[65535C");
+        assert_eq!(buf.cursor(), (9, 2));
+        buf.process(b"\x1b[65535A\x1b[65535D");
+        assert_eq!(buf.cursor(), (0, 0));
+        buf.process(b"x\x1b[65535b");
+        // 65536 characters in rows of 10 leave 6 on the last row
+        assert_eq!(row_text(&buf, 1), "xxxxxxxxxx");
+        assert_eq!(row_text(&buf, 2), "xxxxxx");
+        assert_eq!(buf.cursor(), (6, 2));
+    }
+
+    #[test]
+    fn test_print_runs() {
+        // Runs of text end up as if printed a character at a time
+        for setup in [&b""[..], b"\x1b[?69h\x1b[3;6s\x1b[1;4H", b"\x1b[?69h\x1b[3;6s\x1b[1;8H", b"\x1b[?7l"] {
+            let mut bulk = ScreenBuffer::new(10, 3);
+            let mut single = ScreenBuffer::new(10, 3);
+            bulk.process(setup);
+            single.process(setup);
+            let text = "abcdefghijklmnopqrstuvwxyz0123456789";
+            bulk.put_str(text);
+            text.chars().for_each(|ch| single.put_char(ch));
+            for y in 0..3 {
+                assert_eq!(row_text(&bulk, y), row_text(&single, y));
+            }
+            assert_eq!(bulk.cursor(), single.cursor());
+            assert_eq!(bulk.scrollback_len(), single.scrollback_len());
+        }
+    }
+
+    #[test]
+    fn test_delete_lines_in_scroll_region() {
+        let mut buf = ScreenBuffer::new(5, 5);
+        buf.process(b"a\r\nb\r\nc\r\nd\r\ne");
+        buf.process(b"\x1b[2;4r\x1b[2;1H\x1b[9M");
+        assert_eq!(row_text(&buf, 0), "a");
+        assert_eq!(row_text(&buf, 1), "");
+        assert_eq!(row_text(&buf, 3), "");
+        assert_eq!(row_text(&buf, 4), "e");
+        buf.process(b"\x1b[1;1H\x1b[9L");
+        assert_eq!(row_text(&buf, 0), "a");
+    }
+
+    #[test]
+    fn test_insert_mode_and_repeat() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        buf.process(b"abc\r\x1b[4hXY\x1b[4lZ");
+        assert_eq!(row_text(&buf, 0), "XYZbc");
+        buf.process(b"\r\n-\x1b[3b");
+        assert_eq!(row_text(&buf, 1), "----");
+    }
+
+    #[test]
+    fn test_save_restore_cursor_state() {
+        let mut buf = ScreenBuffer::new(10, 5);
+        buf.process(b"\x1b[2;3H\x1b[1;31m\x1b(0\x1b7");
+        buf.process(b"\x1b[H\x1b[m\x1b(B\x1b8q");
+        let cell = buf.get(2, 1);
+        assert_eq!(cell.ch, '─');
+        assert!(cell.attrs.has(Attrs::BOLD));
+        assert_eq!(cell.fg, Some(Color::DarkRed));
+        assert_eq!(buf.cursor(), (3, 1));
+
+        // CSI s / CSI u share the DECSC slot
+        buf.process(b"\x1b[m\x1b[s\x1b[4;4H\x1b[7m\x1b[u");
+        assert_eq!(buf.cursor(), (3, 1));
+        buf.process(b"x");
+        assert!(!buf.get(3, 1).attrs.has(Attrs::REVERSE));
+    }
+
+    #[test]
+    fn test_alternate_screen_modes() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"main\x1b[2;2H");
+
+        // 1049 saves the cursor and clears the alt screen, restoring both on exit
+        buf.process(b"\x1b[?1049halt\x1b[3;5H\x1b[?1049l");
+        assert_eq!(row_text(&buf, 0), "main");
+        assert_eq!(buf.cursor(), (1, 1));
+        buf.process(b"\x1b[?1049h");
+        assert_eq!(row_text(&buf, 1), "");
+
+        // Each screen has its own DECSC slot
+        buf.process(b"\x1b[3;3H\x1b7\x1b[?1049l\x1b8");
+        assert_eq!(buf.cursor(), (1, 1));
+
+        // 47 keeps the alt screen contents and the cursor between switches
+        buf.process(b"\x1b[?47h\x1b[Hkept\x1b[?47l");
+        assert_eq!(row_text(&buf, 0), "main");
+        assert_eq!(buf.cursor(), (4, 0));
+        buf.process(b"\x1b[?47h");
+        assert_eq!(row_text(&buf, 0), "kept");
+
+        // 1047 clears the alt screen on the way out
+        buf.process(b"\x1b[?1047l\x1b[?1047h");
+        assert_eq!(row_text(&buf, 0), "");
+    }
+
+    #[test]
+    fn test_sgr_params() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        // Colon and semicolon colours, underline styles, and an underline colour
+        // whose components must not be read as attributes
+        buf.process(b"\x1b[38:2::1:2:3;48;5;17;4:3;58;2;1;2;3ma\x1b[4:0mb");
+        let a = buf.get(0, 0);
+        assert_eq!(a.fg, Some(Color::Rgb { r: 1, g: 2, b: 3 }));
+        assert_eq!(a.bg, Some(Color::AnsiValue(17)));
+        assert!(a.attrs.has(Attrs::UNDERLINE));
+        assert!(!a.attrs.has(Attrs::DIM) && !a.attrs.has(Attrs::BOLD));
+        assert!(!buf.get(1, 0).attrs.has(Attrs::UNDERLINE));
+        // A CSI with a private marker isn't SGR (XTMODKEYS)
+        buf.process(b"\x1b[0m\x1b[>4;1mc");
+        assert!(!buf.get(2, 0).attrs.has(Attrs::BOLD));
+    }
+
+    #[test]
+    fn test_scrollback_compact_and_trim() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        buf.process(b"\x1b[31mred\x1b[0m\r\nab\r\ncd\r\n");
+        assert_eq!(buf.scrollback_len(), 2);
+        // Trailing blanks aren't stored but still read back as blanks
+        assert_eq!(buf.scrollback_bytes(), cell::line_bytes(3) + cell::line_bytes(2));
+        assert_eq!(buf.get_at_scroll_offset(0, -2).fg, Some(Color::DarkRed));
+        assert_eq!(buf.get_at_scroll_offset(9, -2).ch, ' ');
+
+        // Trimming drops the oldest lines first
+        assert_eq!(buf.trim_scrollback(1), cell::line_bytes(3));
+        assert_eq!(buf.scrollback_len(), 1);
+        assert_eq!(buf.get_at_scroll_offset(0, -1).ch, 'a');
+        assert_eq!(buf.scrollback_bytes(), cell::line_bytes(2));
+    }
+
+    #[test]
+    fn test_scrollback_spill() {
+        let mut buf = ScreenBuffer::new(10, 1);
+        buf.scrollback_limit = 2;
+        buf.spill_scrollback = true;
+        buf.process(b"\x1b]133;A\x07\x1b[1mone\r\ntwo\r\nthree\r\nfour\r\nlive");
+        // Two lines spilled, two in memory, all readable in order
+        assert_eq!(buf.scrollback_len(), 4);
+        let line = |buf: &ScreenBuffer, y| (0..5).map(|x| buf.get_at_scroll_offset(x, y).ch).collect::<String>();
+        assert_eq!(line(&buf, -4), "one  ");
+        assert_eq!(line(&buf, -1), "four ");
+        assert!(buf.get_at_scroll_offset(0, -4).attrs.has(Attrs::BOLD));
+        assert!(buf.line_marks(-4).prompt);
+        assert_eq!(buf.get_scrolled(0, 0, 3).ch, 't');
+        // Spilled and in-memory lines keep when they scrolled in; screen lines have no time
+        let now = unix_now();
+        assert!(buf.line_time(-4).is_some_and(|t| t <= now && t + 60 > now));
+        assert!(buf.line_time(-4) <= buf.line_time(-1));
+        assert_eq!(buf.line_time(0), None);
+        assert_eq!(buf.line_time(-5), None);
+        // A reader sees the same spilled lines
+        let reader = buf.spill_reader().unwrap().unwrap();
+        let spilled: Vec<char> = reader.lines().map(|line| line.cells[0].ch).collect();
+        assert_eq!((reader.len(), spilled), (2, vec!['o', 't']));
+
+        // Trimming for the memory budget spills rather than drops
+        buf.trim_scrollback(usize::MAX);
+        assert_eq!(buf.scrollback_len(), 4);
+        assert_eq!(buf.scrollback_bytes(), 0);
+        assert_eq!(line(&buf, -1), "four ");
+    }
+
+    #[test]
+    fn test_images_in_spilled_scrollback() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        buf.scrollback_limit = 2;
+        buf.spill_scrollback = true;
+        buf.process(b"\x1bPq#0~~\x1b\\");
+        buf.process(b"\r\n1\r\n2\r\n3\r\n4\r\n5");
+        // The image row keeps counting into the spilled lines
+        assert_eq!(buf.scrollback_len(), 4);
+        assert_eq!(buf.images().len(), 1);
+        assert_eq!(buf.images()[0].row, -4);
+        buf.trim_scrollback(usize::MAX);
+        assert_eq!(buf.images()[0].row, -4);
+    }
+
+    #[test]
+    fn test_erase_scrollback() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        buf.process(b"one\r\ntwo\r\nthree");
+        assert_eq!(buf.scrollback_len(), 1);
+        // ED 3 clears only the scrollback, ED 2 only the screen
+        buf.process(b"\x1b[3J");
+        assert_eq!(buf.scrollback_len(), 0);
+        assert_eq!(buf.scrollback_bytes(), 0);
+        assert!(buf.take_scrollback_cleared());
+        assert!(!buf.take_scrollback_cleared());
+        assert_eq!(row_text(&buf, 1), "three");
+    }
+
+    #[test]
+    fn test_title_stack() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        buf.process(b"\x1b]2;shell\x07\x1b[22;0t\x1b]2;vim\x07");
+        assert_eq!(buf.title(), Some("vim"));
+        // Icon-only pops are ignored
+        buf.process(b"\x1b[23;1t");
+        assert_eq!(buf.title(), Some("vim"));
+        buf.process(b"\x1b[23;0t");
+        assert_eq!(buf.title(), Some("shell"));
+        // Popping an empty stack keeps the title
+        buf.process(b"\x1b[23t");
+        assert_eq!(buf.title(), Some("shell"));
+    }
+
+    #[test]
+    fn test_bell_flag() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        // BEL terminating an OSC is not a bell
+        buf.process(b"\x1b]2;title\x07");
+        assert!(!buf.take_bell());
+        buf.process(b"done\x07");
+        assert!(buf.take_bell());
+        assert!(!buf.take_bell());
+    }
+
+    #[test]
+    fn test_completed_line_capture() {
+        let mut buf = ScreenBuffer::new(5, 3);
+        buf.process(b"skip\r\n");
+        assert!(buf.drain_completed_lines().is_empty());
+
+        buf.set_line_capture(true);
+        // Wrapped rows join into one line, which completes on the line feed
+        buf.process(b"build FAILED\r\nok");
+        assert_eq!(buf.drain_completed_lines(), vec!["build FAILED".to_string()]);
+        buf.process(b"\r\n\x1b[?1049hfull\r\nscreen\r\n");
+        assert_eq!(buf.drain_completed_lines(), vec!["ok".to_string()]);
+    }
+
+    #[test]
+    fn test_notifications() {
+        let mut buf = ScreenBuffer::new(10, 2);
+        buf.process(b"\x1b]9;build done\x07\x1b]777;notify;CI;tests passed\x1b\\");
+        // ConEmu progress reports are not notifications
+        buf.process(b"\x1b]9;4;1;50\x07");
+        assert_eq!(
+            buf.drain_notifications(),
+            vec![
+                Notification { title: None, body: "build done".to_string() },
+                Notification { title: Some("CI".to_string()), body: "tests passed".to_string() },
+            ]
+        );
+        assert!(buf.drain_notifications().is_empty());
+        assert_eq!(row_text(&buf, 0), "");
+    }
+
+    #[test]
+    fn test_prompt_marks() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07a\r\nb\r\n");
+        buf.process(b"\x1b]133;D;2\x07\x1b]133;A\x07$ ");
+        assert_eq!(buf.last_exit_code(), Some(2));
+        buf.process(b"\x1b]133;C\x07");
+        assert_eq!(buf.last_exit_code(), None);
+
+        // The first prompt scrolled into scrollback with its marks
+        let prompt = buf.line_marks(-1);
+        assert!(prompt.prompt && prompt.input && !prompt.output);
+        assert!(buf.line_marks(0).output);
+        let last = buf.line_marks(2);
+        assert!(last.prompt && last.finished);
+        assert_eq!(last.exit_code, Some(2));
+
+        // Marks move with inserted lines and are cleared with the screen
+        buf.process(b"\x1b[1;1H\x1b[L");
+        assert!(buf.line_marks(1).output);
+        buf.process(b"\x1b[2J");
+        assert_eq!(buf.line_marks(1), LineMarks::default());
+        assert!(buf.line_marks(-1).prompt);
+    }
+
+    #[test]
+    fn test_sync_update_mode_report() {
+        let mut buf = ScreenBuffer::new(10, 3);
+        buf.process(b"\x1b[?2026$p");
+        assert_eq!(buf.drain_responses(), vec![b"\x1b[?2026;2$y".to_vec()]);
+
+        buf.process(b"\x1b[?2026h\x1b[?2026$p");
+        assert_eq!(buf.drain_responses(), vec![b"\x1b[?2026;1$y".to_vec()]);
+    }
+}
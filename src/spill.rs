@@ -0,0 +1,320 @@
+//! Scrollback spill files - lines evicted from memory, compressed on disk
+
+use crate::cell::PackedColor;
+use crate::render::{Attrs, Cell, LineMarks};
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Lines compressed together; a read pages in one such block
+const BLOCK_LINES: usize = 256;
+
+/// Deflate level - spilling happens on every line feed once the scrollback is full
+const COMPRESSION_LEVEL: u8 = 1;
+
+/// Distinguishes the spill files of one truetm process
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// A line read back from a spill file
+pub struct SpilledLine {
+    pub cells: Vec<Cell>,
+    pub marks: LineMarks,
+    pub time: u64,
+}
+
+/// Append-only file of scrollback lines, deleted when dropped
+pub struct SpillFile {
+    path: PathBuf,
+    file: File,
+    // Offset and compressed size of each full block
+    blocks: Vec<(u64, usize)>,
+    // Encoded lines of the block being filled (kept in memory until full)
+    pending: Vec<u8>,
+    len: usize,
+    // Last block read, by index
+    cache: RefCell<Option<(usize, Vec<SpilledLine>)>>,
+}
+
+impl SpillFile {
+    /// Create a spill file in $XDG_RUNTIME_DIR (or the temp directory)
+    pub fn create() -> Result<Self> {
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let n = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("truetm-{}-{}.scrollback", std::process::id(), n));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self {
+            path,
+            file,
+            blocks: Vec::new(),
+            pending: Vec::new(),
+            len: 0,
+            cache: RefCell::new(None),
+        })
+    }
+
+    /// Number of lines in the file
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Append a line. It stays readable even if writing its block fails
+    pub fn push(&mut self, cells: impl Iterator<Item = Cell>, marks: LineMarks, time: u64) -> Result<()> {
+        encode_line(cells, marks, time, &mut self.pending);
+        self.len += 1;
+        let block = self.blocks.len();
+        if self.cache.get_mut().as_ref().is_some_and(|(index, _)| *index == block) {
+            *self.cache.get_mut() = None;
+        }
+        if self.len == (block + 1) * BLOCK_LINES {
+            let data = miniz_oxide::deflate::compress_to_vec(&self.pending, COMPRESSION_LEVEL);
+            let offset = self.blocks.last().map_or(0, |&(offset, size)| offset + size as u64);
+            self.file
+                .write_all_at(&data, offset)
+                .with_context(|| format!("Failed to write {}", self.path.display()))?;
+            self.blocks.push((offset, data.len()));
+            self.pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Run `f` on line `index` (0 = oldest), paging its block in if needed
+    pub fn line<R>(&self, index: usize, f: impl FnOnce(&SpilledLine) -> R) -> Option<R> {
+        if index >= self.len {
+            return None;
+        }
+        let block = index / BLOCK_LINES;
+        let mut cache = self.cache.borrow_mut();
+        if !matches!(*cache, Some((cached, _)) if cached == block) {
+            *cache = Some((block, self.read_block(block)));
+        }
+        let (_, lines) = cache.as_ref()?;
+        lines.get(index % BLOCK_LINES).map(f)
+    }
+
+    fn read_block(&self, block: usize) -> Vec<SpilledLine> {
+        read_block(&self.file, &self.path, &self.blocks, &self.pending, block)
+    }
+
+    /// A reader of the lines spilled so far that can be moved to another thread
+    pub fn reader(&self) -> Result<SpillReader> {
+        Ok(SpillReader {
+            file: self.file.try_clone().with_context(|| format!("Failed to open {}", self.path.display()))?,
+            path: self.path.clone(),
+            blocks: self.blocks.clone(),
+            pending: self.pending.clone(),
+            len: self.len,
+        })
+    }
+}
+
+/// The lines of a spill file at the time the reader was made
+pub struct SpillReader {
+    file: File,
+    path: PathBuf,
+    blocks: Vec<(u64, usize)>,
+    pending: Vec<u8>,
+    len: usize,
+}
+
+impl SpillReader {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// All lines, oldest first, paged in a block at a time
+    pub fn lines(&self) -> impl Iterator<Item = SpilledLine> + '_ {
+        (0..=self.blocks.len())
+            .flat_map(move |block| read_block(&self.file, &self.path, &self.blocks, &self.pending, block))
+    }
+}
+
+/// Decode a full block, or the pending lines for the block after the last one
+fn read_block(file: &File, path: &Path, blocks: &[(u64, usize)], pending: &[u8], block: usize) -> Vec<SpilledLine> {
+    let data = match blocks.get(block) {
+        Some(&(offset, size)) => {
+            let mut compressed = vec![0; size];
+            if let Err(e) = file.read_exact_at(&mut compressed, offset) {
+                log::warn!("Failed to read {}: {}", path.display(), e);
+                return Vec::new();
+            }
+            miniz_oxide::inflate::decompress_to_vec(&compressed).unwrap_or_default()
+        }
+        None => pending.to_vec(),
+    };
+    let mut lines = Vec::new();
+    let mut input = data.as_slice();
+    while let Some(line) = decode_line(&mut input) {
+        lines.push(line);
+    }
+    lines
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Line encoding: a marks byte (and exit code), the time as a varint, then runs
+// of cells sharing a style as fg, bg, attrs and the run's characters as
+// length-prefixed UTF-8
+
+const MARK_PROMPT: u8 = 1 << 0;
+const MARK_INPUT: u8 = 1 << 1;
+const MARK_OUTPUT: u8 = 1 << 2;
+const MARK_FINISHED: u8 = 1 << 3;
+const MARK_EXIT_CODE: u8 = 1 << 4;
+
+fn encode_line(cells: impl Iterator<Item = Cell>, marks: LineMarks, time: u64, out: &mut Vec<u8>) {
+    let flags = [
+        (marks.prompt, MARK_PROMPT),
+        (marks.input, MARK_INPUT),
+        (marks.output, MARK_OUTPUT),
+        (marks.finished, MARK_FINISHED),
+        (marks.exit_code.is_some(), MARK_EXIT_CODE),
+    ];
+    out.push(flags.iter().filter(|(set, _)| *set).fold(0, |acc, (_, bit)| acc | bit));
+    if let Some(code) = marks.exit_code {
+        out.extend_from_slice(&code.to_le_bytes());
+    }
+    push_varint(out, time as usize);
+
+    let mut runs: Vec<(Cell, String)> = Vec::new();
+    for cell in cells {
+        match runs.last_mut() {
+            Some((style, text)) if style.fg == cell.fg && style.bg == cell.bg && style.attrs == cell.attrs => {
+                text.push(cell.ch);
+            }
+            _ => runs.push((cell, cell.ch.to_string())),
+        }
+    }
+    push_varint(out, runs.len());
+    for (style, text) in runs {
+        out.extend_from_slice(&PackedColor::pack(style.fg).bits().to_le_bytes());
+        out.extend_from_slice(&PackedColor::pack(style.bg).bits().to_le_bytes());
+        out.push(style.attrs.bits());
+        push_varint(out, text.len());
+        out.extend_from_slice(text.as_bytes());
+    }
+}
+
+fn decode_line(input: &mut &[u8]) -> Option<SpilledLine> {
+    let flags = take(input, 1)?[0];
+    let exit_code = if flags & MARK_EXIT_CODE != 0 {
+        Some(i32::from_le_bytes(take(input, 4)?.try_into().ok()?))
+    } else {
+        None
+    };
+    let marks = LineMarks {
+        prompt: flags & MARK_PROMPT != 0,
+        input: flags & MARK_INPUT != 0,
+        output: flags & MARK_OUTPUT != 0,
+        finished: flags & MARK_FINISHED != 0,
+        exit_code,
+    };
+    let time = take_varint(input)? as u64;
+
+    let mut cells = Vec::new();
+    for _ in 0..take_varint(input)? {
+        let fg = PackedColor::from_bits(u32::from_le_bytes(take(input, 4)?.try_into().ok()?));
+        let bg = PackedColor::from_bits(u32::from_le_bytes(take(input, 4)?.try_into().ok()?));
+        let attrs = Attrs::from_bits(take(input, 1)?[0]);
+        let len = take_varint(input)?;
+        let text = std::str::from_utf8(take(input, len)?).ok()?;
+        cells.extend(text.chars().map(|ch| Cell {
+            ch,
+            fg: fg.unpack(),
+            bg: bg.unpack(),
+            attrs,
+        }));
+    }
+    Some(SpilledLine { cells, marks, time })
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if input.len() < n {
+        return None;
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Some(head)
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn take_varint(input: &mut &[u8]) -> Option<usize> {
+    let mut value = 0usize;
+    for shift in (0..64).step_by(7) {
+        let byte = take(input, 1)?[0];
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::Color;
+
+    fn cell(ch: char, fg: Option<Color>) -> Cell {
+        Cell { ch, fg, ..Cell::default() }
+    }
+
+    #[test]
+    fn test_line_round_trip() {
+        let cells = vec![cell('a', Some(Color::Red)), cell('é', Some(Color::Red)), cell('─', None)];
+        let marks = LineMarks { finished: true, exit_code: Some(-2), ..LineMarks::default() };
+        let mut data = Vec::new();
+        encode_line(cells.clone().into_iter(), marks, 1_700_000_000, &mut data);
+        encode_line(std::iter::empty(), LineMarks::default(), 0, &mut data);
+
+        let mut input = data.as_slice();
+        let line = decode_line(&mut input).unwrap();
+        assert!(line.cells == cells);
+        assert_eq!(line.marks, marks);
+        assert_eq!(line.time, 1_700_000_000);
+        assert!(decode_line(&mut input).unwrap().cells.is_empty());
+        assert!(decode_line(&mut input).is_none());
+    }
+
+    #[test]
+    fn test_spill_file_blocks() {
+        let mut spill = SpillFile::create().unwrap();
+        let path = spill.path.clone();
+        for n in 0..BLOCK_LINES + 10 {
+            let text = n.to_string();
+            spill.push(text.chars().map(|ch| cell(ch, None)), LineMarks::default(), n as u64).unwrap();
+        }
+        assert_eq!(spill.len(), BLOCK_LINES + 10);
+        assert_eq!(spill.blocks.len(), 1);
+        let text = |n| spill.line(n, |line| line.cells.iter().map(|c| c.ch).collect::<String>());
+        // One line from the written block, one still pending, then back again
+        assert_eq!(text(3).as_deref(), Some("3"));
+        assert_eq!(text(BLOCK_LINES + 9), Some((BLOCK_LINES + 9).to_string()));
+        assert_eq!(text(200).as_deref(), Some("200"));
+        assert_eq!(text(BLOCK_LINES + 10), None);
+
+        drop(spill);
+        assert!(!path.exists());
+    }
+}
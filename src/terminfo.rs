@@ -0,0 +1,244 @@
+//! The truetm-256color terminfo entry, compiled into ~/.terminfo on first run
+
+use crate::config;
+use anyhow::{bail, Context, Result};
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Name of the entry in truetm.terminfo
+const ENTRY_NAME: &str = "truetm-256color";
+
+/// The entry's source, shipped inside the binary
+const ENTRY_SOURCE: &str = include_str!("../truetm.terminfo");
+
+/// TERM for new windows: config::TERM if its entry can be found (compiling ours
+/// if it is missing), otherwise config::TERM_FALLBACK
+pub fn window_term() -> String {
+    let dirs = search_dirs();
+    if find_entry(&dirs, config::TERM).is_some() {
+        return config::TERM.to_string();
+    }
+    if config::TERM == ENTRY_NAME {
+        match install() {
+            Ok(()) => return config::TERM.to_string(),
+            Err(e) => log::warn!("Using TERM={}: {:#}", config::TERM_FALLBACK, e),
+        }
+    }
+    config::TERM_FALLBACK.to_string()
+}
+
+/// What a terminfo entry says about colours
+pub struct ColorSupport {
+    pub colors: u32,
+    pub direct: bool, // Tc or RGB - takes RGB colours
+}
+
+/// Colour support of the compiled entry for a TERM, if it can be found
+pub fn color_support(term: &str) -> Option<ColorSupport> {
+    let path = find_entry(&search_dirs(), term)?;
+    parse_color_support(&std::fs::read(path).ok()?)
+}
+
+/// Read `colors` and the Tc/RGB extended capabilities from a compiled entry
+/// (see term(5): header, names, booleans, numbers, strings, then the extended
+/// section laid out the same way with the capability names at the end)
+fn parse_color_support(data: &[u8]) -> Option<ColorSupport> {
+    let short = |at: usize| data.get(at..at + 2).map(|b| i16::from_le_bytes([b[0], b[1]]));
+    let count = |at: usize| short(at).map(|n| n.max(0) as usize);
+    let number_size = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4, // 32-bit numbers
+        _ => return None,
+    };
+    let number = |at: usize| -> Option<i32> {
+        let bytes = data.get(at..at + number_size)?;
+        Some(match number_size {
+            2 => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+            _ => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        })
+    };
+    let even = |at: usize| at + at % 2;
+
+    // Index of colors among the standard numbers
+    const COLORS: usize = 13;
+    let (names, bools, nums, strings, table) = (count(2)?, count(4)?, count(6)?, count(8)?, count(10)?);
+    let numbers_at = even(12 + names + bools);
+    let colors = if nums > COLORS { number(numbers_at + COLORS * number_size)? } else { -1 };
+    let mut support = ColorSupport {
+        colors: colors.max(0) as u32,
+        direct: colors >= 1 << 24,
+    };
+
+    // Extended capabilities, if any
+    let ext_at = even(numbers_at + nums * number_size + strings * 2 + table);
+    let (Some(ext_bools), Some(ext_nums), Some(ext_strings)) = (count(ext_at), count(ext_at + 2), count(ext_at + 4)) else {
+        return Some(support);
+    };
+    let bools_at = ext_at + 10;
+    let nums_at = even(bools_at + ext_bools);
+    let offsets_at = nums_at + ext_nums * number_size;
+    let names_offsets_at = offsets_at + ext_strings * 2;
+    let table_at = names_offsets_at + (ext_bools + ext_nums + ext_strings) * 2;
+    // The names follow the string values in the table
+    let string_at = |offset: usize| -> Option<&[u8]> {
+        let rest = data.get(table_at + offset..)?;
+        Some(&rest[..rest.iter().position(|&b| b == 0)?])
+    };
+    let names_start = (0..ext_strings)
+        .filter_map(|i| usize::try_from(short(offsets_at + i * 2)?).ok())
+        .filter_map(|offset| Some(offset + string_at(offset)?.len() + 1))
+        .max()
+        .unwrap_or(0);
+    for i in 0..ext_bools + ext_nums + ext_strings {
+        let Some(name) = count(names_offsets_at + i * 2).and_then(|offset| string_at(names_start + offset)) else {
+            continue;
+        };
+        let set = if i < ext_bools {
+            data.get(bools_at + i) == Some(&1)
+        } else if i < ext_bools + ext_nums {
+            number(nums_at + (i - ext_bools) * number_size).is_some_and(|n| n >= 0)
+        } else {
+            short(offsets_at + (i - ext_bools - ext_nums) * 2).is_some_and(|n| n >= 0)
+        };
+        if set && (name == b"Tc" || name == b"RGB") {
+            support.direct = true;
+        }
+    }
+    Some(support)
+}
+
+/// Directories searched for terminfo entries, in ncurses order
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(Path::new(&home).join(".terminfo"));
+    }
+    if let Some(list) = std::env::var_os("TERMINFO_DIRS") {
+        dirs.extend(std::env::split_paths(&list).filter(|d| !d.as_os_str().is_empty()));
+    }
+    for dir in ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo", "/usr/lib/terminfo", "/usr/local/share/terminfo"] {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+/// Path of a compiled entry, in either the letter (t/) or hex (74/) layout
+fn find_entry(dirs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    let first = name.chars().next()?;
+    dirs.iter()
+        .flat_map(|dir| {
+            [
+                dir.join(first.to_string()).join(name),
+                dir.join(format!("{:x}", first as u32)).join(name),
+            ]
+        })
+        .find(|path| path.is_file())
+}
+
+/// Compile the shipped entry into ~/.terminfo with tic
+fn install() -> Result<()> {
+    let home = std::env::var_os("HOME").context("HOME is not set")?;
+    let target = Path::new(&home).join(".terminfo");
+    // The source goes in a fresh directory only we can use, never through an existing path
+    let dir = std::env::temp_dir().join(format!("truetm-terminfo-{}", std::process::id()));
+    DirBuilder::new().mode(0o700).create(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let result = compile(&dir.join(format!("{}.terminfo", ENTRY_NAME)), &target);
+    let _ = std::fs::remove_dir_all(&dir);
+    result?;
+    log::info!("Installed {} terminfo into {}", ENTRY_NAME, target.display());
+    Ok(())
+}
+
+/// Write the entry's source to a new file and compile it into `target`
+fn compile(source: &Path, target: &Path) -> Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(source)
+        .with_context(|| format!("Failed to create {}", source.display()))?;
+    file.write_all(ENTRY_SOURCE.as_bytes()).context("Failed to write terminfo source")?;
+
+    // Runs before raw mode, so tic's warnings go to the log rather than the terminal
+    let output = Command::new("tic").arg("-x").arg("-o").arg(target).arg(source).output()
+        .context("Failed to run tic")?;
+    let messages = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("tic failed to compile {} ({}): {}", ENTRY_NAME, output.status, messages.trim());
+    }
+    for line in messages.lines().filter(|l| !l.trim().is_empty()) {
+        log::warn!("tic: {}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_entry_layouts() {
+        let root = std::env::temp_dir().join(format!("truetm-terminfo-test-{}", std::process::id()));
+        let letter = root.join("letter");
+        let hex = root.join("hex");
+        std::fs::create_dir_all(letter.join("t")).unwrap();
+        std::fs::create_dir_all(hex.join("74")).unwrap();
+        std::fs::write(letter.join("t").join("truetm-256color"), b"").unwrap();
+        std::fs::write(hex.join("74").join("truetm-mono"), b"").unwrap();
+
+        let dirs = vec![letter.clone(), hex.clone()];
+        assert_eq!(find_entry(&dirs, "truetm-256color"), Some(letter.join("t").join("truetm-256color")));
+        assert_eq!(find_entry(&dirs, "truetm-mono"), Some(hex.join("74").join("truetm-mono")));
+        assert_eq!(find_entry(&dirs, "xterm"), None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_parse_color_support() {
+        // A minimal compiled entry: 256 colours and the extended Tc flag
+        let mut data = Vec::new();
+        let shorts = |data: &mut Vec<u8>, values: &[i16]| values.iter().for_each(|v| data.extend(v.to_le_bytes()));
+        shorts(&mut data, &[0o432, 2, 0, 14, 0, 0]);
+        data.extend(b"x\0");
+        let mut numbers = [-1; 14];
+        numbers[13] = 256;
+        shorts(&mut data, &numbers);
+        shorts(&mut data, &[1, 0, 0, 1, 3]);
+        data.extend([1, 0]);
+        shorts(&mut data, &[0]);
+        data.extend(b"Tc\0");
+        let support = parse_color_support(&data).unwrap();
+        assert_eq!(support.colors, 256);
+        assert!(support.direct);
+
+        // Without the extended part only the colour count is known
+        let support = parse_color_support(&data[..42]).unwrap();
+        assert_eq!((support.colors, support.direct), (256, false));
+        assert!(parse_color_support(b"not terminfo").is_none());
+    }
+
+    #[test]
+    #[ignore = "needs tic to compile the shipped entry"]
+    fn test_parse_shipped_entry() {
+        let dir = std::env::temp_dir().join(format!("truetm-terminfo-colors-{}", std::process::id()));
+        let source = dir.join("entry.terminfo");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&source, ENTRY_SOURCE).unwrap();
+        let compiled = Command::new("tic").arg("-x").arg("-o").arg(&dir).arg(&source).status().unwrap();
+        assert!(compiled.success());
+        let entry = find_entry(std::slice::from_ref(&dir), ENTRY_NAME).unwrap();
+        let support = parse_color_support(&std::fs::read(entry).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(support.colors, 256);
+        assert!(support.direct);
+    }
+
+    #[test]
+    fn test_entry_source_names_entry() {
+        assert!(ENTRY_SOURCE.lines().any(|l| l.starts_with(&format!("{}|", ENTRY_NAME))));
+    }
+}
@@ -0,0 +1,272 @@
+//! Themes - the colours of headers, the status bar, selections and search matches
+
+use crate::color::{ColorDepth, SetBg, SetFg};
+use crate::highlight::HighlightStyle;
+use crate::render::Attrs;
+use anyhow::{bail, Context, Result};
+use crossterm::style::{Attribute, SetAttribute};
+use crossterm::Command;
+use std::fmt;
+use std::path::Path;
+
+/// Built-in themes by name, each as a theme file
+pub const BUILTIN: &[(&str, &str)] = &[("dark", DARK), ("light", LIGHT), ("high-contrast", HIGH_CONTRAST)];
+
+const DARK: &str = "\
+focused_header  = bold,fg=#78be78
+inactive_header = fg=brightblack
+urgent_header   = bold,fg=#c85050
+tag_focused     = bold,fg=#78be78
+tag_viewed      = fg=#509650
+tag_occupied    = fg=#3c643c
+tag_urgent      = bold,fg=black,bg=#c85050
+activity        = fg=#c8aa50
+silence         = fg=#6e96c8
+zoom            = bold,fg=brightmagenta
+copy_mode       = bold,fg=brightyellow
+prompt          = bold,fg=brightyellow
+message         = fg=#c8c8c8
+muted           = fg=brightblack
+selection       = reverse
+search_match    = underline,fg=brightyellow
+separator       = default
+";
+
+const LIGHT: &str = "\
+focused_header  = bold,fg=#2e7d32
+inactive_header = fg=#9e9e9e
+urgent_header   = bold,fg=#c62828
+tag_focused     = bold,fg=#2e7d32
+tag_viewed      = fg=#43a047
+tag_occupied    = fg=#8fb892
+tag_urgent      = bold,fg=white,bg=#c62828
+activity        = fg=#b26a00
+silence         = fg=#1565c0
+zoom            = bold,fg=#8e24aa
+copy_mode       = bold,fg=#b26a00
+prompt          = bold,fg=#b26a00
+message         = fg=#424242
+muted           = fg=#9e9e9e
+selection       = bg=#bbdefb
+search_match    = underline,bg=#fff59d
+separator       = fg=#bdbdbd
+";
+
+// Only the 16 basic colours, so it holds up in any terminal palette
+const HIGH_CONTRAST: &str = "\
+focused_header  = bold,fg=brightwhite,bg=blue
+inactive_header = fg=white
+urgent_header   = bold,fg=brightwhite,bg=red
+tag_focused     = bold,fg=black,bg=brightwhite
+tag_viewed      = bold,fg=brightwhite
+tag_occupied    = fg=white
+tag_urgent      = bold,fg=brightwhite,bg=red
+activity        = bold,fg=brightyellow
+silence         = bold,fg=brightcyan
+zoom            = bold,fg=brightmagenta
+copy_mode       = bold,fg=black,bg=brightyellow
+prompt          = bold,fg=brightyellow
+message         = fg=brightwhite
+muted           = fg=white
+selection       = bold,reverse
+search_match    = bold,underline,fg=black,bg=brightcyan
+separator       = fg=brightwhite
+";
+
+/// Style of each part of truetm's own drawing
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    pub focused_header: HighlightStyle,
+    pub inactive_header: HighlightStyle,
+    pub urgent_header: HighlightStyle,
+    /// Viewed tag that the focused window has
+    pub tag_focused: HighlightStyle,
+    pub tag_viewed: HighlightStyle,
+    /// Tag with windows that isn't viewed
+    pub tag_occupied: HighlightStyle,
+    pub tag_urgent: HighlightStyle,
+    /// The # and ~ monitor markers in the status bar
+    pub activity: HighlightStyle,
+    pub silence: HighlightStyle,
+    pub zoom: HighlightStyle,
+    /// Copy mode headers and prompts
+    pub copy_mode: HighlightStyle,
+    /// Status bar prompts and the notification box
+    pub prompt: HighlightStyle,
+    pub message: HighlightStyle,
+    /// Layout name and timestamp gutter
+    pub muted: HighlightStyle,
+    pub selection: HighlightStyle,
+    pub search_match: HighlightStyle,
+    /// Line between the master and stack areas
+    pub separator: HighlightStyle,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        // The dark theme is the base of all others, so it alone starts from nothing
+        Self::parse_onto(Self::empty(), "dark", DARK).expect("built-in theme")
+    }
+}
+
+impl Theme {
+    /// A built-in theme by name, or a theme file
+    pub fn load(name: &str) -> Result<Self> {
+        if let Some((name, text)) = BUILTIN.iter().find(|(n, _)| *n == name) {
+            return Self::parse(name, text);
+        }
+        let path = Path::new(name);
+        if !path.is_file() {
+            let names: Vec<&str> = BUILTIN.iter().map(|(n, _)| *n).collect();
+            bail!("No theme '{}' (built-in: {})", name, names.join(", "));
+        }
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let name = path.file_stem().map_or(name.into(), |s| s.to_string_lossy().into_owned());
+        Self::parse(&name, &text).with_context(|| format!("In {}", path.display()))
+    }
+
+    /// Parse `role = style` lines, with styles as for highlights or "default".
+    /// Roles not given keep the dark theme's style; lines starting with # are comments
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        Self::parse_onto(Self::default(), name, text)
+    }
+
+    fn parse_onto(mut theme: Self, name: &str, text: &str) -> Result<Self> {
+        theme.name = name.to_string();
+        for (number, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((role, spec)) = line.split_once('=') else {
+                bail!("Line {}: expected 'role = style'", number);
+            };
+            let (role, spec) = (role.trim(), spec.trim());
+            let style = match spec {
+                "default" => HighlightStyle::default(),
+                _ => HighlightStyle::parse(spec).with_context(|| format!("Line {}", number))?,
+            };
+            *theme.role_mut(role).with_context(|| format!("Line {}: unknown role '{}'", number, role))? = style;
+        }
+        Ok(theme)
+    }
+
+    fn empty() -> Self {
+        let none = HighlightStyle::default();
+        Self {
+            name: String::new(),
+            focused_header: none,
+            inactive_header: none,
+            urgent_header: none,
+            tag_focused: none,
+            tag_viewed: none,
+            tag_occupied: none,
+            tag_urgent: none,
+            activity: none,
+            silence: none,
+            zoom: none,
+            copy_mode: none,
+            prompt: none,
+            message: none,
+            muted: none,
+            selection: none,
+            search_match: none,
+            separator: none,
+        }
+    }
+
+    fn role_mut(&mut self, role: &str) -> Option<&mut HighlightStyle> {
+        Some(match role {
+            "focused_header" => &mut self.focused_header,
+            "inactive_header" => &mut self.inactive_header,
+            "urgent_header" => &mut self.urgent_header,
+            "tag_focused" => &mut self.tag_focused,
+            "tag_viewed" => &mut self.tag_viewed,
+            "tag_occupied" => &mut self.tag_occupied,
+            "tag_urgent" => &mut self.tag_urgent,
+            "activity" => &mut self.activity,
+            "silence" => &mut self.silence,
+            "zoom" => &mut self.zoom,
+            "copy_mode" => &mut self.copy_mode,
+            "prompt" => &mut self.prompt,
+            "message" => &mut self.message,
+            "muted" => &mut self.muted,
+            "selection" => &mut self.selection,
+            "search_match" => &mut self.search_match,
+            "separator" => &mut self.separator,
+            _ => return None,
+        })
+    }
+}
+
+/// Set a style's attributes and colours (on top of the current ones)
+#[derive(Clone, Copy)]
+pub struct SetStyle(pub HighlightStyle, pub ColorDepth);
+
+impl Command for SetStyle {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        const ATTRIBUTES: [(u8, Attribute); 6] = [
+            (Attrs::BOLD, Attribute::Bold),
+            (Attrs::DIM, Attribute::Dim),
+            (Attrs::ITALIC, Attribute::Italic),
+            (Attrs::UNDERLINE, Attribute::Underlined),
+            (Attrs::REVERSE, Attribute::Reverse),
+            (Attrs::STRIKETHROUGH, Attribute::CrossedOut),
+        ];
+        let SetStyle(style, depth) = *self;
+        for (flag, attribute) in ATTRIBUTES {
+            if style.attrs.has(flag) {
+                SetAttribute(attribute).write_ansi(f)?;
+            }
+        }
+        if let Some(fg) = style.fg {
+            SetFg(fg, depth).write_ansi(f)?;
+        }
+        if let Some(bg) = style.bg {
+            SetBg(bg, depth).write_ansi(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::Color;
+
+    #[test]
+    fn test_builtin_themes() {
+        for (name, _) in BUILTIN {
+            assert_eq!(Theme::load(name).unwrap().name, *name);
+        }
+        let dark = Theme::default();
+        assert_eq!(dark.tag_viewed.fg, Some(Color::Rgb { r: 0x50, g: 0x96, b: 0x50 }));
+        assert!(dark.selection.attrs.has(Attrs::REVERSE));
+        assert!(dark.separator == HighlightStyle::default());
+    }
+
+    #[test]
+    fn test_parse_theme() {
+        let theme = Theme::parse("mine", "# comment\n\nseparator = fg=#123456\nzoom=default\n").unwrap();
+        assert_eq!(theme.separator.fg, Some(Color::Rgb { r: 0x12, g: 0x34, b: 0x56 }));
+        assert!(theme.zoom == HighlightStyle::default());
+        // Roles not given come from the dark theme
+        assert!(theme.selection == Theme::default().selection);
+        // Even for a theme file named like the built-in one
+        let theme = Theme::parse("dark", "zoom = bold").unwrap();
+        assert!(theme.selection == Theme::default().selection);
+
+        assert!(Theme::parse("bad", "borders = bold").is_err());
+        assert!(Theme::parse("bad", "zoom bold").is_err());
+        assert!(Theme::parse("bad", "zoom = fg=purple").is_err());
+        assert!(Theme::load("no-such-theme").is_err());
+    }
+
+    #[test]
+    fn test_set_style() {
+        let mut out = String::new();
+        let style = HighlightStyle::parse("bold,bg=red").unwrap();
+        SetStyle(style, ColorDepth::Ansi16).write_ansi(&mut out).unwrap();
+        assert_eq!(out, "\x1b[1m\x1b[41m");
+    }
+}
@@ -0,0 +1,180 @@
+//! Output triggers - regex rules matched against completed output lines
+
+use crate::config;
+use crate::pane::PaneId;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::cell::Cell;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Most trigger commands running at once; matches past it don't start another
+const MAX_RUNNING_COMMANDS: usize = 8;
+
+static RUNNING_COMMANDS: AtomicUsize = AtomicUsize::new(0);
+
+/// What happens when a trigger matches a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Variants are chosen in config.rs
+pub enum TriggerAction {
+    /// Mark the pane urgent (as if it rang the bell)
+    Urgent,
+    /// Show the matched line in the status bar
+    Message,
+    /// Run a shell command with TRUETM_MATCH, TRUETM_PATTERN and TRUETM_PANE set
+    Command(&'static str),
+}
+
+/// A trigger rule as written in config.rs
+pub struct TriggerRule {
+    pub pattern: &'static str,
+    pub actions: &'static [TriggerAction],
+}
+
+/// A compiled trigger, global or limited to one pane
+struct Trigger {
+    regex: Regex,
+    actions: &'static [TriggerAction],
+    pane: Option<PaneId>,
+    // When the trigger last started its command
+    last_command: Cell<Option<Instant>>,
+}
+
+/// A trigger that matched a line
+pub struct TriggerMatch<'a> {
+    pub pattern: &'a str,
+    pub actions: &'static [TriggerAction],
+    last_command: &'a Cell<Option<Instant>>,
+}
+
+impl TriggerMatch<'_> {
+    /// Whether the trigger may start its command now - at most once per
+    /// TRIGGER_COMMAND_INTERVAL_MS, so a busy log can't start one per line
+    pub fn command_due(&self) -> bool {
+        let interval = Duration::from_millis(config::TRIGGER_COMMAND_INTERVAL_MS);
+        if self.last_command.get().is_some_and(|last| last.elapsed() < interval) {
+            return false;
+        }
+        self.last_command.set(Some(Instant::now()));
+        true
+    }
+}
+
+/// All active triggers
+pub struct TriggerSet {
+    triggers: Vec<Trigger>,
+}
+
+impl TriggerSet {
+    /// Compile the global triggers from config.rs (invalid patterns are logged and skipped)
+    pub fn from_config() -> Self {
+        let mut set = Self { triggers: Vec::new() };
+        for rule in config::TRIGGERS {
+            if let Err(e) = set.add(None, rule.pattern, rule.actions) {
+                log::error!("Ignoring trigger: {:#}", e);
+            }
+        }
+        set
+    }
+
+    /// Add a trigger for one pane (or all panes if `pane` is None)
+    pub fn add(&mut self, pane: Option<PaneId>, pattern: &str, actions: &'static [TriggerAction]) -> Result<()> {
+        let regex = Regex::new(pattern).with_context(|| format!("Invalid trigger pattern '{}'", pattern))?;
+        self.triggers.push(Trigger {
+            regex,
+            actions,
+            pane,
+            last_command: Cell::new(None),
+        });
+        Ok(())
+    }
+
+    /// Remove the triggers of one pane
+    pub fn remove_pane(&mut self, pane: PaneId) {
+        self.triggers.retain(|t| t.pane != Some(pane));
+    }
+
+    /// Whether any trigger watches the given pane
+    pub fn applies_to(&self, pane: PaneId) -> bool {
+        self.triggers.iter().any(|t| t.pane.is_none() || t.pane == Some(pane))
+    }
+
+    /// Triggers of the given pane that match a line
+    pub fn matches<'a>(&'a self, pane: PaneId, line: &'a str) -> impl Iterator<Item = TriggerMatch<'a>> {
+        self.triggers
+            .iter()
+            .filter(move |t| (t.pane.is_none() || t.pane == Some(pane)) && t.regex.is_match(line))
+            .map(|t| TriggerMatch {
+                pattern: t.regex.as_str(),
+                actions: t.actions,
+                last_command: &t.last_command,
+            })
+    }
+}
+
+/// Run a trigger command in the background with the match in its environment
+pub fn run_command(command: &str, pane: PaneId, pattern: &str, line: &str) -> Result<()> {
+    if RUNNING_COMMANDS.load(Ordering::Relaxed) >= MAX_RUNNING_COMMANDS {
+        bail!("{} trigger commands still running, not starting '{}'", MAX_RUNNING_COMMANDS, command);
+    }
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("TRUETM_MATCH", line)
+        .env("TRUETM_PATTERN", pattern)
+        .env("TRUETM_PANE", pane.0.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run trigger command '{}'", command))?;
+    // Reap the child without blocking the UI
+    RUNNING_COMMANDS.fetch_add(1, Ordering::Relaxed);
+    std::thread::spawn(move || {
+        let _ = child.wait();
+        RUNNING_COMMANDS.fetch_sub(1, Ordering::Relaxed);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URGENT: &[TriggerAction] = &[TriggerAction::Urgent];
+
+    #[test]
+    fn test_global_and_pane_triggers() {
+        let mut set = TriggerSet { triggers: Vec::new() };
+        set.add(None, "error|FAILED", URGENT).unwrap();
+        set.add(Some(PaneId(1)), "^done$", URGENT).unwrap();
+
+        assert_eq!(set.matches(PaneId(0), "build FAILED").count(), 1);
+        assert_eq!(set.matches(PaneId(0), "done").count(), 0);
+        assert_eq!(set.matches(PaneId(1), "done").count(), 1);
+
+        set.remove_pane(PaneId(1));
+        assert_eq!(set.matches(PaneId(1), "done").count(), 0);
+        assert!(set.applies_to(PaneId(1)));
+    }
+
+    #[test]
+    fn test_command_debounce() {
+        let mut set = TriggerSet { triggers: Vec::new() };
+        set.add(None, "error", URGENT).unwrap();
+        set.add(None, "warn", URGENT).unwrap();
+
+        assert!(set.matches(PaneId(0), "error").all(|m| m.command_due()));
+        assert!(!set.matches(PaneId(0), "error").any(|m| m.command_due()));
+        // Each rule has its own interval
+        assert!(set.matches(PaneId(0), "warn").all(|m| m.command_due()));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let mut set = TriggerSet { triggers: Vec::new() };
+        assert!(set.add(Some(PaneId(0)), "(unclosed", URGENT).is_err());
+        assert!(!set.applies_to(PaneId(0)));
+    }
+}